@@ -0,0 +1,110 @@
+use eframe::egui;
+use image::AnimationDecoder;
+use image::codecs::gif::GifDecoder;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Enough for a typical GIF loop without holding hundreds of textures per message.
+const MAX_FRAMES: usize = 300;
+/// Frames per second MP4s are sampled at, GIFs keep their own timing.
+const MP4_FPS: u32 = 15;
+/// MP4s are scaled down to this width, about what the chat view shows anyway.
+const MP4_WIDTH: u32 = 320;
+/// Used for GIFs that don't say how long a frame lasts, browsers do the same.
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+/// How long ffmpeg gets before it's killed, a broken file can otherwise keep it busy forever.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(30);
+/// Larger animations aren't downloaded, their thumbnail is shown instead.
+pub const MAX_SIZE: i64 = 20 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Frame {
+    pub image: egui::ColorImage,
+    pub delay: Duration,
+}
+
+/// Decodes the frames of an animation, a GIF as is or an MP4 through the `ffmpeg` command.
+pub fn decode(bytes: &[u8], mime_type: &str, scratch: &Path) -> Result<Vec<Frame>, String> {
+    if mime_type == "image/gif" {
+        return decode_gif(bytes);
+    }
+    // ffmpeg needs to seek in MP4s, so the file goes through disk rather than a pipe
+    std::fs::write(scratch, bytes).map_err(|e| format!("Failed to write {}: {}", scratch.display(), e))?;
+    let gif = run_ffmpeg(scratch);
+    let _ = std::fs::remove_file(scratch);
+    decode_gif(&gif?)
+}
+
+/// Converts the MP4 at `path` to a GIF, killing ffmpeg if it runs past `FFMPEG_TIMEOUT`.
+fn run_ffmpeg(path: &Path) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(path)
+        .arg("-vf")
+        .arg(format!("fps={},scale='min({},iw)':-2", MP4_FPS, MP4_WIDTH))
+        .args(["-f", "gif", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run ffmpeg, is it installed? {}", e))?;
+    // Drained on threads of their own, a full pipe would stall ffmpeg before it could exit
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < FFMPEG_TIMEOUT => std::thread::sleep(Duration::from_millis(50)),
+            result => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(match result {
+                    Err(e) => format!("Failed to wait for ffmpeg: {}", e),
+                    _ => format!("ffmpeg took longer than {}s", FFMPEG_TIMEOUT.as_secs()),
+                });
+            }
+        }
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = stderr.join().unwrap_or_default();
+        return Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&stderr).trim()));
+    }
+    Ok(stdout)
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+fn decode_gif(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    let decoder = GifDecoder::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames().take(MAX_FRAMES) {
+        let frame = frame.map_err(|e| e.to_string())?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay = match Duration::from_millis(u64::from(numerator) / u64::from(denominator.max(1))) {
+            Duration::ZERO => DEFAULT_DELAY,
+            delay => delay,
+        };
+        let buffer = frame.into_buffer();
+        let size = [buffer.width() as usize, buffer.height() as usize];
+        frames.push(Frame { image: egui::ColorImage::from_rgba_unmultiplied(size, buffer.as_raw()), delay });
+    }
+    if frames.is_empty() {
+        return Err("No frames".to_string());
+    }
+    Ok(frames)
+}
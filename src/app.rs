@@ -1,24 +1,166 @@
+use crate::animation::Frame;
+use crate::config::{Account, Config, MtprotoProxy, ProxyConfig, WindowGeometry, DEFAULT_WINDOW_SIZE};
+use crate::emoji::EMOJI;
+use crate::fonts;
+use crate::logs::{LogBuffer, LogLine};
+use crate::outbox::{self, QueuedMessage};
+use crate::tray::{NativeWindow, Tray, TrayAction};
+use crate::voice::{self, Recorder};
+use chrono::{DateTime, Local, NaiveDate, Utc};
 use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 #[derive(Debug)]
 pub enum GuiState {
     Configuration,
     LoginPhone,
+    LoginQr,
     LoginCode,
     LoginPassword,
     LoggedIn,
 }
 
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Also the ID of our Saved Messages chat.
+    pub id: String,
+    pub name: String,
+    pub username: Option<String>,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LoginMode {
+    #[default]
+    User,
+    Bot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
 pub enum GuiAction {
-    Configure { api_id: i32, api_hash: String },
+    /// Connects with these settings, replacing the current connection if there is one.
+    Configure { api_id: i32, api_hash: String, account: usize, proxy: Option<ProxyConfig>, live_updates: bool },
+    /// Turns listening for updates on or off, reconnecting to apply it.
+    SetLiveUpdates(bool),
+    /// Pauses handling updates while the window is in the background, catching up once it's back.
+    SetActive(bool),
+    /// Reconnects through the given proxy, or directly with `None`, once it's known to reach Telegram.
+    /// Answered with `ProxyApplied`, the current connection stays if the proxy doesn't work.
+    SetProxy(Option<ProxyConfig>),
+    /// Checks the proxy server accepts connections, answered with `ProxyTested`.
+    TestProxy { host: String, port: u16 },
     Login(String),
+    LoginQr,
+    CancelQrLogin,
+    LoginBot(String),
     SendCode(String),
+    ResendCode,
+    CancelLogin,
     SendPassword(String),
+    FetchMe,
     RefreshChats,
-    SelectChat(String),
-    SendMessage { chat_id: String, text: String },
+    /// Continues the main chat list where the last page ended.
+    LoadMoreChats,
+    LoadFolders,
+    LoadArchivedChats,
+    ArchiveChat(String),
+    UnarchiveChat(String),
+    /// Loads the messages of a chat, marking it as read on the server with `mark_read`.
+    SelectChat { chat_id: String, mark_read: bool },
+    MarkRead(String),
+    ToggleMute(String),
+    TogglePinChat(String),
+    LeaveChat(String),
+    /// Deletes all messages of a chat, also removing it from the chat list with `delete_chat`.
+    ClearHistory { chat_id: String, delete_chat: bool, for_everyone: bool },
+    /// Adds our reaction to a message, replacing any earlier one, or takes it back.
+    ToggleReaction { chat_id: String, message_id: i32, emoji: String, add: bool },
+    SearchChats(String),
+    /// Looks up chats to forward to, answered with `ForwardTargetsFound` rather than adding them to the chat list.
+    SearchForwardTargets(String),
+    /// A `tg://` link clicked in a message, resolved to a chat by the backend.
+    OpenDeepLink(String),
+    /// Opens a chat with `@username` or `+phone`, even if there is no dialog with them yet.
+    OpenUsername(String),
+    /// Joins through a `t.me/+hash` or `t.me/joinchat/hash` invite link, or a public `t.me/name` link.
+    JoinChat(String),
+    LoadOlderMessages { chat_id: String, before_id: i32 },
+    SearchMessages { chat_id: String, query: String },
+    /// With `markdown` set, `**bold**`, `_italic_` and `` `code` `` are sent as formatting entities.
+    /// `temp_id` identifies the placeholder bubble shown until the send is confirmed.
+    SendMessage { chat_id: String, text: String, markdown: bool, temp_id: i32, random_id: i64 },
+    /// Sends a message at `at` instead of right away.
+    ScheduleMessage { chat_id: String, text: String, markdown: bool, at: DateTime<Utc> },
+    LoadScheduled(String),
+    CancelScheduled { chat_id: String, message_id: i32 },
+    LoadContacts,
+    /// Adds someone to the contacts by phone number, answered with `ContactAdded`.
+    AddContact { phone: String, first_name: String, last_name: String },
+    /// Forwards a message, or all of an album, to Saved Messages, answered with `MessageSaved`.
+    SaveMessage { chat_id: String, message_ids: Vec<i32> },
+    /// Votes for the options at `option_indices`, answered with `PollUpdated`.
+    VotePoll { chat_id: String, message_id: i32, option_indices: Vec<usize> },
+    /// Deletes messages for everyone, answered with `MessagesDeleted`.
+    DeleteMessages { chat_id: String, message_ids: Vec<i32> },
+    /// Forwards messages of `chat_id` to the chat `to`, answered with `MessagesForwarded`.
+    ForwardMessages { chat_id: String, message_ids: Vec<i32>, to: String },
+    /// With `silent` set, chat members aren't notified about the pin.
+    PinMessage { chat_id: String, message_id: i32, silent: bool },
+    UnpinMessage { chat_id: String, message_id: i32 },
+    GetPinned(String),
+    /// Looks up a single message, answered with `MessageFetched`.
+    FetchMessage { chat_id: String, id: i32 },
+    EditMessage { chat_id: String, message_id: i32, new_text: String },
+    /// With `temporary` set, the file is deleted once the upload is over.
+    SendPhoto { chat_id: String, path: PathBuf, temporary: bool },
+    /// Sends any file as a document, keeping its file name.
+    SendDocument { chat_id: String, path: PathBuf },
+    CancelUpload,
+    /// Sends a recording as a voice message, reporting back like `SendDocument`.
+    SendVoice { chat_id: String, samples: Vec<f32>, sample_rate: u32 },
+    DownloadMedia { chat_id: String, message_id: i32, path: PathBuf },
+    /// Writes the whole history of a chat to `path`, reporting with `ExportProgress` and `ExportFinished`.
+    ExportChat { chat_id: String, format: ExportFormat, path: PathBuf },
+    CancelExport,
+    /// Downloads a photo at full resolution for the viewer, answered with `FullPhotoDownloaded`.
+    FetchFullPhoto { chat_id: String, message_id: i32 },
+    SetTyping(String),
+    /// Stores the draft of a chat on the server, clearing it with an empty `text`.
+    SaveDraft { chat_id: String, text: String },
+    /// Fetches a page of members, `offset` being how many are already shown.
+    LoadMembers { chat_id: String, offset: usize },
+    FetchAvatar { peer_id: String },
+    /// Downloads the thumbnail of a message's link preview, answered with `MediaDownloaded`.
+    FetchPreviewImage { chat_id: String, message_id: i32 },
+    /// Downloads a static sticker, answered with `StickerDownloaded`.
+    FetchSticker { chat_id: String, message_id: i32, file_id: i64 },
+    /// Downloads and decodes a GIF, answered with `AnimationLoaded` or just its thumbnail if it can't be played.
+    FetchAnimation { chat_id: String, message_id: i32 },
+    SetMessageLimit(usize),
+    SwitchAccount(usize),
+    /// Saves what needs saving and stops the backend, answered with `ShutdownComplete`.
+    Shutdown,
     Logout,
+    /// Starts the current account over with an empty session after `SessionExpired` and connects again logged out.
+    ResetSession,
     BackToChats,
 }
 
@@ -26,6 +168,321 @@ pub enum GuiAction {
 pub struct ChatInfo {
     pub name: String,
     pub id: String,
+    /// Public username, without the `@`.
+    pub username: Option<String>,
+    pub unread_count: i32,
+    /// Incoming messages up to this ID have been read by us.
+    pub read_inbox_max_id: i32,
+    /// Our messages up to this ID have been read by the other side of a private chat.
+    pub read_outbox_max_id: i32,
+    pub last_message: String,
+    pub last_message_date: String,
+    pub muted: bool,
+    /// Whether `muted` comes from Telegram, chats found by search or link don't say.
+    pub mute_known: bool,
+    /// The unsent text saved on the server, from this or another device.
+    pub draft: Option<String>,
+    /// Set for channels and supergroups, which have shareable message links.
+    pub channel_id: Option<i64>,
+    /// Groups and channels, which have a member list.
+    pub is_group: bool,
+    /// Groups and channels we created.
+    pub is_owner: bool,
+    /// Pinned to the top of the chat list.
+    pub pinned: bool,
+}
+
+impl ChatInfo {
+    /// A private chat known only by the other user's ID and name.
+    fn private(id: String, name: String) -> Self {
+        Self {
+            name,
+            id,
+            username: None,
+            unread_count: 0,
+            read_inbox_max_id: 0,
+            read_outbox_max_id: 0,
+            last_message: String::new(),
+            last_message_date: String::new(),
+            muted: false,
+            mute_known: false,
+            draft: None,
+            channel_id: None,
+            is_group: false,
+            is_owner: false,
+            pinned: false,
+        }
+    }
+
+    /// How to mention the chat in a message, a markdown link for users without a username.
+    fn mention(&self) -> String {
+        match &self.username {
+            Some(username) => format!("@{}", username),
+            None => format!("[{}](tg://user?id={})", self.name, self.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContactInfo {
+    /// Also the ID of the private chat with the contact.
+    pub id: String,
+    pub name: String,
+    pub username: Option<String>,
+    /// Without the leading +, as Telegram stores it.
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemberInfo {
+    pub name: String,
+    pub username: Option<String>,
+    pub is_admin: bool,
+}
+
+/// The member list window, filled a page at a time.
+struct MemberList {
+    chat_id: String,
+    total: usize,
+    members: Vec<MemberInfo>,
+    loading: bool,
+    all_loaded: bool,
+}
+
+/// The proxy fields as typed, see `ProxyConfig` and `MtprotoProxy`.
+#[derive(Default)]
+struct ProxyInput {
+    enabled: bool,
+    /// Whether `link` is used rather than the SOCKS5 fields.
+    mtproto: bool,
+    host: String,
+    port: String,
+    username: String,
+    password: String,
+    link: String,
+}
+
+impl ProxyInput {
+    fn new(proxy: Option<&ProxyConfig>, mtproto: Option<&MtprotoProxy>) -> Self {
+        let link = mtproto.map(MtprotoProxy::link).unwrap_or_default();
+        let Some(proxy) = proxy else {
+            return Self { link, ..Self::default() };
+        };
+        Self {
+            enabled: true,
+            mtproto: false,
+            host: proxy.host.clone(),
+            port: proxy.port.to_string(),
+            username: proxy.username.clone().unwrap_or_default(),
+            password: proxy.password.clone().unwrap_or_default(),
+            link,
+        }
+    }
+
+    /// The server and port a connection test should reach.
+    fn address(&self) -> Result<(String, u16), String> {
+        if self.mtproto {
+            let proxy = MtprotoProxy::from_link(&self.link)?;
+            return Ok((proxy.server, proxy.port));
+        }
+        let host = self.host.trim();
+        if host.is_empty() {
+            return Err("Enter the proxy server".to_string());
+        }
+        let port = self
+            .port
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&port: &u16| port != 0)
+            .ok_or("Invalid proxy port (must be between 1 and 65535)")?;
+        Ok((host.to_string(), port))
+    }
+
+    fn parse(&self) -> Result<Option<ProxyConfig>, String> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        if self.mtproto {
+            let kind = MtprotoProxy::from_link(&self.link)?.secret_kind()?;
+            return Err(format!(
+                "grammers can't connect through MTProto proxies yet (this one uses a {} secret), use a SOCKS5 proxy instead",
+                kind
+            ));
+        }
+        let (host, port) = self.address()?;
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        Ok(Some(ProxyConfig {
+            host,
+            port,
+            username: optional(self.username.trim()),
+            password: optional(&self.password),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Severity {
+    Info,
+    Success,
+    Error,
+}
+
+/// A short-lived message stacked in the corner of the window.
+struct Toast {
+    text: String,
+    /// Shown on hover, like the raw error behind a friendlier message.
+    detail: Option<String>,
+    severity: Severity,
+    expires_at: Instant,
+}
+
+impl Toast {
+    fn new(severity: Severity, text: impl Into<String>) -> Self {
+        let duration = if severity == Severity::Error { ERROR_TOAST_DURATION } else { TOAST_DURATION };
+        Self { text: text.into(), detail: None, severity, expires_at: Instant::now() + duration }
+    }
+
+    /// An error toast with the hint for its kind, keeping the raw message for the tooltip.
+    fn from_error(error: BackendError) -> Self {
+        match error.kind.hint() {
+            Some(hint) => Self { detail: Some(error.message), ..Self::new(Severity::Error, hint) },
+            None => Self::new(Severity::Error, error.message),
+        }
+    }
+}
+
+/// What the last `Configure` was sent with, so "Retry" can send it again.
+#[derive(Debug, Clone)]
+struct ConnectSettings {
+    api_id: i32,
+    api_hash: String,
+    account: usize,
+    proxy: Option<ProxyConfig>,
+}
+
+/// A voice message being recorded.
+struct VoiceRecording {
+    recorder: Recorder,
+    /// Whether the mic button is still held down, letting go of it sends unless the pointer slid off first.
+    held: bool,
+}
+
+/// A GIF playing in a message, one texture per frame.
+struct Animation {
+    frames: Vec<(egui::TextureHandle, Duration)>,
+    current: usize,
+    next_at: Instant,
+    paused: bool,
+}
+
+impl Animation {
+    /// Moves on to the next frame once the current one has been shown long enough.
+    fn advance(&mut self) {
+        let now = Instant::now();
+        if now >= self.next_at {
+            self.current = (self.current + 1) % self.frames.len();
+            self.next_at = now + self.frames[self.current].1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One object per message, for other programs to read.
+    Json,
+    /// One line per message, for people to read.
+    Text,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Text => "txt",
+        }
+    }
+}
+
+/// The export dialog of a chat, first picking the format and then showing the progress.
+struct ChatExport {
+    chat: ChatInfo,
+    format: ExportFormat,
+    /// Messages written so far and the chat's total, set once the export is running.
+    progress: Option<(usize, Option<usize>)>,
+}
+
+/// The chat picker shown when forwarding the selection.
+#[derive(Default)]
+struct ForwardPicker {
+    query: String,
+    /// When the query last changed, the server is searched once it settles.
+    changed_at: Option<Instant>,
+    /// Chats the server found for the query, besides the ones already in the list.
+    found: Vec<ChatInfo>,
+}
+
+/// A history deletion waiting for confirmation.
+struct ClearRequest {
+    chat: ChatInfo,
+    /// Whether the chat is removed from the chat list as well.
+    delete_chat: bool,
+    for_everyone: bool,
+}
+
+/// The add contact form as typed.
+#[derive(Default)]
+struct ContactInput {
+    phone: String,
+    first_name: String,
+    last_name: String,
+}
+
+/// The photo open in the full-size viewer, with how it's zoomed and panned.
+struct PhotoViewer {
+    message_id: i32,
+    zoom: f32,
+    offset: egui::Vec2,
+}
+
+impl PhotoViewer {
+    fn new(message_id: i32) -> Self {
+        Self { message_id, zoom: 1.0, offset: egui::Vec2::ZERO }
+    }
+}
+
+/// A message waiting on the server to be sent at `at`.
+#[derive(Debug, Clone)]
+pub struct ScheduledMessage {
+    pub id: i32,
+    pub text: String,
+    pub at: DateTime<Utc>,
+}
+
+/// The scheduled messages window of the open chat.
+struct ScheduledList {
+    chat_id: String,
+    messages: Vec<ScheduledMessage>,
+    loading: bool,
+}
+
+/// A dialog filter, reduced to the chats it matched among those the backend knows about.
+#[derive(Debug, Clone)]
+pub struct FolderInfo {
+    pub id: i32,
+    pub title: String,
+    pub chat_ids: HashSet<String>,
+    pub exclude_muted: bool,
+    pub exclude_read: bool,
+}
+
+impl FolderInfo {
+    /// Muted and read state change while the app runs, so those rules are checked here instead of by the backend.
+    fn contains(&self, chat: &ChatInfo) -> bool {
+        self.chat_ids.contains(&chat.id)
+            && !(self.exclude_muted && chat.muted)
+            && !(self.exclude_read && chat.unread_count == 0)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -33,105 +490,2964 @@ pub struct MessageInfo {
     pub id: i32,
     pub text: String,
     pub sender: String,
-    pub date: String,
+    /// Empty when the sender is unknown, such as for messages still being sent.
+    pub sender_id: String,
+    pub date: DateTime<Utc>,
+    /// `date` formatted relative to the day it was loaded, see `format_timestamp`.
+    pub date_label: String,
+    /// When the text was last changed, if ever.
+    pub edit_date: Option<DateTime<Utc>>,
+    pub is_outgoing: bool,
+    pub media: Option<MediaInfo>,
+    pub entities: Vec<TextEntity>,
+    pub reactions: Vec<Reaction>,
+    /// ID of the message this one replies to, in the same chat.
+    pub reply_to: Option<i32>,
+    /// The preview of a link in the text, if Telegram generated one.
+    pub webpage: Option<WebpageInfo>,
+    /// Shared by the messages of an album, which are shown as one bubble.
+    pub grouped_id: Option<i64>,
+    /// Who a forwarded message originally came from, empty when they are unknown.
+    pub forward_from: Option<String>,
+    /// Set on the local copy of a message that is still being sent.
+    pub pending: bool,
+}
+
+/// When a user was last online, as precise as their privacy settings allow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Presence {
+    Online,
+    LastSeen(DateTime<Utc>),
+    Recently,
+    LastWeek,
+    LastMonth,
+    LongAgo,
+}
+
+impl Presence {
+    fn label(&self) -> String {
+        match self {
+            Presence::Online => "online".to_string(),
+            Presence::LastSeen(date) => format!("last seen {}", format_timestamp(*date)),
+            Presence::Recently => "last seen recently".to_string(),
+            Presence::LastWeek => "last seen within a week".to_string(),
+            Presence::LastMonth => "last seen within a month".to_string(),
+            Presence::LongAgo => "last seen a long time ago".to_string(),
+        }
+    }
+}
+
+/// Formatting applied to `range`, a byte range of the message text.
+#[derive(Debug, Clone)]
+pub struct TextEntity {
+    pub range: std::ops::Range<usize>,
+    pub kind: EntityKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityKind {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Code,
+    Pre,
+    /// A URL written out in the text itself.
+    Url,
+    /// Text linking to a URL that isn't shown.
+    TextUrl(String),
+    /// Hidden until clicked.
+    Spoiler,
+    /// A custom emoji by document ID, the text under it is the regular emoji it stands in for.
+    CustomEmoji(i64),
+}
+
+/// An emoji reaction on a message, with how many people added it.
+#[derive(Debug, Clone)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i32,
+    /// Whether we added this one ourselves.
+    pub chosen: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum MediaInfo {
+    Photo,
+    Document { name: String, size: i64 },
+    /// `file_id` is the sticker's document, shared by every message sending the same sticker.
+    Sticker { file_id: i64, emoji: String, animated: bool },
+    /// A GIF, which Telegram mostly stores as a silent MP4.
+    Animation,
+    Poll(PollInfo),
+}
+
+impl MediaInfo {
+    /// The name a download is suggested to be saved as, `None` for media that can't be downloaded.
+    fn file_name(&self, message_id: i32) -> Option<String> {
+        match self {
+            MediaInfo::Photo => Some(format!("photo_{}.jpg", message_id)),
+            MediaInfo::Document { name, .. } if !name.is_empty() => Some(name.clone()),
+            MediaInfo::Document { .. } => Some(format!("file_{}", message_id)),
+            MediaInfo::Animation => Some(format!("animation_{}.mp4", message_id)),
+            MediaInfo::Sticker { .. } | MediaInfo::Poll(_) => None,
+        }
+    }
+}
+
+/// A link preview, shown as a card under the message.
+#[derive(Debug, Clone)]
+pub struct WebpageInfo {
+    pub url: String,
+    pub site_name: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Whether there is a thumbnail to fetch with `FetchPreviewImage`.
+    pub has_photo: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PollInfo {
+    pub question: String,
+    pub options: Vec<PollOption>,
+    pub multiple_choice: bool,
+    /// Quizzes have one correct option, revealed once we've voted.
+    pub quiz: bool,
+    pub closed: bool,
+    pub total_voters: i32,
+    /// The quiz explanation shown after voting.
+    pub solution: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PollOption {
+    pub text: String,
+    /// Only known once we've voted or the poll is closed.
+    pub voters: i32,
+    pub chosen: bool,
+    pub correct: bool,
 }
 
 #[derive(Debug)]
 pub enum BackendEvent {
     Configured,
-    CodeSent,
-    PasswordRequired,
+    /// The connection made by `Configure` doesn't work, and won't be retried until asked to.
+    ConnectionFailed(String),
+    /// `next_method` names how a resent code would arrive, if Telegram offers another way.
+    CodeSent { next_method: Option<String> },
+    QrToken(String),
+    PasswordRequired(Option<String>),
     LoggedIn,
+    MeLoaded { id: String, name: String, username: Option<String>, phone: Option<String> },
     ChatsLoaded(Vec<ChatInfo>),
+    /// The main chat list is about to be sent again, one `ChatLoaded` per chat.
+    ChatsLoading,
+    ChatLoaded(ChatInfo),
+    /// `all_loaded` is false if the list was cut short.
+    ChatsLoadingFinished { all_loaded: bool },
+    FoldersLoaded(Vec<FolderInfo>),
+    ArchiveChanged { chat_id: String, archived: bool },
+    PinChanged { chat_id: String, pinned: bool },
+    /// A chat found by link, username or phone number, to be shown right away.
+    ChatOpened(ChatInfo),
+    ChatJoined { chat: ChatInfo, already_member: bool },
+    ChatLeft(String),
+    ChatRead(String),
+    HistoryCleared { chat_id: String, chat_deleted: bool },
+    /// The emoji allowed as reactions in a chat, empty if reactions are disabled there.
+    AllowedReactions { chat_id: String, emoji: Vec<String> },
+    ReactionsUpdated { chat_id: String, message_id: i32, reactions: Vec<Reaction> },
+    PollUpdated { chat_id: String, message_id: i32, poll: PollInfo },
+    MessageSaved,
+    ContactsLoaded(Vec<ContactInfo>),
+    ContactAdded(ContactInfo),
+    MessageScheduled { chat_id: String, at: DateTime<Utc> },
+    /// The other side of a private chat read our messages up to `max_id`.
+    MessagesRead { chat_id: String, max_id: i32 },
+    ScheduledLoaded { chat_id: String, messages: Vec<ScheduledMessage> },
+    /// The chat only admits members approved by an admin, carries its title.
+    JoinRequestSent(String),
     MessagesLoaded(Vec<MessageInfo>),
+    OlderMessagesLoaded(Vec<MessageInfo>),
+    NewMessage { chat_id: String, message: MessageInfo },
+    SearchResults(Vec<MessageInfo>),
+    MuteChanged { chat_id: String, muted: bool },
+    /// The most recently pinned message of the open chat, if any.
+    PinnedMessage(Option<MessageInfo>),
+    /// The quote line of message `id`, `None` if it was deleted.
+    MessageFetched { chat_id: String, id: i32, quote: Result<Option<String>, String> },
+    /// A pending message is on the server, as `real_id`.
+    MessageSent { temp_id: i32, real_id: i32 },
+    /// Telegram refused a pending message, after an `Error` saying why.
+    SendFailed { temp_id: i32 },
+    /// The connection dropped before a pending message was confirmed, so it goes back into the outbox.
+    MessageQueued { temp_id: i32 },
+    /// A pending message reached Telegram but its ID is unknown, e.g. an earlier attempt already delivered it.
+    MessageDelivered { temp_id: i32 },
+    MessagesDeleted(Vec<i32>),
+    /// Messages of `chat_id` arrived in the chat `to`.
+    MessagesForwarded { chat_id: String, message_ids: Vec<i32>, to: String },
+    /// Server results for the forward picker's `query`.
+    ForwardTargetsFound { query: String, chats: Vec<ChatInfo> },
+    /// `own` is set when this answers our own `EditMessage`, rather than an edit made elsewhere.
+    /// `edit_date` is `None` for hidden edits, which leave the edit mark as it was.
+    MessageEdited { chat_id: String, id: i32, text: String, entities: Vec<TextEntity>, edit_date: Option<DateTime<Utc>>, own: bool },
+    MediaDownloaded { message_id: i32, bytes: Vec<u8> },
+    StickerDownloaded { file_id: i64, bytes: Vec<u8> },
+    AnimationLoaded { message_id: i32, frames: Vec<Frame> },
+    AvatarLoaded { peer_id: String, bytes: Vec<u8> },
+    PeerTyping { chat_id: String, user_name: String },
+    MembersLoaded { chat_id: String, offset: usize, total: usize, members: Vec<MemberInfo>, all_loaded: bool },
+    /// `user_id` is the ID of the private chat with that user.
+    UserStatus { user_id: String, status: Presence },
+    DownloadProgress { message_id: i32, downloaded: u64, total: u64 },
+    DownloadFinished { message_id: i32, path: PathBuf },
+    DownloadFailed { message_id: i32, error: String },
+    FullPhotoDownloaded { message_id: i32, bytes: Vec<u8> },
+    FullPhotoFailed { message_id: i32, error: String },
+    UploadProgress { bytes: u64, total: u64 },
+    /// A document or voice message upload ended, `error` is set if it failed.
+    UploadFinished { voice: bool, error: Option<String> },
+    /// `total` is the number of messages in the chat, if Telegram said.
+    ExportProgress { count: usize, total: Option<usize> },
+    /// `result` holds how many messages were written.
+    ExportFinished { path: PathBuf, result: Result<usize, String> },
+    /// `result` holds how long connecting to the proxy took.
+    ProxyTested { host: String, port: u16, result: Result<Duration, String> },
+    /// `result` holds the proxy now in use, or why it was rejected.
+    ProxyApplied(Result<Option<ProxyConfig>, String>),
+    FloodWait(Duration),
     LoggedOut,
-    Error(String),
+    /// Telegram no longer accepts the session, e.g. it was terminated from another device.
+    SessionExpired,
+    ShutdownComplete,
+    AccountSwitched,
+    Disconnected,
+    Reconnecting,
+    Reconnected,
+    Error(BackendError),
+}
+
+/// The common reasons something fails, each with advice the user can act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The session was revoked or expired, so nothing works until logging in again.
+    AuthRequired,
+    FloodWait,
+    Network,
+    PhoneInvalid,
+    PhoneBanned,
+    CodeInvalid,
+    CodeExpired,
+    PasswordWrong,
+    BotTokenInvalid,
+    Other,
+}
+
+impl ErrorKind {
+    /// What to tell the user instead of the raw error, `None` if the raw error is all there is.
+    fn hint(self) -> Option<&'static str> {
+        Some(match self {
+            ErrorKind::AuthRequired => "Your session is no longer valid, log out and log in again.",
+            ErrorKind::FloodWait => "Telegram is limiting requests, wait a bit and try again.",
+            ErrorKind::Network => "Can't reach Telegram, check your internet connection or proxy.",
+            ErrorKind::PhoneInvalid => "That phone number isn't valid, check the country code and try again.",
+            ErrorKind::PhoneBanned => "This phone number is banned from Telegram, use another one or contact Telegram support.",
+            ErrorKind::CodeInvalid => "The code you entered is incorrect, try again.",
+            ErrorKind::CodeExpired => "The code has expired, request a new one.",
+            ErrorKind::PasswordWrong => "The password is incorrect, try again.",
+            ErrorKind::BotTokenInvalid => "The bot token is invalid, copy it again from @BotFather.",
+            ErrorKind::Other => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackendError {
+    pub kind: ErrorKind,
+    /// What was being done and the error as Telegram or grammers reported it.
+    pub message: String,
+}
+
+impl From<String> for BackendError {
+    fn from(message: String) -> Self {
+        Self { kind: ErrorKind::Other, message }
+    }
+}
+
+impl From<&str> for BackendError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
 }
 
+pub const APP_TITLE: &str = "Telegram Rust Client";
+/// Telegram shows "typing" for about 5 seconds per request, so refreshing a bit sooner keeps it steady.
+const TYPING_THROTTLE: Duration = Duration::from_secs(4);
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+const PREVIEW_CHARS: usize = 60;
+/// Stands in for each hidden character of a spoiler in previews.
+const SPOILER_MASK: char = '▒';
+/// Longer bare URLs are shortened on screen; the link itself still points to the full address.
+const MAX_LINK_CHARS: usize = 48;
+const URL_SCHEMES: &[&str] = &["https://", "http://", "tg://"];
+/// Files with these extensions are sent as photos, anything else as a document.
+const PHOTO_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+const AVATAR_SIZE: f32 = 32.0;
+const SENDER_AVATAR_SIZE: f32 = 20.0;
+/// Fallback avatar backgrounds, picked by name so a peer keeps its color.
+const AVATAR_COLORS: [egui::Color32; 7] = [
+    egui::Color32::from_rgb(229, 115, 115),
+    egui::Color32::from_rgb(255, 167, 38),
+    egui::Color32::from_rgb(171, 71, 188),
+    egui::Color32::from_rgb(102, 187, 106),
+    egui::Color32::from_rgb(38, 166, 154),
+    egui::Color32::from_rgb(66, 165, 245),
+    egui::Color32::from_rgb(236, 64, 122),
+];
+const EMOJI_PICKER_SIZE: egui::Vec2 = egui::vec2(280.0, 260.0);
+
+/// Dial codes offered by the country picker on the phone login screen.
+const COUNTRY_CODES: &[(&str, &str)] = &[
+    ("Argentina", "+54"),
+    ("Bolivia", "+591"),
+    ("Brazil", "+55"),
+    ("Chile", "+56"),
+    ("Colombia", "+57"),
+    ("Ecuador", "+593"),
+    ("France", "+33"),
+    ("Germany", "+49"),
+    ("India", "+91"),
+    ("Italy", "+39"),
+    ("Mexico", "+52"),
+    ("Paraguay", "+595"),
+    ("Peru", "+51"),
+    ("Russia", "+7"),
+    ("Spain", "+34"),
+    ("Ukraine", "+380"),
+    ("United Kingdom", "+44"),
+    ("United States / Canada", "+1"),
+    ("Uruguay", "+598"),
+    ("Venezuela", "+58"),
+];
+
+/// Telegram only allows asking for the code again after a while.
+const RESEND_DELAY: Duration = Duration::from_secs(60);
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// egui only repaints on input, so idle windows wake up this often to pick up backend events.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// With updates paused there is little to pick up, so a window in the background wakes up less.
+const INACTIVE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How close to the end of the message list still counts as "at the bottom" for auto-scrolling.
+const BOTTOM_THRESHOLD: f32 = 40.0;
+/// Longer than the backend's own shutdown timeout, so this only kicks in if the backend is stuck.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Share of the message area a single message may take up.
+const MESSAGE_WIDTH: f32 = 0.8;
+const SAVED_MESSAGES: &str = "Saved Messages";
+/// Link preview descriptions longer than this are cut, the full text is shown on hover.
+const MAX_DESCRIPTION_CHARS: usize = 200;
+const PREVIEW_THUMBNAIL_SIZE: f32 = 64.0;
+const ALBUM_THUMBNAIL_SIZE: f32 = 120.0;
+const STICKER_SIZE: f32 = 160.0;
+/// Zoom change per point scrolled in the photo viewer.
+const ZOOM_SPEED: f32 = 0.002;
+const MIN_ZOOM: f32 = 0.2;
+const MAX_ZOOM: f32 = 10.0;
+/// How long a message jumped to stays highlighted.
+const HIGHLIGHT_DURATION: Duration = Duration::from_secs(1);
+/// Pages of older history loaded looking for a replied-to message before giving up.
+const MAX_REPLY_SEARCH_PAGES: usize = 10;
+/// Chats listed in the quick switcher, the query narrows it down from there.
+const QUICK_SWITCHER_RESULTS: usize = 10;
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+/// Errors stay up longer, in case the user looked away while something failed.
+const ERROR_TOAST_DURATION: Duration = Duration::from_secs(15);
+const TOAST_FADE: Duration = Duration::from_millis(500);
+/// Older toasts are dropped past this, so a burst of events doesn't cover the window.
+const MAX_TOASTS: usize = 5;
+/// Presses on the mic button shorter than this toggle recording, longer ones record only while held.
+const HOLD_TO_RECORD: Duration = Duration::from_millis(400);
+/// Shorter recordings are most likely a misclick and get dropped.
+const MIN_VOICE_DURATION: Duration = Duration::from_millis(500);
+const MIN_UI_SCALE: f32 = 0.8;
+const MAX_UI_SCALE: f32 = 2.0;
+const UI_SCALE_STEP: f32 = 0.1;
+
 pub struct TelegramApp {
     state: GuiState,
     api_id_input: String,
     api_hash_input: String,
     phone: String,
+    country: Option<usize>,
+    bot_token: String,
     code: String,
+    code_sent_at: Option<Instant>,
+    next_code_method: Option<String>,
     password: String,
+    password_hint: Option<String>,
+    reveal_secrets: bool,
     chats: Vec<ChatInfo>,
+    /// Whether the contacts tab is shown instead of the chat list.
+    show_contacts: bool,
+    /// `None` until the contacts tab is first opened.
+    contacts: Option<Vec<ContactInfo>>,
+    contact_input: ContactInput,
+    /// How many chats of the list being streamed in have arrived, `None` once it's complete.
+    chats_loading: Option<usize>,
+    all_chats_loaded: bool,
+    /// Keyboard highlight, as a position among the chats currently listed.
+    selected_index: Option<usize>,
+    folders: Vec<FolderInfo>,
+    /// `None` is the "All" tab.
+    selected_folder: Option<i32>,
+    /// Whether the chat list shows the archive instead of the main list.
+    show_archived: bool,
     messages: Vec<MessageInfo>,
+    loading_older: bool,
+    reached_beginning: bool,
+    scroll_anchor: Option<i32>,
+    scroll_to_message: Option<i32>,
+    /// The message last jumped to, flashed until `HIGHLIGHT_DURATION` has passed.
+    highlighted_message: Option<(i32, Instant)>,
+    /// A replied-to message being looked for in older history, with the pages loaded so far.
+    reply_search: Option<(i32, usize)>,
+    /// Options ticked in polls not voted in yet, by message ID.
+    poll_choices: HashMap<i32, Vec<usize>>,
+    scroll_to_bottom: bool,
+    /// Incoming messages after this ID arrived unread, and sit under a "New messages" divider.
+    unread_after: Option<i32>,
+    at_bottom: bool,
+    confirm_delete: Option<i32>,
+    /// Messages picked for a bulk action, selection mode is on while there are any.
+    selected_messages: HashSet<i32>,
+    /// Messages on screen last frame, for "Select all visible".
+    visible_messages: Vec<i32>,
+    confirm_delete_selected: bool,
+    forward_picker: Option<ForwardPicker>,
+    confirm_logout: bool,
+    confirm_leave: Option<ChatInfo>,
+    confirm_clear: Option<ClearRequest>,
+    export: Option<ChatExport>,
+    /// Reactions the open chat accepts.
+    allowed_reactions: Vec<String>,
+    media_textures: HashMap<i32, egui::TextureHandle>,
+    avatars: HashMap<String, egui::TextureHandle>,
+    /// Peers whose avatar was asked for, whether or not they turned out to have one.
+    requested_avatars: HashSet<String>,
+    viewer: Option<PhotoViewer>,
+    /// Full resolution photos by message ID, `None` if the download failed.
+    full_photos: HashMap<i32, Option<egui::TextureHandle>>,
+    requested_full_photos: HashSet<i32>,
+    /// Messages whose link preview thumbnail was asked for.
+    requested_previews: HashSet<i32>,
+    /// Quoted text of replied-to messages that aren't loaded, `None` for deleted ones.
+    quotes: HashMap<i32, Option<String>>,
+    requested_quotes: HashSet<i32>,
+    /// Kept across chats, since the same stickers tend to come up again and again.
+    sticker_textures: HashMap<i64, egui::TextureHandle>,
+    requested_stickers: HashSet<i64>,
+    animations: HashMap<i32, Animation>,
+    requested_animations: HashSet<i32>,
+    /// Spoilers clicked open, by message ID and where the spoiler starts in its text.
+    revealed_spoilers: HashSet<(i32, usize)>,
+    downloads: HashMap<i32, (u64, u64)>,
+    /// Bytes sent and total size of the document being uploaded.
+    upload: Option<(u64, u64)>,
+    recording: Option<VoiceRecording>,
+    /// Chat and path of dropped files waiting for the upload before them.
+    file_queue: VecDeque<(String, PathBuf)>,
+    /// A pasted image saved to a temporary file, waiting for the user to confirm sending it.
+    pasted_image: Option<(PathBuf, egui::TextureHandle)>,
+    scheduled: Option<ScheduledList>,
+    proxy_input: ProxyInput,
+    /// Chat to open once the chat list first arrives, see `Config::last_chats`.
+    restore_chat: Option<String>,
+    /// Custom send time typed into the schedule menu, as `YYYY-MM-DD HH:MM` local time.
+    schedule_input: String,
+    qr_texture: Option<egui::TextureHandle>,
     selected_chat: Option<ChatInfo>,
+    pinned_message: Option<MessageInfo>,
+    profile: Option<Profile>,
     message_input: String,
+    /// Unsent text of the chats that aren't open, overriding what the server had.
+    drafts: HashMap<String, String>,
+    emoji_search: String,
+    editing_message_id: Option<i32>,
+    /// Pending messages get negative IDs, which never collide with server ones.
+    next_temp_id: i32,
+    /// Messages still being sent, by temporary ID.
+    pending_sends: HashMap<i32, QueuedMessage>,
+    /// Messages waiting for a connection, mirrored to disk by `save_outbox`.
+    outbox: Vec<QueuedMessage>,
+    /// Messages that couldn't be sent, kept so they can be retried as they were written.
+    failed_messages: Vec<QueuedMessage>,
+    last_typing_sent: Option<Instant>,
+    typing: HashMap<String, HashMap<String, Instant>>,
+    presence: HashMap<String, Presence>,
+    members: Option<MemberList>,
+    unread_first: bool,
+    chat_filter: String,
+    quick_switcher_open: bool,
+    quick_switcher_query: String,
+    quick_switcher_index: usize,
+    new_chat: String,
+    invite_link: String,
+    chat_filter_changed_at: Option<Instant>,
+    message_search: Option<String>,
+    searched_query: String,
+    search_results: Option<Vec<MessageInfo>>,
+    message_limit: String,
     tx: mpsc::Sender<GuiAction>,
     rx: mpsc::Receiver<BackendEvent>,
+    /// What is currently going on, results are shown as `toasts` instead.
     status_message: String,
+    toasts: VecDeque<Toast>,
+    connection_state: ConnectionState,
+    last_configure: Option<ConnectSettings>,
+    /// A `Configure` is on its way, so "Retry" isn't offered yet.
+    connecting: bool,
+    /// Requests that Telegram rate limits stay disabled until then.
+    flood_wait_until: Option<Instant>,
+    window_checked: bool,
+    /// The day message labels were last formatted on, so "Today" turns into "Yesterday" at midnight.
+    labels_day: NaiveDate,
+    logs: LogBuffer,
+    /// When closing the window started, it stays open until the backend has shut down.
+    shutting_down: Option<Instant>,
+    /// Only created once minimizing to the tray is turned on.
+    tray: Option<Tray>,
+    /// Why the tray icon couldn't be created, which keeps the option turned off.
+    tray_error: Option<String>,
+    window: NativeWindow,
+    /// The unread total in the window title, only set again when it changes.
+    title_unread: i32,
+    /// Unread counts of the main chat list by chat ID, kept while the archive is shown in its place.
+    main_unread: HashMap<String, i32>,
+    /// False while the window is minimized or in the tray and `pause_when_inactive` is on.
+    active: bool,
+    /// The window was closed to the tray and hasn't been brought back yet.
+    in_tray: bool,
+    /// Set by "Quit" in the tray menu, so that close isn't turned into hiding again.
+    quitting: bool,
+    /// Whether the session was already reset since the last login, see `SessionExpired`.
+    session_reset: bool,
+    show_logs: bool,
+    /// Least severe level shown in the log panel.
+    log_level: log::LevelFilter,
+    config: Config,
 }
 
 impl TelegramApp {
-    pub fn new(tx: mpsc::Sender<GuiAction>, rx: mpsc::Receiver<BackendEvent>) -> Self {
-        Self {
+    pub fn new(
+        ctx: &egui::Context,
+        window: NativeWindow,
+        config: Config,
+        tx: mpsc::Sender<GuiAction>,
+        rx: mpsc::Receiver<BackendEvent>,
+        logs: LogBuffer,
+    ) -> Self {
+        // Applied before the first frame so a dark theme doesn't flash light on startup
+        apply_theme(ctx, config.theme);
+        // Zoom shortcuts are handled by `handle_zoom_keys` instead, so the scale is saved
+        ctx.options_mut(|o| o.zoom_with_keyboard = false);
+        apply_ui_scale(ctx, config.ui_scale);
+        let font_error = fonts::install(ctx, config.font.as_deref()).err();
+        let mut status_message = "Please enter API ID and Hash".to_string();
+        let saved = config.credentials().map(|(api_id, api_hash)| ConnectSettings {
+            api_id,
+            api_hash,
+            account: config.active_account,
+            proxy: config.proxy.clone(),
+        });
+        if saved.is_some() {
+            status_message = "Connecting with saved configuration...".to_string();
+        }
+        let mut app = Self {
             state: GuiState::Configuration,
-            api_id_input: "".to_string(),
-            api_hash_input: "".to_string(),
+            api_id_input: config.api_id.map(|id| id.to_string()).unwrap_or_default(),
+            api_hash_input: config.api_hash.clone().unwrap_or_default(),
             phone: "".to_string(),
+            country: None,
+            bot_token: String::new(),
             code: String::new(),
+            code_sent_at: None,
+            next_code_method: None,
             password: String::new(),
+            password_hint: None,
+            reveal_secrets: false,
             chats: Vec::new(),
+            show_contacts: false,
+            contacts: None,
+            contact_input: ContactInput::default(),
+            chats_loading: None,
+            all_chats_loaded: false,
+            selected_index: None,
+            folders: Vec::new(),
+            selected_folder: None,
+            show_archived: false,
             messages: Vec::new(),
+            loading_older: false,
+            reached_beginning: false,
+            scroll_anchor: None,
+            scroll_to_message: None,
+            highlighted_message: None,
+            reply_search: None,
+            poll_choices: HashMap::new(),
+            scroll_to_bottom: false,
+            unread_after: None,
+            at_bottom: true,
+            confirm_delete: None,
+            selected_messages: HashSet::new(),
+            visible_messages: Vec::new(),
+            confirm_delete_selected: false,
+            forward_picker: None,
+            confirm_logout: false,
+            confirm_leave: None,
+            confirm_clear: None,
+            export: None,
+            allowed_reactions: Vec::new(),
+            media_textures: HashMap::new(),
+            avatars: HashMap::new(),
+            requested_avatars: HashSet::new(),
+            requested_previews: HashSet::new(),
+            quotes: HashMap::new(),
+            requested_quotes: HashSet::new(),
+            sticker_textures: HashMap::new(),
+            requested_stickers: HashSet::new(),
+            animations: HashMap::new(),
+            requested_animations: HashSet::new(),
+            revealed_spoilers: HashSet::new(),
+            viewer: None,
+            full_photos: HashMap::new(),
+            requested_full_photos: HashSet::new(),
+            downloads: HashMap::new(),
+            upload: None,
+            recording: None,
+            file_queue: VecDeque::new(),
+            pasted_image: None,
+            scheduled: None,
+            proxy_input: ProxyInput::new(config.proxy.as_ref(), config.mtproto_proxy.as_ref()),
+            schedule_input: String::new(),
+            restore_chat: None,
+            qr_texture: None,
             selected_chat: None,
+            pinned_message: None,
+            profile: None,
             message_input: String::new(),
+            drafts: HashMap::new(),
+            emoji_search: String::new(),
+            editing_message_id: None,
+            next_temp_id: -1,
+            pending_sends: HashMap::new(),
+            outbox: Vec::new(),
+            failed_messages: Vec::new(),
+            last_typing_sent: None,
+            typing: HashMap::new(),
+            presence: HashMap::new(),
+            members: None,
+            unread_first: true,
+            chat_filter: String::new(),
+            quick_switcher_open: false,
+            quick_switcher_query: String::new(),
+            quick_switcher_index: 0,
+            new_chat: String::new(),
+            invite_link: String::new(),
+            chat_filter_changed_at: None,
+            message_search: None,
+            searched_query: String::new(),
+            search_results: None,
+            message_limit: "50".to_string(),
             tx,
             rx,
-            status_message: "Please enter API ID and Hash".to_string(),
+            status_message,
+            toasts: VecDeque::new(),
+            connection_state: ConnectionState::Disconnected,
+            last_configure: None,
+            connecting: false,
+            flood_wait_until: None,
+            window_checked: false,
+            labels_day: Local::now().date_naive(),
+            logs,
+            shutting_down: None,
+            tray: None,
+            tray_error: None,
+            window,
+            title_unread: 0,
+            main_unread: HashMap::new(),
+            active: true,
+            in_tray: false,
+            quitting: false,
+            session_reset: false,
+            show_logs: false,
+            log_level: log::LevelFilter::Info,
+            config,
+        };
+        app.load_outbox();
+        app.restore_chat = app.saved_chat();
+        if app.config.minimize_to_tray {
+            app.enable_tray(ctx);
         }
+        if let Some(e) = font_error {
+            app.notify(Severity::Error, format!("Custom font not loaded: {}", e));
+        }
+        if let Some(settings) = saved {
+            app.configure(settings);
+        }
+        app
     }
 
-    fn handle_backend_events(&mut self) {
-        while let Ok(event) = self.rx.try_recv() {
-            match event {
-                BackendEvent::Configured => {
-                    self.state = GuiState::LoginPhone;
-                    self.status_message = "Configuration set. Enter phone number.".to_string();
+    /// The chat that was open when the active account was last used, if it should be reopened.
+    fn saved_chat(&self) -> Option<String> {
+        if !self.config.restore_last_chat {
+            return None;
+        }
+        self.config.last_chats.get(&self.config.active_account.to_string()).cloned()
+    }
+
+    /// Fields for the proxy, applied by the caller.
+    fn proxy_editor(&mut self, ui: &mut egui::Ui) {
+        let input = &mut self.proxy_input;
+        ui.checkbox(&mut input.enabled, "Connect through a proxy");
+        ui.add_enabled_ui(input.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut input.mtproto, false, "SOCKS5");
+                ui.radio_value(&mut input.mtproto, true, "MTProto");
+            });
+            if input.mtproto {
+                ui.add(
+                    egui::TextEdit::singleline(&mut input.link)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("tg://proxy?server=...&port=...&secret=..."),
+                );
+                return;
+            }
+            ui.horizontal(|ui| {
+                ui.label("Server:");
+                ui.add(egui::TextEdit::singleline(&mut input.host).desired_width(140.0).hint_text("127.0.0.1"));
+                ui.label("Port:");
+                ui.add(egui::TextEdit::singleline(&mut input.port).desired_width(50.0).hint_text("1080"));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Username:");
+                ui.add(egui::TextEdit::singleline(&mut input.username).desired_width(100.0).hint_text("optional"));
+                ui.label("Password:");
+                ui.add(egui::TextEdit::singleline(&mut input.password).desired_width(100.0).password(true));
+            });
+        });
+        if ui.add_enabled(self.proxy_input.enabled, egui::Button::new("Test connection")).clicked() {
+            match self.proxy_input.address() {
+                Ok((host, port)) => {
+                    self.status_message = format!("Testing {}:{}...", host, port);
+                    let _ = self.tx.try_send(GuiAction::TestProxy { host, port });
                 }
-                BackendEvent::CodeSent => {
-                    self.state = GuiState::LoginCode;
-                    self.status_message = "Code sent! Check Telegram.".to_string();
+                Err(e) => self.notify(Severity::Error, e),
+            }
+        }
+    }
+
+    fn login_mode_selector(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Login as:");
+            let before = self.config.login_mode;
+            ui.radio_value(&mut self.config.login_mode, LoginMode::User, "User");
+            ui.radio_value(&mut self.config.login_mode, LoginMode::Bot, "Bot");
+            if self.config.login_mode != before {
+                self.config.save();
+            }
+        });
+    }
+
+    /// Moves a restored window back into view when the monitor it was saved on is gone or smaller.
+    fn keep_window_on_screen(&self, ctx: &egui::Context) {
+        let info = ctx.input(|i| i.viewport().clone());
+        // Without a position (e.g. on Wayland) the compositor places the window itself
+        let (Some(outer), Some(inner)) = (info.outer_rect, info.inner_rect) else {
+            return;
+        };
+        let Some(monitor) = info.monitor_size else {
+            ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(50.0, 50.0)));
+            return;
+        };
+        let decorations = outer.size() - inner.size();
+        let size = inner.size().min(monitor - decorations);
+        if size != inner.size() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(size));
+        }
+        // Whatever is left of the monitor beside the window is where its corner can go
+        let room = (monitor - size - decorations).max(egui::Vec2::ZERO);
+        let position = outer.min.clamp(egui::Pos2::ZERO, room.to_pos2());
+        if position != outer.min {
+            ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(position));
+        }
+    }
+
+    fn save_window_geometry(&mut self, ctx: &egui::Context) {
+        let info = ctx.input(|i| i.viewport().clone());
+        // Minimized and maximized sizes aren't worth restoring, keep the last normal one
+        if info.minimized == Some(true) || info.maximized == Some(true) {
+            return;
+        }
+        let Some(inner) = info.inner_rect else {
+            return;
+        };
+        // The window is created before the zoom applies, so it's saved in unzoomed points
+        let zoom = ctx.zoom_factor();
+        self.config.window = Some(WindowGeometry {
+            position: info.outer_rect.map(|rect| [rect.min.x * zoom, rect.min.y * zoom]),
+            size: [inner.width() * zoom, inner.height() * zoom],
+        });
+        self.config.save();
+    }
+
+    /// Button with a searchable emoji grid, returning the emoji clicked this frame.
+    fn emoji_picker(&mut self, ui: &mut egui::Ui) -> Option<&'static str> {
+        let mut picked = None;
+        ui.menu_button("😀", |ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.emoji_search).hint_text("Search emoji"));
+            let query = self.emoji_search.trim().to_lowercase();
+            let mut categories: Vec<&str> = EMOJI.iter().map(|(category, _, _)| *category).collect();
+            categories.dedup();
+            egui::ScrollArea::vertical().max_height(EMOJI_PICKER_SIZE.y).show(ui, |ui| {
+                ui.set_width(EMOJI_PICKER_SIZE.x);
+                for category in categories {
+                    let matches: Vec<_> = EMOJI
+                        .iter()
+                        .filter(|(c, name, _)| *c == category && name.contains(&query))
+                        .collect();
+                    if matches.is_empty() {
+                        continue;
+                    }
+                    ui.weak(category);
+                    ui.horizontal_wrapped(|ui| {
+                        for (_, name, emoji) in matches {
+                            if ui.button(*emoji).on_hover_text(*name).clicked() {
+                                picked = Some(*emoji);
+                            }
+                        }
+                    });
                 }
-                BackendEvent::PasswordRequired => {
-                    self.state = GuiState::LoginPassword;
-                    self.status_message = "2FA Password Required.".to_string();
+            });
+        });
+        picked
+    }
+
+    /// Dropdown that puts the chosen country's dial code in front of the phone number.
+    fn country_picker(&mut self, ui: &mut egui::Ui) {
+        let mut selected = self.country;
+        egui::ComboBox::from_id_salt("country_code")
+            .selected_text(selected.map_or("Country", |i| COUNTRY_CODES[i].1))
+            .show_ui(ui, |ui| {
+                for (i, (name, code)) in COUNTRY_CODES.iter().enumerate() {
+                    ui.selectable_value(&mut selected, Some(i), format!("{} ({})", name, code));
                 }
-                BackendEvent::LoggedIn => {
-                    self.state = GuiState::LoggedIn;
-                    self.status_message = "Logged in successfully!".to_string();
-                    let _ = self.tx.try_send(GuiAction::RefreshChats);
+            });
+        if selected == self.country {
+            return;
+        }
+        let Some(index) = selected else {
+            return;
+        };
+        let code = COUNTRY_CODES[index].1;
+        // Swap out the code picked earlier, otherwise only prefix numbers entered without one
+        let previous = self.country.map(|i| COUNTRY_CODES[i].1);
+        let number = self.phone.trim();
+        if let Some(rest) = previous.and_then(|previous| number.strip_prefix(previous)) {
+            self.phone = format!("{}{}", code, rest);
+        } else if !number.starts_with('+') {
+            self.phone = format!("{} {}", code, number);
+        }
+        self.country = selected;
+    }
+
+    /// Eye button that shows or masks the login code and 2FA password.
+    fn reveal_toggle(&mut self, ui: &mut egui::Ui) {
+        let (icon, hover) = if self.reveal_secrets { ("🙈", "Hide") } else { ("👁", "Show") };
+        if ui.small_button(icon).on_hover_text(hover).clicked() {
+            self.reveal_secrets = !self.reveal_secrets;
+        }
+    }
+
+    fn cancel_login_button(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Back").clicked() {
+            self.state = GuiState::LoginPhone;
+            self.code.clear();
+            self.password.clear();
+            self.code_sent_at = None;
+            self.next_code_method = None;
+            self.notify(Severity::Info, "Login cancelled.");
+            let _ = self.tx.try_send(GuiAction::CancelLogin);
+        }
+    }
+
+    fn account_switcher(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Account:");
+            let active = self.config.active_account;
+            let current = self
+                .config
+                .accounts
+                .iter()
+                .find(|account| account.id == active)
+                .map_or("Unknown", |account| account.name.as_str());
+            let mut selected = active;
+            egui::ComboBox::from_id_salt("account_switcher")
+                .selected_text(current)
+                .show_ui(ui, |ui| {
+                    for account in &self.config.accounts {
+                        ui.selectable_value(&mut selected, account.id, &account.name);
+                    }
+                });
+            if selected != active {
+                self.switch_account(selected);
+            }
+            if ui.button("Add account").clicked() {
+                let id = self.config.accounts.iter().map(|account| account.id + 1).max().unwrap_or(0);
+                let name = format!("Account {}", self.config.accounts.len() + 1);
+                self.config.accounts.push(Account { id, name });
+                self.switch_account(id);
+            }
+        });
+    }
+
+    /// Shows `chat` in the message view, replacing whatever chat was open before.
+    fn open_chat(&mut self, chat: ChatInfo) {
+        self.stash_draft();
+        self.message_input = self.draft(&chat).unwrap_or_default().to_string();
+        self.messages.clear();
+        self.editing_message_id = None;
+        self.media_textures.clear();
+        self.requested_previews.clear();
+        self.quotes.clear();
+        self.requested_quotes.clear();
+        self.recording = None;
+        self.animations.clear();
+        self.requested_animations.clear();
+        self.revealed_spoilers.clear();
+        self.selected_messages.clear();
+        self.forward_picker = None;
+        self.confirm_delete_selected = false;
+        self.viewer = None;
+        self.full_photos.clear();
+        self.requested_full_photos.clear();
+        self.message_search = None;
+        self.search_results = None;
+        self.reached_beginning = false;
+        self.loading_older = false;
+        self.highlighted_message = None;
+        self.reply_search = None;
+        self.poll_choices.clear();
+        self.pinned_message = None;
+        self.allowed_reactions.clear();
+        self.unread_after = (chat.unread_count > 0).then_some(chat.read_inbox_max_id);
+        self.status_message = format!("Loading messages for {}...", chat.name);
+        let _ = self.tx.try_send(GuiAction::SelectChat { chat_id: chat.id.clone(), mark_read: self.config.auto_read });
+        let _ = self.tx.try_send(GuiAction::GetPinned(chat.id.clone()));
+        self.config.last_chats.insert(self.config.active_account.to_string(), chat.id.clone());
+        self.config.save();
+        self.selected_chat = Some(chat);
+    }
+
+    /// The unsent text of `chat`, if there is any.
+    fn draft<'a>(&'a self, chat: &'a ChatInfo) -> Option<&'a str> {
+        let draft = self.drafts.get(&chat.id).or(chat.draft.as_ref())?;
+        (!draft.is_empty()).then_some(draft.as_str())
+    }
+
+    /// Keeps what was typed in the open chat for when it's opened again, on the server too.
+    fn stash_draft(&mut self) {
+        let Some(chat) = &self.selected_chat else {
+            return;
+        };
+        // Text being edited belongs to a sent message, the chat's draft stays what it was
+        if self.editing_message_id.is_some() {
+            self.message_input.clear();
+            return;
+        }
+        let text = std::mem::take(&mut self.message_input).trim_end().to_string();
+        if self.draft(chat).unwrap_or_default() != text {
+            let _ = self.tx.try_send(GuiAction::SaveDraft { chat_id: chat.id.clone(), text: text.clone() });
+        }
+        self.drafts.insert(chat.id.clone(), text);
+        self.message_input.clear();
+    }
+
+    /// The first incoming message that arrived unread, which the "New messages" divider goes above.
+    fn first_unread(&self) -> Option<i32> {
+        let after = self.unread_after?;
+        self.messages.iter().find(|m| m.id > after && !m.is_outgoing).map(|m| m.id)
+    }
+
+    /// Leaves the message view for the chat list.
+    fn close_chat(&mut self) {
+        self.stash_draft();
+        self.selected_chat = None;
+        if self.config.last_chats.remove(&self.config.active_account.to_string()).is_some() {
+            self.config.save();
+        }
+        self.members = None;
+        self.scheduled = None;
+        self.pinned_message = None;
+        self.messages.clear();
+        self.editing_message_id = None;
+        self.media_textures.clear();
+        self.requested_previews.clear();
+        self.quotes.clear();
+        self.requested_quotes.clear();
+        self.recording = None;
+        self.animations.clear();
+        self.requested_animations.clear();
+        self.revealed_spoilers.clear();
+        self.selected_messages.clear();
+        self.forward_picker = None;
+        self.confirm_delete_selected = false;
+        self.viewer = None;
+        self.full_photos.clear();
+        self.requested_full_photos.clear();
+        self.message_search = None;
+        self.search_results = None;
+        let _ = self.tx.try_send(GuiAction::BackToChats);
+    }
+
+    fn refresh_chats(&self) {
+        let action = if self.show_archived { GuiAction::LoadArchivedChats } else { GuiAction::RefreshChats };
+        let _ = self.tx.try_send(action);
+    }
+
+    /// Fetches the chat list and the open chat again, the only way to see anything new without live updates.
+    fn refresh_all(&self) {
+        self.refresh_chats();
+        if let Some(chat) = &self.selected_chat {
+            let _ = self.tx.try_send(GuiAction::SelectChat { chat_id: chat.id.clone(), mark_read: false });
+        }
+    }
+
+    fn switch_account(&mut self, id: usize) {
+        // Sent ahead of the switch, so it's still saved on the old account
+        self.stash_draft();
+        self.config.active_account = id;
+        self.config.save();
+        self.clear_account_data();
+        self.restore_chat = self.saved_chat();
+        self.status_message = "Switching account...".to_string();
+        let _ = self.tx.try_send(GuiAction::SwitchAccount(id));
+    }
+
+    /// Drops everything loaded for the signed-in account.
+    fn clear_account_data(&mut self) {
+        self.chats.clear();
+        self.drafts.clear();
+        self.contacts = None;
+        self.show_contacts = false;
+        self.chats_loading = None;
+        self.all_chats_loaded = false;
+        self.main_unread.clear();
+        self.folders.clear();
+        self.selected_folder = None;
+        self.show_archived = false;
+        self.messages.clear();
+        self.media_textures.clear();
+        self.requested_previews.clear();
+        self.quotes.clear();
+        self.requested_quotes.clear();
+        self.recording = None;
+        self.animations.clear();
+        self.requested_animations.clear();
+        self.revealed_spoilers.clear();
+        self.selected_messages.clear();
+        self.forward_picker = None;
+        self.confirm_delete_selected = false;
+        self.viewer = None;
+        self.full_photos.clear();
+        self.requested_full_photos.clear();
+        self.avatars.clear();
+        self.requested_avatars.clear();
+        self.downloads.clear();
+        self.upload = None;
+        self.file_queue.clear();
+        self.typing.clear();
+        self.presence.clear();
+        self.members = None;
+        self.scheduled = None;
+        self.selected_chat = None;
+        self.pinned_message = None;
+        self.profile = None;
+        self.editing_message_id = None;
+        self.message_search = None;
+        self.search_results = None;
+        self.pending_sends.clear();
+        self.failed_messages.clear();
+        self.load_outbox();
+    }
+
+    /// Loads the outbox of the active account, giving each message a placeholder ID.
+    fn load_outbox(&mut self) {
+        self.outbox = outbox::load(self.config.active_account);
+        for message in &mut self.outbox {
+            message.temp_id = self.next_temp_id;
+            self.next_temp_id -= 1;
+        }
+    }
+
+    fn save_outbox(&self) {
+        outbox::save(self.config.active_account, &self.outbox);
+    }
+
+    /// The log viewer docked at the bottom of the window.
+    fn log_panel(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("logs").resizable(true).default_height(180.0).show(ctx, |ui| {
+            // Copied out so the lock isn't held while egui, which logs too, draws the panel
+            let lines: Vec<LogLine> = {
+                let logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+                logs.iter().filter(|line| line.level <= self.log_level).cloned().collect()
+            };
+            ui.horizontal(|ui| {
+                ui.strong("Logs");
+                egui::ComboBox::from_id_salt("log_level")
+                    .selected_text(self.log_level.as_str())
+                    .show_ui(ui, |ui| {
+                        for level in [log::LevelFilter::Debug, log::LevelFilter::Info, log::LevelFilter::Warn, log::LevelFilter::Error] {
+                            ui.selectable_value(&mut self.log_level, level, level.as_str());
+                        }
+                    });
+                if ui.button("Copy logs").clicked() {
+                    let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+                    ui.ctx().copy_text(text.join("\n"));
+                    self.notify(Severity::Success, format!("Copied {} log lines.", lines.len()));
                 }
-                BackendEvent::ChatsLoaded(chats) => {
-                    self.chats = chats;
-                    self.status_message = "Chats loaded.".to_string();
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("✖").clicked() {
+                        self.show_logs = false;
+                    }
+                });
+            });
+            ui.separator();
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::both().auto_shrink(false).stick_to_bottom(true).show_rows(
+                ui,
+                row_height,
+                lines.len(),
+                |ui, rows| {
+                    for line in &lines[rows] {
+                        let color = match line.level {
+                            log::Level::Error => ui.visuals().error_fg_color,
+                            log::Level::Warn => ui.visuals().warn_fg_color,
+                            log::Level::Info => ui.visuals().text_color(),
+                            log::Level::Debug | log::Level::Trace => ui.visuals().weak_text_color(),
+                        };
+                        ui.label(egui::RichText::new(line.to_string()).monospace().color(color));
+                    }
+                },
+            );
+        });
+    }
+
+    /// Queues files dropped onto the window for the open chat, and shows a drop zone while they hover.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let (hovering, dropped) = ctx.input(|i| (!i.raw.hovered_files.is_empty(), i.raw.dropped_files.clone()));
+        if hovering {
+            let text = match &self.selected_chat {
+                Some(chat) => format!("Drop to send to {}", chat.name),
+                None => "Open a chat to send files".to_string(),
+            };
+            let rect = ctx.content_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("drop_zone")));
+            painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(rect.center(), egui::Align2::CENTER_CENTER, text, egui::FontId::proportional(20.0), egui::Color32::WHITE);
+        }
+        let paths: Vec<PathBuf> = dropped.into_iter().filter_map(|file| file.path).collect();
+        if paths.is_empty() {
+            return;
+        }
+        let Some(chat) = &self.selected_chat else {
+            self.notify(Severity::Info, "Open a chat to send files");
+            return;
+        };
+        self.status_message = format!("Sending {} file(s)...", paths.len());
+        let chat_id = chat.id.clone();
+        self.file_queue.extend(paths.into_iter().map(|path| (chat_id.clone(), path)));
+        self.send_next_file();
+    }
+
+    /// Covers the window with the photo of `viewer`, zoomed with the scroll wheel and panned by dragging.
+    fn photo_viewer(&mut self, ctx: &egui::Context) {
+        let (Some(viewer), Some(chat)) = (&mut self.viewer, &self.selected_chat) else {
+            self.viewer = None;
+            return;
+        };
+        let id = viewer.message_id;
+        if !self.full_photos.contains_key(&id)
+            && !self.requested_full_photos.contains(&id)
+            && self.tx.try_send(GuiAction::FetchFullPhoto { chat_id: chat.id.clone(), message_id: id }).is_ok()
+        {
+            self.requested_full_photos.insert(id);
+        }
+        let photos: Vec<i32> = self
+            .messages
+            .iter()
+            .filter(|m| matches!(m.media, Some(MediaInfo::Photo)))
+            .map(|m| m.id)
+            .collect();
+        let position = photos.iter().position(|&photo| photo == id);
+        // The thumbnail stands in until the full photo arrives
+        let loading = !self.full_photos.contains_key(&id);
+        let texture = self.full_photos.get(&id).cloned().flatten().or_else(|| self.media_textures.get(&id).cloned());
+
+        let (mut close, mut step) = ctx.input_mut(|i| {
+            let step = if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft) {
+                -1
+            } else if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight) {
+                1
+            } else {
+                0
+            };
+            (i.consume_key(egui::Modifiers::NONE, egui::Key::Escape), step)
+        });
+        let rect = ctx.content_rect();
+        egui::Area::new(egui::Id::new("photo_viewer"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(rect.min)
+            .show(ctx, |ui| {
+                let (response, painter) = ui.allocate_painter(rect.size(), egui::Sense::click_and_drag());
+                painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(230));
+                if let Some(texture) = &texture {
+                    let fit = (rect.size() * 0.9 / texture.size_vec2()).min_elem();
+                    let image_rect = egui::Rect::from_center_size(rect.center() + viewer.offset, texture.size_vec2() * fit * viewer.zoom);
+                    let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                    painter.image(texture.id(), image_rect, uv, egui::Color32::WHITE);
                 }
-                BackendEvent::MessagesLoaded(msgs) => {
-                    self.messages = msgs;
-                    self.status_message = "Messages loaded.".to_string();
+                if response.hovered() {
+                    let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+                    viewer.zoom = (viewer.zoom * (1.0 + scroll * ZOOM_SPEED)).clamp(MIN_ZOOM, MAX_ZOOM);
                 }
-                BackendEvent::LoggedOut => {
-                    self.state = GuiState::LoginPhone;
-                    self.chats.clear();
-                    self.messages.clear();
-                    self.selected_chat = None;
-                    self.status_message = "Logged out.".to_string();
+                viewer.offset += response.drag_delta();
+                if response.double_clicked() {
+                    viewer.zoom = 1.0;
+                    viewer.offset = egui::Vec2::ZERO;
+                }
+                if loading {
+                    ui.put(egui::Rect::from_center_size(rect.center(), egui::Vec2::splat(32.0)), egui::Spinner::new().size(32.0));
+                } else if self.full_photos.get(&id).is_some_and(Option::is_none) {
+                    let label = egui::RichText::new("Couldn't load the full photo").color(egui::Color32::WHITE);
+                    ui.put(egui::Rect::from_center_size(rect.center_bottom() - egui::vec2(0.0, 60.0), egui::vec2(300.0, 20.0)), egui::Label::new(label));
+                }
+                let button = |text: &str| egui::Button::new(egui::RichText::new(text).size(20.0));
+                if ui.put(egui::Rect::from_min_size(rect.right_top() + egui::vec2(-48.0, 8.0), egui::vec2(40.0, 40.0)), button("✕")).clicked() {
+                    close = true;
                 }
-                BackendEvent::Error(msg) => {
-                    self.status_message = format!("Error: {}", msg);
+                let side = egui::vec2(40.0, 60.0);
+                if position.is_some_and(|p| p > 0)
+                    && ui.put(egui::Rect::from_min_size(rect.left_center() + egui::vec2(8.0, -30.0), side), button("‹")).clicked()
+                {
+                    step = -1;
                 }
+                if position.is_some_and(|p| p + 1 < photos.len())
+                    && ui.put(egui::Rect::from_min_size(rect.right_center() + egui::vec2(-48.0, -30.0), side), button("›")).clicked()
+                {
+                    step = 1;
+                }
+                if let Some(position) = position {
+                    let counter = egui::RichText::new(format!("{} / {}", position + 1, photos.len())).color(egui::Color32::WHITE);
+                    ui.put(egui::Rect::from_center_size(rect.center_bottom() - egui::vec2(0.0, 24.0), egui::vec2(100.0, 20.0)), egui::Label::new(counter));
+                }
+            });
+        if close {
+            self.viewer = None;
+        } else if let Some(next) = position.and_then(|p| p.checked_add_signed(step)).and_then(|p| photos.get(p))
+            && *next != id
+        {
+            self.viewer = Some(PhotoViewer::new(*next));
+        }
+    }
+
+    /// Connects with `settings`, remembering them for "Retry".
+    fn configure(&mut self, settings: ConnectSettings) {
+        let _ = self.tx.try_send(GuiAction::Configure {
+            api_id: settings.api_id,
+            api_hash: settings.api_hash.clone(),
+            account: settings.account,
+            proxy: settings.proxy.clone(),
+            live_updates: self.config.live_updates,
+        });
+        self.last_configure = Some(settings);
+        self.connecting = true;
+    }
+
+    /// Reports how an operation ended, which also ends its status line.
+    fn notify(&mut self, severity: Severity, text: impl Into<String>) {
+        self.push_toast(Toast::new(severity, text));
+    }
+
+    fn push_toast(&mut self, toast: Toast) {
+        self.status_message.clear();
+        self.toasts.push_back(toast);
+    }
+
+    /// Stacks the toasts in the bottom right corner, fading each out as it expires.
+    fn show_toasts(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        self.toasts.retain(|toast| toast.expires_at > now);
+        while self.toasts.len() > MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        if self.toasts.is_empty() {
+            return;
+        }
+        let mut dismissed = None;
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+            .order(egui::Order::Foreground)
+            .interactable(true)
+            .show(ctx, |ui| {
+                for (index, toast) in self.toasts.iter().enumerate() {
+                    let remaining = toast.expires_at.saturating_duration_since(now);
+                    ui.set_opacity((remaining.as_secs_f32() / TOAST_FADE.as_secs_f32()).min(1.0));
+                    let (icon, color) = match toast.severity {
+                        Severity::Info => ("ℹ", ui.visuals().text_color()),
+                        Severity::Success => ("✔", egui::Color32::from_rgb(80, 180, 80)),
+                        Severity::Error => ("⚠", ui.visuals().error_fg_color),
+                    };
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(300.0);
+                        ui.horizontal(|ui| {
+                            ui.colored_label(color, icon);
+                            let label = ui.label(&toast.text);
+                            if let Some(detail) = &toast.detail {
+                                label.on_hover_text(detail);
+                            }
+                            if toast.severity == Severity::Error && ui.small_button("✕").on_hover_text("Dismiss").clicked() {
+                                dismissed = Some(index);
+                            }
+                        });
+                    });
+                }
+            });
+        if let Some(index) = dismissed {
+            self.toasts.remove(index);
+        }
+        // Wake up for the next fade step or expiry
+        let next = self.toasts.iter().map(|t| t.expires_at.saturating_duration_since(now)).min().unwrap_or_default();
+        ctx.request_repaint_after(next.saturating_sub(TOAST_FADE).max(Duration::from_millis(30)));
+    }
+
+    /// Tells the backend when the window goes to the background and comes back.
+    fn track_activity(&mut self, ctx: &egui::Context) {
+        // Only a window out of sight pauses, one that's merely unfocused may still be watched
+        let active = !(self.config.live_updates && self.config.pause_when_inactive)
+            || !(self.in_tray || ctx.input(|i| i.viewport().minimized == Some(true)));
+        if active != self.active {
+            self.active = active;
+            let _ = self.tx.try_send(GuiAction::SetActive(active));
+        }
+    }
+
+    /// Puts the number of unread messages across all chats in the window title, e.g. "Telegram Rust Client (5)".
+    fn update_title(&mut self, ctx: &egui::Context) {
+        // Muted chats don't ask for attention anywhere else either
+        let unread: i32 = self
+            .main_unread
+            .iter()
+            .filter(|(chat_id, _)| !self.config.muted_chats.contains(*chat_id))
+            .map(|(_, &unread)| unread.max(0))
+            .sum();
+        if unread == self.title_unread {
+            return;
+        }
+        self.title_unread = unread;
+        let title = if unread > 0 { format!("{} ({})", APP_TITLE, unread) } else { APP_TITLE.to_string() };
+        ctx.send_viewport_cmd(egui::ViewportCommand::Title(title));
+    }
+
+    /// Shows the tray icon, creating it the first time.
+    fn enable_tray(&mut self, ctx: &egui::Context) {
+        if let Some(tray) = &self.tray {
+            tray.set_visible(true);
+            return;
+        }
+        match Tray::new(ctx, self.window) {
+            Ok(tray) => self.tray = Some(tray),
+            Err(e) => {
+                self.config.minimize_to_tray = false;
+                self.config.save();
+                self.notify(Severity::Error, format!("System tray not available: {}", e));
+                self.tray_error = Some(e);
+            }
+        }
+    }
+
+    fn handle_tray(&mut self, ctx: &egui::Context) {
+        let Some(tray) = &self.tray else {
+            return;
+        };
+        while let Some(action) = tray.poll() {
+            self.in_tray = false;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            if action == TrayAction::Quit {
+                // The window comes back first so the shutdown has somewhere to show
+                self.quitting = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    /// Zooms the interface in and out with Ctrl+Plus and Ctrl+Minus, Ctrl+0 going back to 100%.
+    fn handle_zoom_keys(&mut self, ctx: &egui::Context) {
+        use egui::gui_zoom::kb_shortcuts;
+        let scale = ctx.input_mut(|i| {
+            if i.consume_shortcut(&kb_shortcuts::ZOOM_RESET) {
+                Some(1.0)
+            } else if i.consume_shortcut(&kb_shortcuts::ZOOM_IN) || i.consume_shortcut(&kb_shortcuts::ZOOM_IN_SECONDARY) {
+                Some(self.config.ui_scale + UI_SCALE_STEP)
+            } else if i.consume_shortcut(&kb_shortcuts::ZOOM_OUT) {
+                Some(self.config.ui_scale - UI_SCALE_STEP)
+            } else {
+                None
+            }
+        });
+        if let Some(scale) = scale {
+            self.set_ui_scale(ctx, scale);
+        }
+    }
+
+    fn set_ui_scale(&mut self, ctx: &egui::Context, scale: f32) {
+        // Rounded so repeated steps don't drift away from whole percentages
+        self.config.ui_scale = ((scale * 100.0).round() / 100.0).clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+        apply_ui_scale(ctx, self.config.ui_scale);
+        self.config.save();
+    }
+
+    /// Sends or throws away a voice recording.
+    fn finish_recording(&mut self, recording: VoiceRecording, send: bool, chat_id: &str) {
+        let duration = recording.recorder.elapsed();
+        let (samples, sample_rate) = recording.recorder.finish();
+        if !send {
+            self.notify(Severity::Info, "Voice message cancelled.");
+            return;
+        }
+        if duration < MIN_VOICE_DURATION {
+            self.notify(Severity::Info, "Too short, hold the 🎤 button to record.");
+            return;
+        }
+        if self.tx.try_send(GuiAction::SendVoice { chat_id: chat_id.to_string(), samples, sample_rate }).is_err() {
+            self.notify(Severity::Error, "Couldn't send the voice message, please try again.");
+            return;
+        }
+        self.upload = Some((0, 0));
+        self.status_message = "Sending voice message...".to_string();
+    }
+
+    /// The selected messages, oldest first.
+    fn selected_ids(&self) -> Vec<i32> {
+        let mut ids: Vec<i32> = self.selected_messages.iter().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Actions on the selected messages, shown above the chat while any are selected.
+    fn selection_bar(&mut self, ui: &mut egui::Ui, chat: &ChatInfo) {
+        ui.horizontal(|ui| {
+            if ui.small_button("✕").on_hover_text("Exit selection").clicked() {
+                self.selected_messages.clear();
+                self.confirm_delete_selected = false;
+                return;
+            }
+            let count = self.selected_messages.len();
+            ui.label(format!("{} selected", count));
+            if ui.button("Select all visible").clicked() {
+                self.selected_messages.extend(self.visible_messages.iter().copied());
+            }
+            if ui.button("Copy").clicked() {
+                let text: Vec<String> = self
+                    .messages
+                    .iter()
+                    .filter(|m| self.selected_messages.contains(&m.id))
+                    .map(|m| {
+                        let date = m.date.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+                        // Copying is asking for the text, spoilers included
+                        let text = if m.text.is_empty() { message_summary(m) } else { m.text.clone() };
+                        format!("[{}] {}:\n{}", date, m.sender, text)
+                    })
+                    .collect();
+                ui.ctx().copy_text(text.join("\n\n"));
+                self.notify(Severity::Success, format!("Copied {} message(s)", text.len()));
+            }
+            if ui.button("Forward…").clicked() {
+                self.forward_picker = Some(ForwardPicker::default());
+            }
+            if !self.confirm_delete_selected {
+                if ui.button("Delete…").clicked() {
+                    self.confirm_delete_selected = true;
+                }
+                return;
+            }
+            ui.label(format!("Delete {} message(s)?", count));
+            if ui.small_button("Yes").clicked() {
+                // The selection stays until `MessagesDeleted`, so a failed delete can be retried
+                let _ = self.tx.try_send(GuiAction::DeleteMessages { chat_id: chat.id.clone(), message_ids: self.selected_ids() });
+                self.status_message = "Deleting messages...".to_string();
+                self.confirm_delete_selected = false;
+            }
+            if ui.small_button("No").clicked() {
+                self.confirm_delete_selected = false;
+            }
+        });
+    }
+
+    /// Picks the chat the selected messages are forwarded to.
+    fn forward_picker(&mut self, ctx: &egui::Context) {
+        let (Some(picker), Some(chat)) = (&mut self.forward_picker, &self.selected_chat) else {
+            self.forward_picker = None;
+            return;
+        };
+        let chat_id = chat.id.clone();
+        // Like the chat list's search, only hit the server once the user stops typing
+        if let Some(changed_at) = picker.changed_at {
+            if changed_at.elapsed() >= SEARCH_DEBOUNCE {
+                picker.changed_at = None;
+                let query = picker.query.trim();
+                if !query.is_empty() && self.config.login_mode != LoginMode::Bot {
+                    let _ = self.tx.try_send(GuiAction::SearchForwardTargets(query.to_string()));
+                }
+            } else {
+                ctx.request_repaint_after(SEARCH_DEBOUNCE.saturating_sub(changed_at.elapsed()));
+            }
+        }
+        let mut target = None;
+        let mut close = false;
+        egui::Window::new("Forward to")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.set_width(320.0);
+                let response =
+                    ui.add(egui::TextEdit::singleline(&mut picker.query).hint_text("Search chats").desired_width(f32::INFINITY));
+                if response.changed() {
+                    picker.found.clear();
+                    picker.changed_at = Some(Instant::now());
+                }
+                ui.separator();
+                let needle = picker.query.trim().to_lowercase();
+                let known = self.chats.iter().filter(|c| name_matches(&c.name, &needle));
+                let found = picker.found.iter().filter(|f| !self.chats.iter().any(|c| c.id == f.id));
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for chat in known.chain(found) {
+                        ui.horizontal(|ui| {
+                            avatar(ui, self.avatars.get(&chat.id), &chat.name, SENDER_AVATAR_SIZE);
+                            if ui.selectable_label(false, &chat.name).clicked() {
+                                target = Some(chat.id.clone());
+                            }
+                        });
+                    }
+                });
+                ui.separator();
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+            });
+        if let Some(to) = target {
+            // The selection stays until `MessagesForwarded`, so a failed forward can be retried
+            let message_ids = self.selected_ids();
+            self.status_message = format!("Forwarding {} message(s)...", message_ids.len());
+            let _ = self.tx.try_send(GuiAction::ForwardMessages { chat_id, message_ids, to });
+            close = true;
+        }
+        if close {
+            self.forward_picker = None;
+        }
+    }
+
+    /// Opens any chat by name from anywhere, toggled with Ctrl+K.
+    fn quick_switcher(&mut self, ctx: &egui::Context) {
+        if !matches!(self.state, GuiState::LoggedIn) {
+            self.quick_switcher_open = false;
+            return;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::K)) {
+            self.quick_switcher_open = !self.quick_switcher_open;
+            self.quick_switcher_query.clear();
+            self.quick_switcher_index = 0;
+        }
+        if !self.quick_switcher_open {
+            return;
+        }
+
+        let query = self.quick_switcher_query.trim().to_lowercase();
+        let matches: Vec<usize> = (0..self.chats.len())
+            .filter(|&i| name_matches(&self.chats[i].name, &query))
+            .take(QUICK_SWITCHER_RESULTS)
+            .collect();
+        // Taken before the text field sees them, so they drive the list instead
+        let (escape, down, up, enter) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+            )
+        });
+        if down {
+            self.quick_switcher_index += 1;
+        }
+        if up {
+            self.quick_switcher_index = self.quick_switcher_index.saturating_sub(1);
+        }
+        self.quick_switcher_index = self.quick_switcher_index.min(matches.len().saturating_sub(1));
+        let mut opened = if enter { matches.get(self.quick_switcher_index).copied() } else { None };
+
+        egui::Window::new("Go to chat")
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 80.0))
+            .show(ctx, |ui| {
+                ui.set_width(320.0);
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.quick_switcher_query)
+                        .hint_text("Go to chat…")
+                        .desired_width(f32::INFINITY),
+                );
+                response.request_focus();
+                if response.changed() {
+                    self.quick_switcher_index = 0;
+                }
+                ui.separator();
+                if matches.is_empty() {
+                    ui.weak("No matching chats");
+                }
+                for (position, &i) in matches.iter().enumerate() {
+                    let chat = &self.chats[i];
+                    let selected = position == self.quick_switcher_index;
+                    let response = ui.horizontal(|ui| {
+                        avatar(ui, self.avatars.get(&chat.id), &chat.name, SENDER_AVATAR_SIZE);
+                        ui.selectable_label(selected, &chat.name)
+                    });
+                    if response.inner.clicked() {
+                        opened = Some(i);
+                    }
+                }
+            });
+
+        if let Some(i) = opened {
+            let chat = self.chats[i].clone();
+            self.open_chat(chat);
+        }
+        if escape || opened.is_some() {
+            self.quick_switcher_open = false;
+        }
+    }
+
+    /// Offers to send an image pasted into an open chat as a photo.
+    ///
+    /// egui only pastes text, so the clipboard is checked for an image whenever a paste happens.
+    fn handle_pasted_image(&mut self, ctx: &egui::Context) {
+        let Some(chat) = self.selected_chat.clone() else {
+            return;
+        };
+        let pasted = ctx.input(|i| {
+            i.events.iter().any(|event| match event {
+                egui::Event::Paste(_) => true,
+                egui::Event::Key { key: egui::Key::V, pressed: true, modifiers, .. } => modifiers.command,
+                _ => false,
+            })
+        });
+        if pasted
+            && self.pasted_image.is_none()
+            && let Ok(clipboard_image) = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_image())
+        {
+            let size = [clipboard_image.width, clipboard_image.height];
+            let Some(rgba) = image::RgbaImage::from_raw(
+                clipboard_image.width as u32,
+                clipboard_image.height as u32,
+                clipboard_image.bytes.into_owned(),
+            ) else {
+                return;
+            };
+            let path = std::env::temp_dir().join(format!("telegram_paste_{}.png", Utc::now().timestamp_millis()));
+            match rgba.save_with_format(&path, image::ImageFormat::Png) {
+                Ok(()) => {
+                    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, rgba.as_raw());
+                    let texture = ctx.load_texture("pasted_image", color_image, Default::default());
+                    self.pasted_image = Some((path, texture));
+                }
+                Err(e) => self.notify(Severity::Error, format!("Failed to save pasted image: {}", e)),
+            }
+        }
+
+        let Some((path, texture)) = &self.pasted_image else {
+            return;
+        };
+        let mut send = None;
+        egui::Window::new("Send image")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("Send this image to {}?", chat.name));
+                ui.add(egui::Image::new(texture).max_size(egui::vec2(300.0, 300.0)));
+                ui.horizontal(|ui| {
+                    if ui.button("Send").clicked() {
+                        send = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        send = Some(false);
+                    }
+                });
+            });
+        match send {
+            Some(true) => {
+                // The upload reads the file later and deletes it when done
+                let _ = self.tx.try_send(GuiAction::SendPhoto { chat_id: chat.id.clone(), path: path.clone(), temporary: true });
+                self.status_message = "Uploading photo...".to_string();
+                self.pasted_image = None;
+            }
+            Some(false) => {
+                let _ = std::fs::remove_file(path);
+                self.pasted_image = None;
+            }
+            None => {}
+        }
+    }
+
+    /// Sends queued files in order; photos go straight out, documents wait for the upload before them.
+    fn send_next_file(&mut self) {
+        while self.upload.is_none() {
+            let Some((chat_id, path)) = self.file_queue.pop_front() else {
+                break;
+            };
+            if is_photo(&path) {
+                let _ = self.tx.try_send(GuiAction::SendPhoto { chat_id, path, temporary: false });
+            } else {
+                let _ = self.tx.try_send(GuiAction::SendDocument { chat_id, path });
+                self.upload = Some((0, 0));
+            }
+        }
+    }
+
+    /// The bubble shown for a message until Telegram confirms it.
+    fn pending_bubble(&self, message: &QueuedMessage) -> MessageInfo {
+        let now = Utc::now();
+        MessageInfo {
+            id: message.temp_id,
+            text: message.text.clone(),
+            sender: self.profile.as_ref().map_or("You", |p| p.name.as_str()).to_string(),
+            sender_id: String::new(),
+            date: now,
+            date_label: format_timestamp(now),
+            edit_date: None,
+            is_outgoing: true,
+            media: None,
+            entities: Vec::new(),
+            reactions: Vec::new(),
+            reply_to: None,
+            webpage: None,
+            grouped_id: None,
+            forward_from: None,
+            pending: true,
+        }
+    }
+
+    /// Scrolls to `message_id` and flashes it, returning false if it isn't loaded.
+    fn jump_to_message(&mut self, message_id: i32) -> bool {
+        if !self.messages.iter().any(|m| m.id == message_id) {
+            return false;
+        }
+        self.search_results = None;
+        self.reply_search = None;
+        // Otherwise the view stays where it was before older messages were prepended
+        self.scroll_anchor = None;
+        self.scroll_to_message = Some(message_id);
+        self.highlighted_message = Some((message_id, Instant::now()));
+        true
+    }
+
+    /// Loads another page of older history while `message_id` hasn't turned up.
+    fn find_older_message(&mut self, message_id: i32, pages: usize) {
+        self.reply_search = None;
+        if self.reached_beginning || pages >= MAX_REPLY_SEARCH_PAGES {
+            self.notify(Severity::Info, "The original message is too far back or was deleted.");
+            return;
+        }
+        let (Some(chat), Some(oldest)) = (&self.selected_chat, self.messages.iter().map(|m| m.id).min()) else {
+            return;
+        };
+        let _ = self.tx.try_send(GuiAction::LoadOlderMessages { chat_id: chat.id.clone(), before_id: oldest });
+        self.loading_older = true;
+        self.reply_search = Some((message_id, pages + 1));
+        self.status_message = "Looking for the original message...".to_string();
+    }
+
+    /// The contacts tab: a form to add someone by phone number, and the contacts to open chats with.
+    fn contacts_view(&mut self, ui: &mut egui::Ui) {
+        if self.contacts.is_none() && self.tx.try_send(GuiAction::LoadContacts).is_ok() {
+            self.contacts = Some(Vec::new());
+            self.status_message = "Loading contacts...".to_string();
+        }
+        ui.collapsing("➕ Add contact", |ui| {
+            let input = &mut self.contact_input;
+            egui::Grid::new("add_contact").num_columns(2).show(ui, |ui| {
+                ui.label("Phone:");
+                ui.add(egui::TextEdit::singleline(&mut input.phone).hint_text("+51999999999"));
+                ui.end_row();
+                ui.label("First name:");
+                ui.text_edit_singleline(&mut input.first_name);
+                ui.end_row();
+                ui.label("Last name:");
+                ui.add(egui::TextEdit::singleline(&mut input.last_name).hint_text("optional"));
+                ui.end_row();
+            });
+            if ui.button("Add").clicked() {
+                match normalize_phone(&input.phone) {
+                    Err(e) => self.notify(Severity::Error, e.to_string()),
+                    Ok(_) if input.first_name.trim().is_empty() => self.notify(Severity::Error, "Enter a first name"),
+                    Ok(phone) => {
+                        let _ = self.tx.try_send(GuiAction::AddContact {
+                            phone,
+                            first_name: input.first_name.trim().to_string(),
+                            last_name: input.last_name.trim().to_string(),
+                        });
+                        self.status_message = "Adding contact...".to_string();
+                    }
+                }
+            }
+        });
+        ui.separator();
+        let contacts = self.contacts.as_deref().unwrap_or_default();
+        if contacts.is_empty() {
+            ui.weak("No contacts yet");
+        }
+        let mut opened = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for contact in contacts {
+                let row = ui.horizontal(|ui| {
+                    ui.set_min_width(ui.available_width());
+                    avatar(ui, self.avatars.get(&contact.id), &contact.name, AVATAR_SIZE);
+                    ui.vertical(|ui| {
+                        ui.strong(&contact.name);
+                        let username = contact.username.iter().map(|u| format!("@{}", u));
+                        let phone = contact.phone.iter().map(|p| format!("+{}", p));
+                        ui.weak(username.chain(phone).collect::<Vec<_>>().join(" · "));
+                    });
+                });
+                let response = ui
+                    .interact(row.response.rect, ui.id().with(("contact", &contact.id)), egui::Sense::click())
+                    .on_hover_cursor(egui::CursorIcon::PointingHand);
+                if response.clicked() {
+                    opened = Some(ChatInfo::private(contact.id.clone(), contact.name.clone()));
+                }
+                ui.separator();
+            }
+        });
+        if let Some(chat) = opened {
+            self.open_chat(chat);
+        }
+    }
+
+    /// Our chat with ourselves, with what the chat list knows about it if it's listed.
+    fn saved_messages(&self, me: &str) -> ChatInfo {
+        let chat = self
+            .chats
+            .iter()
+            .find(|c| c.id == me)
+            .cloned()
+            .unwrap_or_else(|| ChatInfo::private(me.to_string(), String::new()));
+        ChatInfo { name: SAVED_MESSAGES.to_string(), ..chat }
+    }
+
+    /// Takes the mute state from the server if it reports one, otherwise from the saved set.
+    ///
+    /// The saved set covers chats the server hasn't reported on yet, e.g. search results.
+    /// Returns whether the saved set changed.
+    fn apply_saved_mute(&mut self, chat: &mut ChatInfo) -> bool {
+        if !chat.mute_known {
+            chat.muted = self.config.muted_chats.contains(&chat.id);
+            false
+        } else if chat.muted {
+            self.config.muted_chats.insert(chat.id.clone())
+        } else {
+            // Unmuted on another device
+            self.config.muted_chats.remove(&chat.id)
+        }
+    }
+
+    /// Asks the backend for the avatars of `peer_ids` that haven't been requested yet.
+    fn request_avatars(&mut self, peer_ids: Vec<String>) {
+        for peer_id in peer_ids {
+            if peer_id.is_empty() || self.requested_avatars.contains(&peer_id) {
+                continue;
+            }
+            // Retried next frame if the channel is full
+            if self.tx.try_send(GuiAction::FetchAvatar { peer_id: peer_id.clone() }).is_ok() {
+                self.requested_avatars.insert(peer_id);
+            }
+        }
+    }
+
+    fn send_queued(&mut self, message: QueuedMessage) {
+        let _ = self.tx.try_send(GuiAction::SendMessage {
+            chat_id: message.chat_id.clone(),
+            text: message.text.clone(),
+            markdown: message.markdown,
+            temp_id: message.temp_id,
+            random_id: message.random_id,
+        });
+        self.pending_sends.insert(message.temp_id, message);
+    }
+
+    /// Sends queued messages in order, skipping ones already in flight.
+    fn flush_outbox(&mut self) {
+        if self.connection_state != ConnectionState::Connected {
+            return;
+        }
+        let ready: Vec<QueuedMessage> = self
+            .outbox
+            .iter()
+            .filter(|m| !self.pending_sends.contains_key(&m.temp_id))
+            .cloned()
+            .collect();
+        for message in ready {
+            self.send_queued(message);
+        }
+    }
+
+    /// Forgets a pending message once Telegram has either taken or refused it.
+    fn resolve_pending(&mut self, temp_id: i32) -> Option<QueuedMessage> {
+        let sent = self.pending_sends.remove(&temp_id);
+        let queued = self.outbox.len();
+        self.outbox.retain(|m| m.temp_id != temp_id);
+        if self.outbox.len() != queued {
+            self.save_outbox();
+        }
+        sent
+    }
+
+    fn handle_backend_events(&mut self, ctx: &egui::Context) {
+        while let Ok(event) = self.rx.try_recv() {
+            match event {
+                BackendEvent::ConnectionFailed(error) => {
+                    self.connecting = false;
+                    self.connection_state = ConnectionState::Disconnected;
+                    // Back to the settings, in case it's the API ID or the proxy that is wrong
+                    if matches!(self.state, GuiState::LoginPhone) {
+                        self.state = GuiState::Configuration;
+                    }
+                    self.notify(Severity::Error, error);
+                }
+                BackendEvent::Configured => {
+                    self.connecting = false;
+                    self.connection_state = ConnectionState::Connected;
+                    if let Ok(api_id) = self.api_id_input.parse::<i32>() {
+                        self.config.api_id = Some(api_id);
+                        self.config.api_hash = Some(self.api_hash_input.clone());
+                        self.config.save();
+                    }
+                    self.state = GuiState::LoginPhone;
+                    match self.config.login_mode {
+                        LoginMode::User => self.notify(Severity::Success, "Configuration set. Enter phone number."),
+                        LoginMode::Bot => self.notify(Severity::Success, "Configuration set. Enter bot token."),
+                    }
+                }
+                BackendEvent::CodeSent { next_method } => {
+                    self.state = GuiState::LoginCode;
+                    self.code_sent_at = Some(Instant::now());
+                    self.next_code_method = next_method;
+                    self.notify(Severity::Success, "Code sent! Check Telegram.");
+                }
+                BackendEvent::QrToken(url) => {
+                    match qr_image(&url) {
+                        Some(image) => {
+                            self.qr_texture = Some(ctx.load_texture("qr_login", image, egui::TextureOptions::NEAREST));
+                            self.status_message = "Scan the QR code from Telegram on your phone.".to_string();
+                        }
+                        None => {
+                            self.notify(Severity::Error, "Failed to render QR code");
+                        }
+                    }
+                }
+                BackendEvent::PasswordRequired(hint) => {
+                    self.password_hint = hint.filter(|hint| !hint.is_empty());
+                    self.state = GuiState::LoginPassword;
+                    self.status_message = "2FA Password Required.".to_string();
+                }
+                BackendEvent::LoggedIn => {
+                    self.state = GuiState::LoggedIn;
+                    self.session_reset = false;
+                    self.notify(Severity::Success, "Logged in successfully!");
+                    let _ = self.tx.try_send(GuiAction::FetchMe);
+                    self.refresh_chats();
+                    let _ = self.tx.try_send(GuiAction::LoadFolders);
+                }
+                BackendEvent::MeLoaded { id, name, username, phone } => {
+                    // Label the account after whoever signed into it
+                    let active = self.config.active_account;
+                    if let Some(account) = self.config.accounts.iter_mut().find(|a| a.id == active)
+                        && account.name != name
+                    {
+                        account.name = name.clone();
+                        self.config.save();
+                    }
+                    self.profile = Some(Profile { id, name, username, phone });
+                }
+                BackendEvent::ChatsLoaded(mut chats) => {
+                    let mut changed = false;
+                    for chat in &mut chats {
+                        changed |= self.apply_saved_mute(chat);
+                    }
+                    if changed {
+                        self.config.save();
+                    }
+                    self.request_avatars(chats.iter().map(|c| c.id.clone()).collect());
+                    self.chats = chats;
+                    self.chats_loading = None;
+                    self.all_chats_loaded = false;
+                    self.status_message.clear();
+                    self.flush_outbox();
+                    // The chat may have been left or deleted since, then there is nothing to restore
+                    if let Some(chat_id) = self.restore_chat.take()
+                        && self.selected_chat.is_none()
+                        && let Some(chat) = self.chats.iter().find(|c| c.id == chat_id)
+                    {
+                        self.open_chat(chat.clone());
+                    }
+                }
+                BackendEvent::ChatsLoading => {
+                    self.chats_loading = Some(0);
+                    self.all_chats_loaded = false;
+                }
+                BackendEvent::ChatLoaded(mut chat) => {
+                    if self.apply_saved_mute(&mut chat) {
+                        self.config.save();
+                    }
+                    self.request_avatars(vec![chat.id.clone()]);
+                    // Only the main list streams in chat by chat
+                    self.main_unread.insert(chat.id.clone(), chat.unread_count);
+                    // Refreshed chats replace the old list from the top, so it doesn't empty out in between
+                    let position = self.chats_loading.unwrap_or(self.chats.len()).min(self.chats.len());
+                    if let Some(old) = self.chats[position..].iter().position(|c| c.id == chat.id) {
+                        self.chats.remove(position + old);
+                    }
+                    if self.restore_chat.as_ref() == Some(&chat.id) && self.selected_chat.is_none() {
+                        self.restore_chat = None;
+                        self.open_chat(chat.clone());
+                    }
+                    self.chats.insert(position, chat);
+                    self.chats_loading = Some(position + 1);
+                }
+                BackendEvent::ChatsLoadingFinished { all_loaded } => {
+                    // Whatever wasn't sent again is gone from the list
+                    if let Some(loaded) = self.chats_loading.take() {
+                        self.chats.truncate(loaded);
+                        self.main_unread = self.chats.iter().map(|c| (c.id.clone(), c.unread_count)).collect();
+                    }
+                    self.all_chats_loaded = all_loaded;
+                    self.status_message.clear();
+                    self.flush_outbox();
+                    // The chat may have been left or deleted since, then there is nothing to restore
+                    self.restore_chat = None;
+                }
+                BackendEvent::FoldersLoaded(folders) => {
+                    // Fall back to "All" if the selected folder was deleted
+                    if self.selected_folder.is_some_and(|id| !folders.iter().any(|f| f.id == id)) {
+                        self.selected_folder = None;
+                    }
+                    self.folders = folders;
+                }
+                BackendEvent::ArchiveChanged { chat_id, archived } => {
+                    let unread = self.chats.iter().find(|c| c.id == chat_id).map(|c| c.unread_count);
+                    if archived {
+                        self.main_unread.remove(&chat_id);
+                    } else if let Some(unread) = unread {
+                        self.main_unread.insert(chat_id.clone(), unread);
+                    }
+                    // Either way the chat no longer belongs in the list being shown
+                    self.chats.retain(|c| c.id != chat_id);
+                    self.notify(Severity::Success, if archived { "Chat archived." } else { "Chat unarchived." });
+                }
+                BackendEvent::ChatOpened(chat) => self.open_chat(chat),
+                BackendEvent::ChatJoined { chat, already_member } => {
+                    let name = chat.name.clone();
+                    self.open_chat(chat);
+                    self.refresh_chats();
+                    let text = if already_member {
+                        format!("You're already a member of {}.", name)
+                    } else {
+                        format!("Joined {}.", name)
+                    };
+                    self.notify(Severity::Success, text);
+                }
+                BackendEvent::HistoryCleared { chat_id, chat_deleted } => {
+                    let is_open = self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id);
+                    if chat_deleted {
+                        self.chats.retain(|c| c.id != chat_id);
+                        self.main_unread.remove(&chat_id);
+                        if is_open {
+                            self.close_chat();
+                        }
+                        self.notify(Severity::Success, "Chat deleted.");
+                    } else {
+                        if is_open {
+                            self.messages.clear();
+                            self.pinned_message = None;
+                            self.reached_beginning = true;
+                        }
+                        if let Some(chat) = self.chats.iter_mut().find(|c| c.id == chat_id) {
+                            chat.last_message.clear();
+                            chat.last_message_date.clear();
+                            chat.unread_count = 0;
+                        }
+                        if let Some(unread) = self.main_unread.get_mut(&chat_id) {
+                            *unread = 0;
+                        }
+                        self.notify(Severity::Success, "History cleared.");
+                    }
+                }
+                BackendEvent::ChatRead(chat_id) => {
+                    // The divider stays put while the chat is open, like in the official apps
+                    if let Some(chat) = self.chats.iter_mut().find(|c| c.id == chat_id) {
+                        chat.unread_count = 0;
+                    }
+                    if let Some(unread) = self.main_unread.get_mut(&chat_id) {
+                        *unread = 0;
+                    }
+                }
+                BackendEvent::ChatLeft(chat_id) => {
+                    self.chats.retain(|c| c.id != chat_id);
+                    self.main_unread.remove(&chat_id);
+                    if self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id) {
+                        self.close_chat();
+                    }
+                    self.notify(Severity::Success, "Left chat.");
+                }
+                BackendEvent::AllowedReactions { chat_id, emoji } => {
+                    if self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id) {
+                        self.allowed_reactions = emoji;
+                    }
+                }
+                BackendEvent::ReactionsUpdated { chat_id, message_id, reactions } => {
+                    if self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id)
+                        && let Some(msg) = self.messages.iter_mut().find(|m| m.id == message_id)
+                    {
+                        msg.reactions = reactions;
+                    }
+                }
+                BackendEvent::ContactsLoaded(contacts) => {
+                    self.request_avatars(contacts.iter().map(|c| c.id.clone()).collect());
+                    self.contacts = Some(contacts);
+                }
+                BackendEvent::ContactAdded(contact) => {
+                    self.notify(Severity::Success, format!("Added {} to your contacts.", contact.name));
+                    self.request_avatars(vec![contact.id.clone()]);
+                    self.contact_input = ContactInput::default();
+                    if let Some(contacts) = &mut self.contacts {
+                        contacts.retain(|c| c.id != contact.id);
+                        contacts.push(contact);
+                        contacts.sort_by_key(|c| c.name.to_lowercase());
+                    }
+                }
+                BackendEvent::MessageSaved => self.notify(Severity::Success, "Saved to Saved Messages."),
+                BackendEvent::PollUpdated { chat_id, message_id, poll } => {
+                    self.poll_choices.remove(&message_id);
+                    if self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id)
+                        && let Some(msg) = self.messages.iter_mut().find(|m| m.id == message_id)
+                    {
+                        msg.media = Some(MediaInfo::Poll(poll));
+                    }
+                }
+                BackendEvent::JoinRequestSent(title) => {
+                    self.notify(Severity::Info, format!("Asked to join {}, an admin has to approve the request.", title));
+                }
+                BackendEvent::MessagesLoaded(msgs) => {
+                    self.request_avatars(msgs.iter().map(|m| m.sender_id.clone()).collect());
+                    self.messages = msgs;
+                    group_albums(&mut self.messages);
+                    self.revealed_spoilers.clear();
+                    self.selected_messages.clear();
+                    // Messages that haven't gone out yet stay visible at the end of the chat
+                    if let Some(chat) = &self.selected_chat {
+                        let waiting = self.outbox.iter().chain(self.pending_sends.values());
+                        let bubbles: Vec<MessageInfo> = waiting
+                            .filter(|m| m.chat_id == chat.id)
+                            .map(|m| self.pending_bubble(m))
+                            .collect();
+                        self.messages.extend(bubbles);
+                    }
+                    self.loading_older = false;
+                    self.reached_beginning = false;
+                    // Start at the first unread message rather than the very bottom
+                    match self.first_unread() {
+                        Some(id) => self.scroll_to_message = Some(id),
+                        None => self.scroll_to_bottom = true,
+                    }
+                    self.status_message.clear();
+                }
+                BackendEvent::OlderMessagesLoaded(mut msgs) => {
+                    self.loading_older = false;
+                    self.request_avatars(msgs.iter().map(|m| m.sender_id.clone()).collect());
+                    if msgs.is_empty() {
+                        self.reached_beginning = true;
+                    } else {
+                        self.scroll_anchor = self.messages.first().map(|m| m.id);
+                        msgs.append(&mut self.messages);
+                        self.messages = msgs;
+                        group_albums(&mut self.messages);
+                        self.status_message.clear();
+                    }
+                    if let Some((message_id, pages)) = self.reply_search
+                        && !self.jump_to_message(message_id)
+                    {
+                        self.find_older_message(message_id, pages);
+                    }
+                }
+                BackendEvent::NewMessage { chat_id, message } => {
+                    self.request_avatars(vec![message.sender_id.clone()]);
+                    let focused = ctx.input(|i| i.viewport().focused).unwrap_or(true);
+                    let is_open = self.selected_chat.as_ref().is_some_and(|chat| chat.id == chat_id);
+                    let chat_name = self.chats.iter().find(|c| c.id == chat_id).map(|c| c.name.clone());
+                    let muted = self.config.muted_chats.contains(&chat_id);
+                    // No need to announce what's already on screen
+                    let seen = focused && is_open;
+                    if self.config.notifications_enabled && !(message.is_outgoing || muted || seen) {
+                        let summary = match chat_name {
+                            Some(name) if name != message.sender => format!("{} in {}", message.sender, name),
+                            _ => message.sender.clone(),
+                        };
+                        show_notification(summary, preview_line(&message_summary(&message)));
+                    }
+                    let unread = !(message.is_outgoing || (is_open && self.config.auto_read));
+                    if let Some(chat) = self.chats.iter_mut().find(|c| c.id == chat_id) {
+                        chat.last_message = message_summary(&message);
+                        chat.last_message_date = message.date_label.clone();
+                        if unread {
+                            chat.unread_count += 1;
+                        }
+                    }
+                    if unread && let Some(count) = self.main_unread.get_mut(&chat_id) {
+                        *count += 1;
+                    }
+                    if is_open && !self.messages.iter().any(|m| m.id == message.id) {
+                        if !message.is_outgoing && self.config.auto_read {
+                            let _ = self.tx.try_send(GuiAction::MarkRead(chat_id));
+                            self.unread_after = None;
+                        }
+                        self.messages.push(message);
+                        group_albums(&mut self.messages);
+                        // Don't yank the view away from history the user is reading
+                        self.scroll_to_bottom |= self.at_bottom;
+                    }
+                }
+                BackendEvent::ForwardTargetsFound { query, chats } => {
+                    if let Some(picker) = &mut self.forward_picker
+                        && picker.query.trim() == query
+                    {
+                        picker.found = chats;
+                    }
+                }
+                BackendEvent::SearchResults(results) => {
+                    self.notify(Severity::Info, format!("Found {} messages.", results.len()));
+                    self.search_results = Some(results);
+                }
+                BackendEvent::MuteChanged { chat_id, muted } => {
+                    let chats = self.chats.iter_mut().chain(self.selected_chat.as_mut());
+                    for chat in chats.filter(|c| c.id == chat_id) {
+                        chat.muted = muted;
+                    }
+                    if muted {
+                        self.config.muted_chats.insert(chat_id);
+                        self.notify(Severity::Success, "Chat muted.");
+                    } else {
+                        self.config.muted_chats.remove(&chat_id);
+                        self.notify(Severity::Success, "Chat unmuted.");
+                    }
+                    self.config.save();
+                }
+                BackendEvent::PinChanged { chat_id, pinned } => {
+                    let chats = self.chats.iter_mut().chain(self.selected_chat.as_mut());
+                    for chat in chats.filter(|c| c.id == chat_id) {
+                        chat.pinned = pinned;
+                    }
+                    self.notify(Severity::Success, if pinned { "Chat pinned." } else { "Chat unpinned." });
+                }
+                BackendEvent::PinnedMessage(message) => self.pinned_message = message,
+                BackendEvent::MessageFetched { chat_id, id, quote } => {
+                    if self.selected_chat.as_ref().is_some_and(|c| c.id == chat_id) {
+                        match quote {
+                            Ok(quote) => {
+                                self.quotes.insert(id, quote.as_deref().map(preview_line));
+                            }
+                            // Asked again the next time the reply is shown
+                            Err(e) => {
+                                log::warn!("Failed to fetch message {}: {}", id, e);
+                                self.requested_quotes.remove(&id);
+                            }
+                        }
+                    }
+                }
+                BackendEvent::MessageSent { temp_id, real_id } => {
+                    self.resolve_pending(temp_id);
+                    // The update for the sent message may have beaten the confirmation here
+                    if self.messages.iter().any(|m| m.id == real_id) {
+                        self.messages.retain(|m| m.id != temp_id);
+                    } else if let Some(msg) = self.messages.iter_mut().find(|m| m.id == temp_id) {
+                        msg.id = real_id;
+                        msg.pending = false;
+                    }
+                    self.notify(Severity::Success, "Message sent.");
+                }
+                BackendEvent::SendFailed { temp_id } => {
+                    if let Some(failed) = self.resolve_pending(temp_id) {
+                        self.failed_messages.push(failed);
+                    }
+                    self.messages.retain(|m| m.id != temp_id);
+                }
+                BackendEvent::MessageQueued { temp_id } => {
+                    // Flushed messages never left the outbox, so only fresh sends need adding
+                    if let Some(message) = self.pending_sends.remove(&temp_id)
+                        && !self.outbox.iter().any(|m| m.temp_id == temp_id)
+                    {
+                        self.outbox.push(message);
+                        self.save_outbox();
+                    }
+                }
+                BackendEvent::MessageDelivered { temp_id } => {
+                    self.resolve_pending(temp_id);
+                    self.messages.retain(|m| m.id != temp_id);
+                    self.notify(Severity::Success, "Message sent.");
+                    // Reload to show the delivered copy, whose ID we don't know
+                    if let Some(chat) = &self.selected_chat {
+                        let _ = self.tx.try_send(GuiAction::SelectChat { chat_id: chat.id.clone(), mark_read: false });
+                    }
+                }
+                BackendEvent::MessagesDeleted(ids) => {
+                    self.messages.retain(|m| !ids.contains(&m.id));
+                    if let Some(results) = &mut self.search_results {
+                        results.retain(|m| !ids.contains(&m.id));
+                    }
+                    for id in &ids {
+                        self.selected_messages.remove(id);
+                    }
+                    match ids.len() {
+                        1 => self.notify(Severity::Success, "Message deleted."),
+                        count => self.notify(Severity::Success, format!("{} messages deleted.", count)),
+                    }
+                }
+                BackendEvent::MessagesForwarded { chat_id, message_ids, to } => {
+                    if self.selected_chat.as_ref().is_some_and(|chat| chat.id == chat_id) {
+                        for id in &message_ids {
+                            self.selected_messages.remove(id);
+                        }
+                    }
+                    let name = self.chats.iter().find(|c| c.id == to).map_or("the chat", |c| c.name.as_str());
+                    let text = match message_ids.len() {
+                        1 => format!("Message forwarded to {}.", name),
+                        count => format!("{} messages forwarded to {}.", count, name),
+                    };
+                    self.notify(Severity::Success, text);
+                }
+                BackendEvent::MessageEdited { chat_id, id, text, entities, edit_date, own } => {
+                    if self.selected_chat.as_ref().is_some_and(|chat| chat.id == chat_id) {
+                        let results = self.search_results.iter_mut().flatten();
+                        for msg in self.messages.iter_mut().chain(results).filter(|m| m.id == id) {
+                            msg.text = text.clone();
+                            msg.entities = entities.clone();
+                            if edit_date.is_some() {
+                                msg.edit_date = edit_date;
+                            }
+                        }
+                    }
+                    if own {
+                        self.notify(Severity::Success, "Message edited.");
+                    }
+                }
+                BackendEvent::MediaDownloaded { message_id, bytes } => {
+                    match image::load_from_memory(&bytes) {
+                        Ok(img) => {
+                            let img = img.to_rgba8();
+                            let size = [img.width() as usize, img.height() as usize];
+                            let color_image = egui::ColorImage::from_rgba_unmultiplied(size, img.as_raw());
+                            let texture = ctx.load_texture(format!("media_{}", message_id), color_image, Default::default());
+                            self.media_textures.insert(message_id, texture);
+                        }
+                        Err(e) => {
+                            log::warn!("Failed to decode media for message {}: {}", message_id, e);
+                        }
+                    }
+                }
+                BackendEvent::StickerDownloaded { file_id, bytes } => match image::load_from_memory(&bytes) {
+                    Ok(img) => {
+                        let img = img.to_rgba8();
+                        let size = [img.width() as usize, img.height() as usize];
+                        let color_image = egui::ColorImage::from_rgba_unmultiplied(size, img.as_raw());
+                        let texture = ctx.load_texture(format!("sticker_{}", file_id), color_image, Default::default());
+                        self.sticker_textures.insert(file_id, texture);
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to decode sticker {}: {}", file_id, e);
+                    }
+                },
+                BackendEvent::AnimationLoaded { message_id, frames } => {
+                    let frames: Vec<_> = frames
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, frame)| {
+                            let texture = ctx.load_texture(format!("animation_{}_{}", message_id, i), frame.image, Default::default());
+                            (texture, frame.delay)
+                        })
+                        .collect();
+                    let next_at = Instant::now() + frames[0].1;
+                    self.animations.insert(message_id, Animation { frames, current: 0, next_at, paused: false });
+                }
+                BackendEvent::FullPhotoDownloaded { message_id, bytes } => {
+                    let texture = match image::load_from_memory(&bytes) {
+                        Ok(img) => {
+                            let img = img.to_rgba8();
+                            let size = [img.width() as usize, img.height() as usize];
+                            let color_image = egui::ColorImage::from_rgba_unmultiplied(size, img.as_raw());
+                            Some(ctx.load_texture(format!("photo_{}", message_id), color_image, Default::default()))
+                        }
+                        Err(e) => {
+                            log::warn!("Failed to decode photo of message {}: {}", message_id, e);
+                            None
+                        }
+                    };
+                    self.full_photos.insert(message_id, texture);
+                }
+                BackendEvent::FullPhotoFailed { message_id, error } => {
+                    log::warn!("Failed to download photo of message {}: {}", message_id, error);
+                    self.full_photos.insert(message_id, None);
+                }
+                BackendEvent::AvatarLoaded { peer_id, bytes } => match image::load_from_memory(&bytes) {
+                    Ok(img) => {
+                        let img = img.to_rgba8();
+                        let size = [img.width() as usize, img.height() as usize];
+                        let color_image = egui::ColorImage::from_rgba_unmultiplied(size, img.as_raw());
+                        let texture = ctx.load_texture(format!("avatar_{}", peer_id), color_image, Default::default());
+                        self.avatars.insert(peer_id, texture);
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to decode avatar of {}: {}", peer_id, e);
+                    }
+                },
+                BackendEvent::MembersLoaded { chat_id, offset, total, members, all_loaded } => {
+                    // Ignore pages for a list that was closed or replaced in the meantime
+                    if let Some(list) = &mut self.members
+                        && list.chat_id == chat_id
+                        && list.members.len() == offset
+                    {
+                        list.total = total;
+                        list.members.extend(members);
+                        list.all_loaded = all_loaded;
+                        list.loading = false;
+                    }
+                }
+                BackendEvent::MessagesRead { chat_id, max_id } => {
+                    for chat in self.chats.iter_mut().chain(self.selected_chat.as_mut()).filter(|c| c.id == chat_id) {
+                        chat.read_outbox_max_id = chat.read_outbox_max_id.max(max_id);
+                    }
+                }
+                BackendEvent::MessageScheduled { chat_id, at } => {
+                    self.notify(Severity::Success, format!("Message scheduled for {}.", format_timestamp(at)));
+                    if let Some(list) = &self.scheduled
+                        && list.chat_id == chat_id
+                    {
+                        let _ = self.tx.try_send(GuiAction::LoadScheduled(chat_id));
+                    }
+                }
+                BackendEvent::ScheduledLoaded { chat_id, messages } => {
+                    if let Some(list) = &mut self.scheduled
+                        && list.chat_id == chat_id
+                    {
+                        list.messages = messages;
+                        list.loading = false;
+                    }
+                }
+                BackendEvent::UserStatus { user_id, status } => {
+                    self.presence.insert(user_id, status);
+                }
+                BackendEvent::PeerTyping { chat_id, user_name } => {
+                    self.typing.entry(chat_id).or_default().insert(user_name, Instant::now());
+                }
+                BackendEvent::DownloadProgress { message_id, downloaded, total } => {
+                    self.downloads.insert(message_id, (downloaded, total));
+                }
+                BackendEvent::DownloadFinished { message_id, path } => {
+                    self.downloads.remove(&message_id);
+                    self.notify(Severity::Success, format!("Saved to {}", path.display()));
+                }
+                BackendEvent::DownloadFailed { message_id, error } => {
+                    self.downloads.remove(&message_id);
+                    self.notify(Severity::Error, error);
+                }
+                BackendEvent::UploadProgress { bytes, total } => {
+                    // Progress still queued from a cancelled upload is dropped
+                    if self.upload.is_some() {
+                        self.upload = Some((bytes, total));
+                    }
+                }
+                BackendEvent::UploadFinished { voice, error } => {
+                    self.upload = None;
+                    match error {
+                        Some(error) => self.notify(Severity::Error, error),
+                        None if voice => self.notify(Severity::Success, "Voice message sent."),
+                        None => self.notify(Severity::Success, "File sent."),
+                    }
+                    self.send_next_file();
+                }
+                BackendEvent::ExportProgress { count, total } => {
+                    // Progress still queued from a cancelled export is dropped
+                    if let Some(export) = self.export.as_mut().filter(|e| e.progress.is_some()) {
+                        export.progress = Some((count, total));
+                    }
+                }
+                BackendEvent::ExportFinished { path, result } => {
+                    self.export = None;
+                    match result {
+                        Ok(count) => self.notify(Severity::Success, format!("Exported {} messages to {}", count, path.display())),
+                        Err(e) => self.notify(Severity::Error, format!("Failed to export chat: {}", e)),
+                    }
+                }
+                BackendEvent::ProxyTested { host, port, result } => match result {
+                    Ok(elapsed) => {
+                        self.notify(Severity::Success, format!("Reached {}:{} in {} ms.", host, port, elapsed.as_millis()));
+                        // Remember the link once it's known to work, so it doesn't have to be pasted again
+                        if self.proxy_input.mtproto
+                            && let Ok(proxy) = MtprotoProxy::from_link(&self.proxy_input.link)
+                            && proxy.server == host
+                            && proxy.port == port
+                        {
+                            self.config.mtproto_proxy = Some(proxy);
+                            self.config.save();
+                        }
+                    }
+                    Err(e) => self.notify(Severity::Error, format!("Can't reach {}:{}: {}", host, port, e)),
+                },
+                BackendEvent::ProxyApplied(result) => match result {
+                    Ok(proxy) => {
+                        let text = match &proxy {
+                            Some(proxy) => format!("Connecting through {}:{}.", proxy.host, proxy.port),
+                            None => "Connecting without proxy.".to_string(),
+                        };
+                        self.config.proxy = proxy;
+                        self.config.save();
+                        self.notify(Severity::Success, text);
+                    }
+                    Err(e) => self.notify(Severity::Error, format!("{}. Kept the current connection.", e)),
+                },
+                BackendEvent::ShutdownComplete => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+                BackendEvent::LoggedOut => {
+                    self.state = GuiState::LoginPhone;
+                    self.clear_account_data();
+                    self.outbox.clear();
+                    self.save_outbox();
+                    self.notify(Severity::Info, "Logged out.");
+                }
+                BackendEvent::SessionExpired => {
+                    // Everything in flight fails the same way, only the first one needs handling.
+                    // That includes the login screen, where the revoked key would otherwise keep failing.
+                    if !self.session_reset {
+                        self.session_reset = true;
+                        self.state = GuiState::LoginPhone;
+                        self.clear_account_data();
+                        // Whoever logs in next needn't be the same user, so nothing queued goes out on their behalf
+                        self.outbox.clear();
+                        self.save_outbox();
+                        let _ = self.tx.try_send(GuiAction::ResetSession);
+                        self.notify(Severity::Error, "Your session expired, please log in again.");
+                    }
+                }
+                BackendEvent::AccountSwitched => {
+                    // Stays here unless the account's session is already authorized
+                    self.state = GuiState::LoginPhone;
+                    self.session_reset = false;
+                    self.clear_account_data();
+                    self.notify(Severity::Info, "Account switched. Sign in to continue.");
+                }
+                BackendEvent::Disconnected => {
+                    self.connection_state = ConnectionState::Disconnected;
+                    self.notify(Severity::Error, "Connection lost.");
+                }
+                BackendEvent::Reconnecting => {
+                    self.connection_state = ConnectionState::Reconnecting;
+                }
+                BackendEvent::Reconnected => {
+                    self.connection_state = ConnectionState::Connected;
+                    self.notify(Severity::Success, "Reconnected.");
+                    self.flush_outbox();
+                }
+                BackendEvent::FloodWait(wait) => {
+                    self.flood_wait_until = Some(Instant::now() + wait);
+                }
+                BackendEvent::Error(error) => {
+                    self.push_toast(Toast::from_error(error));
+                }
+            }
+        }
+    }
+}
+
+impl eframe::App for TelegramApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_backend_events(ctx);
+        self.track_activity(ctx);
+        ctx.request_repaint_after(if self.active { EVENT_POLL_INTERVAL } else { INACTIVE_POLL_INTERVAL });
+        self.update_title(ctx);
+        self.handle_dropped_files(ctx);
+        self.handle_pasted_image(ctx);
+        self.handle_zoom_keys(ctx);
+        self.photo_viewer(ctx);
+        self.quick_switcher(ctx);
+        self.forward_picker(ctx);
+        self.show_toasts(ctx);
+
+        if !self.window_checked {
+            self.window_checked = true;
+            self.keep_window_on_screen(ctx);
+        }
+        self.handle_tray(ctx);
+        let close_requested = ctx.input(|i| i.viewport().close_requested());
+        if close_requested && self.config.minimize_to_tray && self.tray.is_some() && !self.quitting {
+            // Everything keeps running, only the window goes away
+            self.save_window_geometry(ctx);
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+            // A hidden window may not get another frame to notice
+            self.in_tray = true;
+            self.track_activity(ctx);
+        } else if close_requested && self.shutting_down.is_none() {
+            self.save_window_geometry(ctx);
+            self.stash_draft();
+            if self.tx.try_send(GuiAction::Shutdown).is_ok() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.shutting_down = Some(Instant::now());
+            }
+        }
+        if let Some(since) = self.shutting_down {
+            // Don't hang on a backend that stopped answering
+            if since.elapsed() >= SHUTDOWN_TIMEOUT {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            let rect = ctx.content_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("shutdown")));
+            painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(rect.center(), egui::Align2::CENTER_CENTER, "Saving…", egui::FontId::proportional(20.0), egui::Color32::WHITE);
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        if let Some(until) = self.flood_wait_until {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                self.flood_wait_until = None;
+                self.notify(Severity::Info, "You can try again now.");
+            } else {
+                ctx.request_repaint_after(Duration::from_secs(1).min(remaining));
+            }
+        }
+
+        let today = Local::now().date_naive();
+        if today != self.labels_day {
+            self.labels_day = today;
+            for msg in self.messages.iter_mut().chain(self.search_results.iter_mut().flatten()) {
+                msg.date_label = format_timestamp(msg.date);
+            }
+        }
+
+        // Typing updates aren't cancelled explicitly, so entries expire on their own
+        self.typing.retain(|_, users| {
+            users.retain(|_, at| at.elapsed() < TYPING_TIMEOUT);
+            !users.is_empty()
+        });
+        if !self.typing.is_empty() {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+
+        // Only hit the server once the user stops typing in the search box
+        if let Some(changed_at) = self.chat_filter_changed_at {
+            if changed_at.elapsed() >= SEARCH_DEBOUNCE {
+                self.chat_filter_changed_at = None;
+                let query = self.chat_filter.trim();
+                if query.is_empty() {
+                    self.refresh_chats();
+                } else if self.config.login_mode != LoginMode::Bot {
+                    let _ = self.tx.try_send(GuiAction::SearchChats(query.to_string()));
+                }
+            } else {
+                ctx.request_repaint_after(SEARCH_DEBOUNCE.saturating_sub(changed_at.elapsed()));
+            }
+        }
+
+        if self.confirm_logout {
+            egui::Window::new("Log out")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label("Are you sure?");
+                    // grammers only offers auth.logOut, which also invalidates the session on the server
+                    ui.weak("This ends the session on Telegram's servers, signing in again needs a new code.");
+                    ui.horizontal(|ui| {
+                        if ui.button("Confirm").clicked() {
+                            self.confirm_logout = false;
+                            self.status_message = "Logging out...".to_string();
+                            let _ = self.tx.try_send(GuiAction::Logout);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_logout = false;
+                        }
+                    });
+                });
+        }
+
+        if let Some(chat) = &self.confirm_leave {
+            let mut close = false;
+            egui::Window::new("Leave chat")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(format!("Leave {}?", chat.name));
+                    if chat.is_owner {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            "You own this chat. Leaving doesn't delete it, it stays up for the other members and you can't take ownership back.",
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Leave").clicked() {
+                            close = true;
+                            self.status_message = format!("Leaving {}...", chat.name);
+                            let _ = self.tx.try_send(GuiAction::LeaveChat(chat.id.clone()));
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if close {
+                self.confirm_leave = None;
+            }
+        }
+
+        if let Some(export) = &mut self.export {
+            let mut close = false;
+            let mut cancelled = false;
+            egui::Window::new("Export chat")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(format!("Export the messages of {}", export.chat.name));
+                    if let Some((count, total)) = export.progress {
+                        let bar = match total.filter(|&t| t > 0) {
+                            Some(total) => egui::ProgressBar::new(count as f32 / total as f32).text(format!("{} / {} messages", count, total)),
+                            None => egui::ProgressBar::new(0.0).animate(true).text(format!("{} messages", count)),
+                        };
+                        ui.add(bar);
+                        if ui.button("Cancel").clicked() {
+                            let _ = self.tx.try_send(GuiAction::CancelExport);
+                            close = true;
+                            cancelled = true;
+                        }
+                        return;
+                    }
+                    ui.radio_value(&mut export.format, ExportFormat::Json, "JSON")
+                        .on_hover_text("Structured, for reading with other programs");
+                    ui.radio_value(&mut export.format, ExportFormat::Text, "Plain text")
+                        .on_hover_text("One line per message, for reading yourself");
+                    ui.horizontal(|ui| {
+                        if ui.button("Choose file…").clicked()
+                            && let Some(path) = rfd::FileDialog::new()
+                                .set_file_name(format!("{}.{}", file_name_from(&export.chat.name), export.format.extension()))
+                                .save_file()
+                        {
+                            let _ = self.tx.try_send(GuiAction::ExportChat {
+                                chat_id: export.chat.id.clone(),
+                                format: export.format,
+                                path,
+                            });
+                            export.progress = Some((0, None));
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if close {
+                self.export = None;
+            }
+            if cancelled {
+                self.notify(Severity::Info, "Export cancelled.");
+            }
+        }
+
+        if let Some(request) = &mut self.confirm_clear {
+            let mut close = false;
+            let title = if request.delete_chat { "Delete chat" } else { "Clear history" };
+            egui::Window::new(title)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    let chat = &request.chat;
+                    if request.delete_chat {
+                        ui.label(format!("Delete the chat with {} and all its messages?", chat.name));
+                    } else {
+                        ui.label(format!("Delete all messages in {}? The chat stays in your list.", chat.name));
+                    }
+                    if !chat.is_group {
+                        ui.checkbox(&mut request.for_everyone, format!("Also delete for {}", chat.name));
+                    } else if chat.is_owner {
+                        ui.checkbox(&mut request.for_everyone, "Delete for all members");
+                    } else {
+                        ui.weak("Only the owner can delete messages for everyone, this clears the history just for you.");
+                    }
+                    ui.colored_label(ui.visuals().warn_fg_color, "This can't be undone.");
+                    ui.horizontal(|ui| {
+                        if ui.button(title).clicked() {
+                            close = true;
+                            self.status_message = format!("Deleting messages in {}...", chat.name);
+                            let _ = self.tx.try_send(GuiAction::ClearHistory {
+                                chat_id: chat.id.clone(),
+                                delete_chat: request.delete_chat,
+                                for_everyone: request.for_everyone,
+                            });
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if close {
+                self.confirm_clear = None;
+            }
+        }
+
+        if let Some(list) = &mut self.scheduled {
+            let mut open = true;
+            let mut cancel = None;
+            egui::Window::new("Scheduled messages")
+                .open(&mut open)
+                .default_size([280.0, 300.0])
+                .show(ctx, |ui| {
+                    if list.loading {
+                        ui.spinner();
+                    } else if list.messages.is_empty() {
+                        ui.weak("No scheduled messages");
+                    }
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for message in &list.messages {
+                            ui.horizontal(|ui| {
+                                ui.weak(format_timestamp(message.at));
+                                ui.label(preview_line(&message.text));
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    if ui.small_button("✖").on_hover_text("Cancel").clicked() {
+                                        cancel = Some(message.id);
+                                    }
+                                });
+                            });
+                        }
+                    });
+                });
+            if let Some(message_id) = cancel {
+                list.loading = true;
+                let _ = self.tx.try_send(GuiAction::CancelScheduled { chat_id: list.chat_id.clone(), message_id });
+            }
+            if !open {
+                self.scheduled = None;
+            }
+        }
+
+        if let Some(list) = &mut self.members {
+            let mut open = true;
+            let mut load_more = false;
+            egui::Window::new("Members")
+                .open(&mut open)
+                .default_size([260.0, 400.0])
+                .show(ctx, |ui| {
+                    if list.total > 0 {
+                        ui.label(format!("{} members", list.total));
+                        ui.separator();
+                    }
+                    let output = egui::ScrollArea::vertical().show(ui, |ui| {
+                        for member in &list.members {
+                            ui.horizontal(|ui| {
+                                ui.label(&member.name);
+                                if let Some(username) = &member.username {
+                                    ui.weak(format!("@{}", username));
+                                }
+                                if member.is_admin {
+                                    ui.small("admin");
+                                }
+                            });
+                        }
+                        if list.loading {
+                            ui.spinner();
+                        }
+                    });
+                    // Large channels are fetched a page at a time as the list is scrolled
+                    let remaining = output.content_size.y - output.state.offset.y - output.inner_rect.height();
+                    load_more = remaining < BOTTOM_THRESHOLD && !list.loading && !list.all_loaded;
+                });
+            if load_more {
+                list.loading = true;
+                let _ = self.tx.try_send(GuiAction::LoadMembers { chat_id: list.chat_id.clone(), offset: list.members.len() });
+            }
+            if !open {
+                self.members = None;
             }
         }
-    }
-}
 
-impl eframe::App for TelegramApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.handle_backend_events();
+        if self.show_logs {
+            self.log_panel(ctx);
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Telegram Rust Client");
+            ui.horizontal(|ui| {
+                ui.heading("Telegram Rust Client");
+                let (color, text) = match self.connection_state {
+                    ConnectionState::Connected => (egui::Color32::GREEN, "Connected"),
+                    ConnectionState::Disconnected => (egui::Color32::RED, "Disconnected"),
+                    ConnectionState::Reconnecting => (egui::Color32::YELLOW, "Reconnecting..."),
+                };
+                ui.colored_label(color, "●");
+                ui.weak(text);
+                // Dropped connections come back on their own, this is for when connecting failed outright
+                if self.connection_state == ConnectionState::Disconnected
+                    && !self.connecting
+                    && let Some(settings) = &self.last_configure
+                    && ui.small_button("Retry").on_hover_text("Connect again with the last settings").clicked()
+                {
+                    self.status_message = "Connecting...".to_string();
+                    self.configure(settings.clone());
+                }
+                if let Some(proxy) = &self.config.proxy {
+                    ui.weak(format!("via {}:{}", proxy.host, proxy.port)).on_hover_text("Connected through a SOCKS5 proxy");
+                }
+                if !self.outbox.is_empty() {
+                    ui.weak(format!("📤 {} queued", self.outbox.len()))
+                        .on_hover_text("Sent automatically once the connection is back");
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.menu_button("☰", |ui| {
+                        if ui.checkbox(&mut self.show_logs, "Show logs").clicked() {
+                            ui.close();
+                        }
+                        if ui.button("Reset window").clicked() {
+                            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(DEFAULT_WINDOW_SIZE.into()));
+                            self.config.window = None;
+                            self.config.save();
+                            ui.close();
+                        }
+                    });
+                });
+            });
+            // Kept apart from the status line, so operations starting meanwhile don't hide the countdown
+            if let Some(until) = self.flood_wait_until {
+                let remaining = until.saturating_duration_since(Instant::now());
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("Too many requests. Try again in {}s.", remaining.as_secs() + 1),
+                );
+            }
             ui.label(&self.status_message);
             ui.separator();
 
@@ -145,114 +3461,2001 @@ impl eframe::App for TelegramApp {
                         ui.label("API Hash:");
                         ui.text_edit_singleline(&mut self.api_hash_input);
                     });
+                    self.login_mode_selector(ui);
+                    ui.collapsing("Proxy", |ui| self.proxy_editor(ui));
                     if ui.button("Save Configuration").clicked() {
-                        if let Ok(api_id) = self.api_id_input.parse::<i32>() {
-                             let _ = self.tx.try_send(GuiAction::Configure {
-                                 api_id,
-                                 api_hash: self.api_hash_input.clone(),
-                             });
-                             self.status_message = "Saving configuration...".to_string();
-                        } else {
-                            self.status_message = "Invalid API ID (must be a number)".to_string();
+                        match (self.api_id_input.parse::<i32>(), self.proxy_input.parse()) {
+                            (Ok(api_id), Ok(proxy)) => {
+                                self.config.proxy = proxy.clone();
+                                self.config.save();
+                                self.configure(ConnectSettings {
+                                    api_id,
+                                    api_hash: self.api_hash_input.clone(),
+                                    account: self.config.active_account,
+                                    proxy,
+                                });
+                                self.status_message = "Saving configuration...".to_string();
+                            }
+                            (Err(_), _) => self.notify(Severity::Error, "Invalid API ID (must be a number)"),
+                            (_, Err(e)) => self.notify(Severity::Error, e.to_string()),
                         }
                     }
                 }
                 GuiState::LoginPhone => {
-                    ui.horizontal(|ui| {
-                        ui.label("Phone Number:");
-                        ui.text_edit_singleline(&mut self.phone);
-                    });
-                    if ui.button("Send Code").clicked() {
-                        self.status_message = "Sending code...".to_string();
-                        let _ = self.tx.try_send(GuiAction::Login(self.phone.clone()));
+                    if self.config.accounts.len() > 1 {
+                        self.account_switcher(ui);
+                    }
+                    self.login_mode_selector(ui);
+                    match self.config.login_mode {
+                        LoginMode::User => {
+                            ui.horizontal(|ui| {
+                                ui.label("Phone Number:");
+                                self.country_picker(ui);
+                                ui.text_edit_singleline(&mut self.phone);
+                            });
+                            let phone = normalize_phone(&self.phone);
+                            if let Err(problem) = &phone
+                                && !self.phone.trim().is_empty()
+                            {
+                                ui.colored_label(ui.visuals().warn_fg_color, *problem);
+                            }
+                            let can_send = phone.is_ok() && self.flood_wait_until.is_none();
+                            if ui.add_enabled(can_send, egui::Button::new("Send Code")).clicked()
+                                && let Ok(phone) = phone
+                            {
+                                self.status_message = "Sending code...".to_string();
+                                let _ = self.tx.try_send(GuiAction::Login(phone));
+                            }
+                            if ui.button("Login via QR").clicked() {
+                                self.state = GuiState::LoginQr;
+                                self.qr_texture = None;
+                                self.status_message = "Requesting QR code...".to_string();
+                                let _ = self.tx.try_send(GuiAction::LoginQr);
+                            }
+                        }
+                        LoginMode::Bot => {
+                            ui.horizontal(|ui| {
+                                ui.label("Bot Token:");
+                                ui.add(egui::TextEdit::singleline(&mut self.bot_token).password(true));
+                            });
+                            if ui.button("Sign In as Bot").clicked() {
+                                self.status_message = "Signing in as bot...".to_string();
+                                let _ = self.tx.try_send(GuiAction::LoginBot(self.bot_token.trim().to_string()));
+                            }
+                        }
+                    }
+                }
+                GuiState::LoginQr => {
+                    if let Some(texture) = &self.qr_texture {
+                        ui.add(egui::Image::new(texture).fit_to_exact_size(egui::vec2(250.0, 250.0)));
+                    } else {
+                        ui.spinner();
+                    }
+                    ui.label("Open Telegram on your phone, go to Settings > Devices > Link Desktop Device and scan this code.");
+                    if ui.button("Back").clicked() {
+                        self.state = GuiState::LoginPhone;
+                        self.qr_texture = None;
+                        self.status_message = "Enter phone number.".to_string();
+                        let _ = self.tx.try_send(GuiAction::CancelQrLogin);
                     }
                 }
                 GuiState::LoginCode => {
                     ui.horizontal(|ui| {
                         ui.label("Login Code:");
-                        ui.text_edit_singleline(&mut self.code);
+                        ui.add(egui::TextEdit::singleline(&mut self.code).password(!self.reveal_secrets));
+                        self.reveal_toggle(ui);
                     });
-                    if ui.button("Sign In").clicked() {
+                    if ui.add_enabled(self.flood_wait_until.is_none(), egui::Button::new("Sign In")).clicked() {
                         self.status_message = "Verifying code...".to_string();
                         let _ = self.tx.try_send(GuiAction::SendCode(self.code.clone()));
                     }
+                    match &self.next_code_method {
+                        Some(method) => {
+                            let wait = RESEND_DELAY.saturating_sub(self.code_sent_at.map_or(RESEND_DELAY, |at| at.elapsed()));
+                            ui.horizontal(|ui| {
+                                let label = format!("Resend code via {}", method);
+                                if ui.add_enabled(wait.is_zero(), egui::Button::new(label)).clicked() {
+                                    self.status_message = "Requesting a new code...".to_string();
+                                    let _ = self.tx.try_send(GuiAction::ResendCode);
+                                }
+                                if !wait.is_zero() {
+                                    ui.weak(format!("available in {}s", wait.as_secs() + 1));
+                                    ui.ctx().request_repaint_after(Duration::from_secs(1).min(wait));
+                                }
+                            });
+                        }
+                        None => {
+                            ui.weak("Telegram offers no other way to send the code.");
+                        }
+                    }
+                    self.cancel_login_button(ui);
                 }
                 GuiState::LoginPassword => {
                     ui.horizontal(|ui| {
                         ui.label("2FA Password:");
-                        ui.text_edit_singleline(&mut self.password);
+                        ui.add(egui::TextEdit::singleline(&mut self.password).password(!self.reveal_secrets));
+                        self.reveal_toggle(ui);
                     });
+                    if let Some(hint) = &self.password_hint {
+                        ui.weak(format!("Hint: {}", hint));
+                    }
                     if ui.button("Verify Password").clicked() {
                         self.status_message = "Verifying password...".to_string();
                         let _ = self.tx.try_send(GuiAction::SendPassword(self.password.clone()));
                     }
+                    self.cancel_login_button(ui);
                 }
                 GuiState::LoggedIn => {
                     if let Some(selected_chat) = self.selected_chat.clone() {
-                         ui.horizontal(|ui| {
-                             if ui.button("Back").clicked() {
-                                 self.selected_chat = None;
-                                 self.messages.clear();
-                                 let _ = self.tx.try_send(GuiAction::BackToChats);
-                             }
+                         let escape = ui.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape));
+                         let back = ui.horizontal(|ui| {
+                             let back = ui.button("Back").clicked();
                              ui.label(format!("Chat: {}", selected_chat.name));
-                         });
+                             if selected_chat.is_group && ui.small_button("👥").on_hover_text("Members").clicked() {
+                                 self.members = Some(MemberList {
+                                     chat_id: selected_chat.id.clone(),
+                                     total: 0,
+                                     members: Vec::new(),
+                                     loading: true,
+                                     all_loaded: false,
+                                 });
+                                 let _ = self.tx.try_send(GuiAction::LoadMembers { chat_id: selected_chat.id.clone(), offset: 0 });
+                             }
+                             if ui.small_button("🔍").on_hover_text("Search messages").clicked() {
+                                 self.message_search = match self.message_search {
+                                     Some(_) => None,
+                                     None => Some(String::new()),
+                                 };
+                             }
+                             if !self.config.live_updates && ui.small_button("⟳").on_hover_text("Check for new messages").clicked() {
+                                 self.refresh_all();
+                             }
+                             back
+                         }).inner;
+                         if let Some(status) = self.presence.get(&selected_chat.id) {
+                             let label = status.label();
+                             if *status == Presence::Online {
+                                 ui.colored_label(egui::Color32::from_rgb(0, 136, 204), label);
+                             } else {
+                                 ui.weak(label);
+                             }
+                         }
+                         if back || escape {
+                             self.close_chat();
+                         }
+                         if let Some(query) = &mut self.message_search {
+                             ui.horizontal(|ui| {
+                                 let response = ui.add(egui::TextEdit::singleline(query).hint_text("Search in chat"));
+                                 let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                 if (submitted || ui.button("Search").clicked()) && !query.trim().is_empty() {
+                                     self.searched_query = query.trim().to_string();
+                                     self.status_message = "Searching...".to_string();
+                                     let _ = self.tx.try_send(GuiAction::SearchMessages {
+                                         chat_id: selected_chat.id.clone(),
+                                         query: self.searched_query.clone(),
+                                     });
+                                 }
+                             });
+                         }
+                         if self.search_results.is_some() {
+                             ui.horizontal(|ui| {
+                                 ui.label(format!("Search results for \"{}\"", self.searched_query));
+                                 if ui.button("Exit search").clicked() {
+                                     self.search_results = None;
+                                 }
+                             });
+                         }
+                         if let Some(pinned) = &self.pinned_message {
+                             let pinned_id = pinned.id;
+                             let builder = egui::UiBuilder::new().sense(egui::Sense::click());
+                             let banner = ui.scope_builder(builder, |ui| {
+                                 egui::Frame::group(ui.style()).show(ui, |ui| {
+                                     ui.set_min_width(ui.available_width());
+                                     ui.horizontal(|ui| {
+                                         ui.label("📌");
+                                         ui.label(preview_line(&message_summary(pinned)));
+                                         ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                             if ui.small_button("✖").on_hover_text("Unpin").clicked() {
+                                                 let _ = self.tx.try_send(GuiAction::UnpinMessage {
+                                                     chat_id: selected_chat.id.clone(),
+                                                     message_id: pinned_id,
+                                                 });
+                                             }
+                                         });
+                                     });
+                                 });
+                             }).response.on_hover_cursor(egui::CursorIcon::PointingHand);
+                             if banner.clicked() {
+                                 if self.messages.iter().any(|m| m.id == pinned_id) {
+                                     self.search_results = None;
+                                     self.scroll_to_message = Some(pinned_id);
+                                 } else {
+                                     self.notify(Severity::Info, "The pinned message is further back in the history.");
+                                 }
+                             }
+                         }
+                         if !self.selected_messages.is_empty() {
+                             self.selection_bar(ui, &selected_chat);
+                         }
                          ui.separator();
                          
                          // Messages Area
-                         egui::ScrollArea::vertical()
+                         let scroll_anchor = self.scroll_anchor.take();
+                         let scroll_to_message = self.scroll_to_message.take();
+                         let mut failed_action = None;
+                         let mut pending_discarded = None;
+                         let mut reaction_toggle = None;
+                         let mut reply_clicked = None;
+                         let mut quote_request = None;
+                         let mut poll_vote = None;
+                         let mut preview_request = None;
+                         let mut sticker_request = None;
+                         let mut animation_request = None;
+                         let mut spoiler_revealed = None;
+                         let mut selection_toggle: Option<(Vec<i32>, bool)> = None;
+                         let mut copied_toast = None;
+                         let mut visible_now = Vec::new();
+                         let selecting = !self.selected_messages.is_empty();
+                         let mut photo_clicked = None;
+                         let first_unread = if self.search_results.is_none() { self.first_unread() } else { None };
+                         let output = egui::ScrollArea::vertical()
                              .max_height(ui.available_height() - 50.0)
                              .show(ui, |ui| {
-                             for msg in &self.messages {
-                                 ui.group(|ui| {
-                                     ui.horizontal(|ui| {
-                                         ui.strong(&msg.sender);
-                                         ui.weak(&msg.date);
+                             let messages = self.search_results.as_ref().unwrap_or(&self.messages);
+                             if self.search_results.is_some() {
+                                 if messages.is_empty() {
+                                     ui.vertical_centered(|ui| {
+                                         ui.weak("No messages found");
+                                     });
+                                 }
+                             } else if self.reached_beginning {
+                                 ui.vertical_centered(|ui| {
+                                     ui.weak("Beginning of chat");
+                                 });
+                             } else if self.loading_older {
+                                 ui.vertical_centered(|ui| {
+                                     ui.spinner();
+                                 });
+                             }
+                             let mut last_day = None;
+                             for (index, msg) in messages.iter().enumerate() {
+                                 // The rest of an album is drawn in the bubble of its first message
+                                 if msg.grouped_id.is_some() && index > 0 && messages[index - 1].grouped_id == msg.grouped_id {
+                                     continue;
+                                 }
+                                 let album = match msg.grouped_id {
+                                     Some(grouped_id) => {
+                                         let len = messages[index..].iter().take_while(|m| m.grouped_id == Some(grouped_id)).count();
+                                         &messages[index..index + len]
+                                     }
+                                     None => std::slice::from_ref(msg),
+                                 };
+                                 // The caption lives on one message of the album, which then stands for the whole bubble
+                                 let msg = album.iter().find(|m| !m.text.is_empty()).unwrap_or(msg);
+                                 // Albums are selected as a whole, like they're shown
+                                 let album_ids: Vec<i32> = album.iter().map(|m| m.id).collect();
+                                 let media = if album.len() > 1 { None } else { msg.media.as_ref() };
+                                 let day = msg.date.with_timezone(&Local).date_naive();
+                                 if last_day != Some(day) {
+                                     last_day = Some(day);
+                                     ui.vertical_centered(|ui| {
+                                         ui.weak(day_label(day));
+                                     });
+                                 }
+                                 if album.iter().any(|m| Some(m.id) == first_unread) {
+                                     unread_divider(ui);
+                                 }
+                                 let align = if msg.is_outgoing { egui::Align::Max } else { egui::Align::Min };
+                                 let layout = egui::Layout::top_down(align);
+                                 // Clicks are sensed on a background layer so the buttons inside stay usable
+                                 let builder = egui::UiBuilder::new().layout(layout).sense(egui::Sense::click());
+                                 let response = ui.scope_builder(builder, |ui| {
+                                     let mut frame = egui::Frame::group(ui.style());
+                                     if msg.is_outgoing {
+                                         frame = frame.fill(own_message_fill(ui.visuals()));
+                                     }
+                                     if let Some((id, since)) = self.highlighted_message
+                                         && id == msg.id
+                                         && since.elapsed() < HIGHLIGHT_DURATION
+                                     {
+                                         let fade = 1.0 - since.elapsed().as_secs_f32() / HIGHLIGHT_DURATION.as_secs_f32();
+                                         frame = frame.fill(ui.visuals().selection.bg_fill.gamma_multiply(fade));
+                                         ui.ctx().request_repaint();
+                                     }
+                                     frame.show(ui, |ui| {
+                                         ui.set_max_width(ui.available_width() * MESSAGE_WIDTH);
+                                         ui.horizontal(|ui| {
+                                             if selecting && !msg.pending {
+                                                 let mut checked = album.iter().all(|m| self.selected_messages.contains(&m.id));
+                                                 if ui.checkbox(&mut checked, "").changed() {
+                                                     selection_toggle = Some((album_ids.clone(), checked));
+                                                 }
+                                             }
+                                             avatar(ui, self.avatars.get(&msg.sender_id), &msg.sender, SENDER_AVATAR_SIZE);
+                                             ui.strong(&msg.sender);
+                                             ui.weak(&msg.date_label).on_hover_text(
+                                                 msg.date.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+                                             );
+                                             if let Some(edited) = msg.edit_date {
+                                                 ui.weak("(edited)").on_hover_text(
+                                                     edited.with_timezone(&Local).format("Edited %Y-%m-%d %H:%M:%S").to_string(),
+                                                 );
+                                             }
+                                             if msg.pending {
+                                                 // Nothing can be done to a message the server doesn't know about yet
+                                                 ui.weak("🕓").on_hover_text("Sending…");
+                                                 if ui.small_button("Discard").clicked() {
+                                                     pending_discarded = Some(msg.id);
+                                                 }
+                                                 return;
+                                             }
+                                             if msg.is_outgoing {
+                                                 // Groups and channels don't report who read what, so they stay at one check
+                                                 if !selected_chat.is_group && msg.id <= selected_chat.read_outbox_max_id {
+                                                     ui.weak("✔✔").on_hover_text("Read");
+                                                 } else {
+                                                     ui.weak("✔").on_hover_text("Sent");
+                                                 }
+                                             }
+                                             if self.confirm_delete == Some(msg.id) {
+                                                 ui.label("Delete?");
+                                                 if ui.small_button("Yes").clicked() {
+                                                     let _ = self.tx.try_send(GuiAction::DeleteMessages {
+                                                         chat_id: selected_chat.id.clone(),
+                                                         message_ids: album_ids.clone(),
+                                                     });
+                                                     self.confirm_delete = None;
+                                                     self.status_message = "Deleting message...".to_string();
+                                                 }
+                                                 if ui.small_button("No").clicked() {
+                                                     self.confirm_delete = None;
+                                                 }
+                                             } else if ui.small_button("🗑").on_hover_text("Delete message").clicked() {
+                                                 self.confirm_delete = Some(msg.id);
+                                             }
+                                             if msg.is_outgoing && ui.small_button("Edit").clicked() {
+                                                 self.editing_message_id = Some(msg.id);
+                                                 self.message_input = msg.text.clone();
+                                             }
+                                         });
+                                         if let Some(origin) = &msg.forward_from {
+                                             let label = if origin.is_empty() {
+                                                 "Forwarded from unknown".to_string()
+                                             } else {
+                                                 format!("Forwarded from {}", origin)
+                                             };
+                                             ui.label(egui::RichText::new(label).italics().weak());
+                                         }
+                                         if let Some(reply_to) = msg.reply_to {
+                                             let quoted = match (self.messages.iter().find(|m| m.id == reply_to), self.quotes.get(&reply_to)) {
+                                                 (Some(original), _) => format!("↩ {}: {}", original.sender, preview_line(&message_summary(original))),
+                                                 (None, Some(Some(quote))) => format!("↩ {}", quote),
+                                                 (None, Some(None)) => "↩ Deleted message".to_string(),
+                                                 (None, None) => {
+                                                     if !msg.pending
+                                                         && self.connection_state == ConnectionState::Connected
+                                                         && !self.requested_quotes.contains(&reply_to)
+                                                     {
+                                                         quote_request = Some(reply_to);
+                                                     }
+                                                     "↩ Reply to an earlier message".to_string()
+                                                 }
+                                             };
+                                             let quote = egui::Frame::new()
+                                                 .fill(ui.visuals().faint_bg_color)
+                                                 .inner_margin(4.0)
+                                                 .show(ui, |ui| ui.weak(quoted))
+                                                 .response;
+                                             let quote = ui
+                                                 .interact(quote.rect, ui.id().with(("reply", msg.id)), egui::Sense::click())
+                                                 .on_hover_cursor(egui::CursorIcon::PointingHand)
+                                                 .on_hover_text("Go to the original message");
+                                             if quote.clicked() {
+                                                 reply_clicked = Some(reply_to);
+                                             }
+                                         }
+                                         if album.len() > 1 {
+                                             ui.horizontal_wrapped(|ui| {
+                                                 ui.set_max_width(3.0 * (ALBUM_THUMBNAIL_SIZE + ui.spacing().item_spacing.x));
+                                                 for item in album {
+                                                     match (&item.media, self.media_textures.get(&item.id)) {
+                                                         (Some(MediaInfo::Photo), Some(texture)) => {
+                                                             let image = egui::Image::new(texture)
+                                                                 .max_size(egui::Vec2::splat(ALBUM_THUMBNAIL_SIZE))
+                                                                 .sense(egui::Sense::click());
+                                                             if ui.add(image).on_hover_cursor(egui::CursorIcon::PointingHand).clicked() {
+                                                                 photo_clicked = Some(item.id);
+                                                             }
+                                                         }
+                                                         (Some(MediaInfo::Document { name, .. }), _) => {
+                                                             ui.label(format!("📄 {}", name));
+                                                         }
+                                                         _ => {
+                                                             ui.weak("📷 Photo");
+                                                         }
+                                                     }
+                                                 }
+                                             });
+                                         }
+                                         match media {
+                                             Some(MediaInfo::Photo) => {
+                                                 if let Some(texture) = self.media_textures.get(&msg.id) {
+                                                     let image = egui::Image::new(texture).max_width(250.0).sense(egui::Sense::click());
+                                                     if ui.add(image).on_hover_cursor(egui::CursorIcon::PointingHand).clicked() {
+                                                         photo_clicked = Some(msg.id);
+                                                     }
+                                                 } else {
+                                                     ui.weak("📷 Photo");
+                                                 }
+                                             }
+                                             Some(MediaInfo::Document { name, size }) => {
+                                                 ui.label(format!("📄 {} ({} KB)", name, size / 1024));
+                                             }
+                                             Some(MediaInfo::Animation) => {
+                                                 if let Some(animation) = self.animations.get_mut(&msg.id) {
+                                                     let texture = &animation.frames[animation.current].0;
+                                                     let hint = if animation.paused { "Click to play" } else { "Click to pause" };
+                                                     let response = ui
+                                                         .add(egui::Image::new(texture).max_width(250.0).sense(egui::Sense::click()))
+                                                         .on_hover_cursor(egui::CursorIcon::PointingHand)
+                                                         .on_hover_text(hint);
+                                                     if response.clicked() {
+                                                         animation.paused = !animation.paused;
+                                                     }
+                                                     // Animations scrolled out of view keep their frame, so long chats don't eat the CPU
+                                                     if !animation.paused && ui.is_rect_visible(response.rect) {
+                                                         animation.advance();
+                                                         ui.ctx().request_repaint_after(animation.next_at.saturating_duration_since(Instant::now()));
+                                                     }
+                                                 } else {
+                                                     // The thumbnail only shows up when the GIF couldn't be decoded
+                                                     if let Some(texture) = self.media_textures.get(&msg.id) {
+                                                         ui.add(egui::Image::new(texture).max_width(250.0));
+                                                     }
+                                                     let label = ui.weak("🎞 GIF");
+                                                     if !msg.pending && ui.is_rect_visible(label.rect) && !self.requested_animations.contains(&msg.id) {
+                                                         animation_request = Some(msg.id);
+                                                     }
+                                                 }
+                                             }
+                                             Some(MediaInfo::Sticker { file_id, emoji, animated }) => {
+                                                 if let Some(texture) = self.sticker_textures.get(file_id) {
+                                                     ui.add(egui::Image::new(texture).max_size(egui::Vec2::splat(STICKER_SIZE)));
+                                                 } else {
+                                                     if !animated && !msg.pending && !self.requested_stickers.contains(file_id) {
+                                                         sticker_request = Some((msg.id, *file_id));
+                                                     }
+                                                     // The emoji stands in until the image arrives, and for good for animated ones
+                                                     let (rect, response) = ui.allocate_exact_size(egui::Vec2::splat(STICKER_SIZE), egui::Sense::hover());
+                                                     ui.painter().text(
+                                                         rect.center(),
+                                                         egui::Align2::CENTER_CENTER,
+                                                         emoji,
+                                                         egui::FontId::proportional(STICKER_SIZE / 2.0),
+                                                         ui.visuals().text_color(),
+                                                     );
+                                                     if *animated {
+                                                         response.on_hover_text("Animated stickers can't be played yet");
+                                                     }
+                                                 }
+                                             }
+                                             Some(MediaInfo::Poll(poll)) => {
+                                                 let kind = if poll.quiz { "Quiz" } else { "Poll" };
+                                                 ui.strong(format!("📊 {}", poll.question));
+                                                 let voted = poll.options.iter().any(|o| o.chosen);
+                                                 if voted || poll.closed {
+                                                     for option in &poll.options {
+                                                         let share = if poll.total_voters > 0 {
+                                                             option.voters as f32 / poll.total_voters as f32
+                                                         } else {
+                                                             0.0
+                                                         };
+                                                         // A quiz marks the right answer, and ours if it was wrong
+                                                         let mark = match (poll.quiz, option.correct, option.chosen) {
+                                                             (true, true, _) => "✅ ",
+                                                             (true, false, true) => "❌ ",
+                                                             (false, _, true) => "✔ ",
+                                                             _ => "",
+                                                         };
+                                                         let text = format!("{}{} · {:.0}%", mark, option.text, share * 100.0);
+                                                         ui.add(egui::ProgressBar::new(share).text(text));
+                                                     }
+                                                     if let Some(solution) = &poll.solution {
+                                                         ui.weak(format!("💡 {}", solution));
+                                                     }
+                                                 } else {
+                                                     let selected = self.poll_choices.entry(msg.id).or_default();
+                                                     for (index, option) in poll.options.iter().enumerate() {
+                                                         if poll.multiple_choice {
+                                                             let mut checked = selected.contains(&index);
+                                                             if ui.checkbox(&mut checked, &option.text).changed() {
+                                                                 if checked {
+                                                                     selected.push(index);
+                                                                 } else {
+                                                                     selected.retain(|&i| i != index);
+                                                                 }
+                                                             }
+                                                         } else if ui.radio(selected.first() == Some(&index), &option.text).clicked() {
+                                                             *selected = vec![index];
+                                                         }
+                                                     }
+                                                     if ui.add_enabled(!selected.is_empty(), egui::Button::new("Vote")).clicked() {
+                                                         poll_vote = Some((msg.id, selected.clone()));
+                                                     }
+                                                 }
+                                                 let closed = if poll.closed { ", closed" } else { "" };
+                                                 ui.weak(format!("{} · {} votes{}", kind, poll.total_voters, closed));
+                                             }
+                                             None => {}
+                                         }
+                                         if let Some(file_name) = media.and_then(|media| media.file_name(msg.id)) {
+                                             if let Some(&(downloaded, total)) = self.downloads.get(&msg.id) {
+                                                 let progress = if total > 0 { downloaded as f32 / total as f32 } else { 0.0 };
+                                                 ui.add(egui::ProgressBar::new(progress).show_percentage());
+                                             } else if ui.small_button("Download").clicked()
+                                                 && let Some(path) = rfd::FileDialog::new().set_file_name(file_name).save_file()
+                                             {
+                                                 self.downloads.insert(msg.id, (0, 0));
+                                                 let _ = self.tx.try_send(GuiAction::DownloadMedia {
+                                                     chat_id: selected_chat.id.clone(),
+                                                     message_id: msg.id,
+                                                     path,
+                                                 });
+                                             }
+                                         }
+                                         if self.search_results.is_some() {
+                                             ui.label(highlight_matches(&msg.text, &self.searched_query, ui.style()));
+                                         } else if !msg.text.is_empty() {
+                                             let revealed = |start| self.revealed_spoilers.contains(&(msg.id, start));
+                                             match formatted_text(ui, &msg.text, &msg.entities, revealed) {
+                                                 Some(TextClick::Link(link)) => {
+                                                     let _ = self.tx.try_send(GuiAction::OpenDeepLink(link));
+                                                 }
+                                                 Some(TextClick::Spoiler(start)) => spoiler_revealed = Some((msg.id, start)),
+                                                 None => {}
+                                             }
+                                         }
+                                         if let Some(page) = &msg.webpage {
+                                             let card = egui::Frame::group(ui.style()).show(ui, |ui| {
+                                                 ui.horizontal(|ui| {
+                                                     if page.has_photo {
+                                                         match self.media_textures.get(&msg.id) {
+                                                             Some(texture) => {
+                                                                 ui.add(egui::Image::new(texture).max_size(egui::Vec2::splat(PREVIEW_THUMBNAIL_SIZE)));
+                                                             }
+                                                             // Only fetched once the card is actually on screen
+                                                             None if !msg.pending && !self.requested_previews.contains(&msg.id) => {
+                                                                 preview_request = Some(msg.id);
+                                                             }
+                                                             None => {}
+                                                         }
+                                                     }
+                                                     ui.vertical(|ui| {
+                                                         if let Some(site_name) = &page.site_name {
+                                                             ui.label(egui::RichText::new(site_name).small().color(ui.visuals().hyperlink_color));
+                                                         }
+                                                         if let Some(title) = &page.title {
+                                                             ui.strong(title);
+                                                         }
+                                                         if let Some(description) = &page.description {
+                                                             if description.chars().count() > MAX_DESCRIPTION_CHARS {
+                                                                 let short: String = description.chars().take(MAX_DESCRIPTION_CHARS).collect();
+                                                                 ui.label(format!("{}…", short.trim_end())).on_hover_text(description);
+                                                             } else {
+                                                                 ui.label(description);
+                                                             }
+                                                         }
+                                                     });
+                                                 });
+                                             }).response;
+                                             let card = ui
+                                                 .interact(card.rect, ui.id().with(("webpage", msg.id)), egui::Sense::click())
+                                                 .on_hover_cursor(egui::CursorIcon::PointingHand)
+                                                 .on_hover_text(&page.url);
+                                             if card.clicked() {
+                                                 ui.ctx().open_url(egui::OpenUrl::new_tab(&page.url));
+                                             }
+                                         }
+                                         let can_react = !msg.pending && !self.allowed_reactions.is_empty();
+                                         if !msg.reactions.is_empty() || can_react {
+                                             ui.horizontal_wrapped(|ui| {
+                                                 for reaction in &msg.reactions {
+                                                     let fill = if reaction.chosen {
+                                                         ui.visuals().selection.bg_fill
+                                                     } else {
+                                                         ui.visuals().faint_bg_color
+                                                     };
+                                                     let chip = egui::RichText::new(format!("{} {}", reaction.emoji, reaction.count))
+                                                         .small()
+                                                         .background_color(fill);
+                                                     // Our own reaction can always be taken back, even if it's no longer allowed
+                                                     let clickable = reaction.chosen || self.allowed_reactions.contains(&reaction.emoji);
+                                                     let sense = if clickable { egui::Sense::click() } else { egui::Sense::hover() };
+                                                     let response = ui.add(egui::Label::new(chip).sense(sense));
+                                                     let response = if reaction.chosen {
+                                                         response.on_hover_text("You reacted with this, click to remove")
+                                                     } else {
+                                                         response
+                                                     };
+                                                     if response.clicked() {
+                                                         reaction_toggle = Some((msg.id, reaction.emoji.clone(), !reaction.chosen));
+                                                     }
+                                                 }
+                                                 if can_react {
+                                                     ui.menu_button("+", |ui| {
+                                                         ui.horizontal_wrapped(|ui| {
+                                                             ui.set_max_width(200.0);
+                                                             for emoji in &self.allowed_reactions {
+                                                                 if ui.button(emoji).clicked() {
+                                                                     reaction_toggle = Some((msg.id, emoji.clone(), true));
+                                                                     ui.close();
+                                                                 }
+                                                             }
+                                                         });
+                                                     }).response.on_hover_text("Add reaction");
+                                                 }
+                                             });
+                                         }
                                      });
-                                     ui.label(&msg.text);
+                                 }).response;
+                                 if !msg.pending && ui.is_rect_visible(response.rect) {
+                                     visible_now.extend(album_ids.iter().copied());
+                                 }
+                                 if response.long_touched() && !msg.pending {
+                                     let selected = album_ids.iter().all(|id| self.selected_messages.contains(id));
+                                     selection_toggle = Some((album_ids.clone(), !selected));
+                                 }
+                                 response.context_menu(|ui| {
+                                     if !msg.pending && !selecting && ui.button("Select").clicked() {
+                                         selection_toggle = Some((album_ids.clone(), true));
+                                         ui.close();
+                                     }
+                                     if ui.add_enabled(!msg.text.is_empty(), egui::Button::new("Copy text")).clicked() {
+                                         ui.ctx().copy_text(msg.text.clone());
+                                         copied_toast = Some("Copied!".to_string());
+                                         ui.close();
+                                     }
+                                     // Private links only exist for channels and supergroups
+                                     if let Some(channel_id) = selected_chat.channel_id
+                                         && ui.button("Copy message link").clicked()
+                                     {
+                                         ui.ctx().copy_text(format!("https://t.me/c/{}/{}", channel_id, msg.id));
+                                         copied_toast = Some("Copied!".to_string());
+                                         ui.close();
+                                     }
+                                     if !msg.sender_id.is_empty() && ui.button("Copy sender ID").clicked() {
+                                         ui.ctx().copy_text(msg.sender_id.clone());
+                                         copied_toast = Some(format!("Copied {}", msg.sender_id));
+                                         ui.close();
+                                     }
+                                     if msg.pending {
+                                         return;
+                                     }
+                                     if self.config.login_mode == LoginMode::User
+                                         && self.profile.as_ref().is_some_and(|p| p.id != selected_chat.id)
+                                         && ui.button("Save to Saved Messages").clicked()
+                                     {
+                                         let _ = self.tx.try_send(GuiAction::SaveMessage {
+                                             chat_id: selected_chat.id.clone(),
+                                             message_ids: album_ids.clone(),
+                                         });
+                                         ui.close();
+                                     }
+                                     ui.separator();
+                                     if self.pinned_message.as_ref().is_some_and(|p| p.id == msg.id) {
+                                         if ui.button("Unpin").clicked() {
+                                             let _ = self.tx.try_send(GuiAction::UnpinMessage {
+                                                 chat_id: selected_chat.id.clone(),
+                                                 message_id: msg.id,
+                                             });
+                                             ui.close();
+                                         }
+                                     } else {
+                                         for (label, silent) in [("Pin", false), ("Pin silently", true)] {
+                                             if ui.button(label).clicked() {
+                                                 let _ = self.tx.try_send(GuiAction::PinMessage {
+                                                     chat_id: selected_chat.id.clone(),
+                                                     message_id: msg.id,
+                                                     silent,
+                                                 });
+                                                 ui.close();
+                                             }
+                                         }
+                                     }
                                  });
+                                 if scroll_to_message == Some(msg.id) {
+                                     response.scroll_to_me(Some(egui::Align::Center));
+                                 }
+                                 // Keep the previously first message in place after older ones are prepended
+                                 if scroll_anchor == Some(msg.id) {
+                                     ui.scroll_to_rect_animation(
+                                         response.rect,
+                                         Some(egui::Align::TOP),
+                                         egui::style::ScrollAnimation::none(),
+                                     );
+                                 }
+                             }
+                             if self.search_results.is_none() {
+                                 let failed = self.failed_messages.iter().enumerate().filter(|(_, m)| m.chat_id == selected_chat.id);
+                                 for (index, QueuedMessage { text, .. }) in failed {
+                                     ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                                         let error_color = ui.visuals().error_fg_color;
+                                         egui::Frame::group(ui.style()).stroke(egui::Stroke::new(1.0, error_color)).show(ui, |ui| {
+                                             ui.set_max_width(ui.available_width() * MESSAGE_WIDTH);
+                                             ui.label(text);
+                                             ui.horizontal(|ui| {
+                                                 ui.colored_label(error_color, "Failed to send");
+                                                 if ui.small_button("Retry").clicked() {
+                                                     failed_action = Some((index, true));
+                                                 }
+                                                 if ui.small_button("Copy to input").clicked() {
+                                                     failed_action = Some((index, false));
+                                                 }
+                                             });
+                                         });
+                                     });
+                                 }
+                             }
+                             if self.scroll_to_bottom {
+                                 self.scroll_to_bottom = false;
+                                 ui.scroll_to_cursor(Some(egui::Align::BOTTOM));
                              }
                          });
+                         if let Some(message_id) = reply_clicked
+                             && !self.jump_to_message(message_id)
+                         {
+                             if self.loading_older {
+                                 // Checked again when the page already on its way arrives
+                                 self.reply_search = Some((message_id, 0));
+                             } else {
+                                 self.find_older_message(message_id, 0);
+                             }
+                         }
+                         if let Some(message_id) = photo_clicked {
+                             self.viewer = Some(PhotoViewer::new(message_id));
+                         }
+                         if let Some(id) = quote_request
+                             && self.tx.try_send(GuiAction::FetchMessage { chat_id: selected_chat.id.clone(), id }).is_ok()
+                         {
+                             self.requested_quotes.insert(id);
+                         }
+                         if let Some(message_id) = preview_request
+                             && self.tx.try_send(GuiAction::FetchPreviewImage { chat_id: selected_chat.id.clone(), message_id }).is_ok()
+                         {
+                             self.requested_previews.insert(message_id);
+                         }
+                         if let Some(spoiler) = spoiler_revealed {
+                             self.revealed_spoilers.insert(spoiler);
+                         }
+                         self.visible_messages = visible_now;
+                         if let Some(text) = copied_toast {
+                             self.notify(Severity::Success, text);
+                         }
+                         if let Some((ids, select)) = selection_toggle {
+                             for id in ids {
+                                 if select {
+                                     self.selected_messages.insert(id);
+                                 } else {
+                                     self.selected_messages.remove(&id);
+                                 }
+                             }
+                         }
+                         if let Some(message_id) = animation_request
+                             && self.tx.try_send(GuiAction::FetchAnimation { chat_id: selected_chat.id.clone(), message_id }).is_ok()
+                         {
+                             self.requested_animations.insert(message_id);
+                         }
+                         if let Some((message_id, file_id)) = sticker_request
+                             && self.tx.try_send(GuiAction::FetchSticker { chat_id: selected_chat.id.clone(), message_id, file_id }).is_ok()
+                         {
+                             self.requested_stickers.insert(file_id);
+                         }
+                         if let Some((message_id, option_indices)) = poll_vote {
+                             self.status_message = "Voting...".to_string();
+                             let _ = self.tx.try_send(GuiAction::VotePoll {
+                                 chat_id: selected_chat.id.clone(),
+                                 message_id,
+                                 option_indices,
+                             });
+                         }
+                         if let Some((message_id, emoji, add)) = reaction_toggle {
+                             if let Some(msg) = self.messages.iter_mut().find(|m| m.id == message_id) {
+                                 toggle_reaction(&mut msg.reactions, &emoji, add);
+                             }
+                             let _ = self.tx.try_send(GuiAction::ToggleReaction {
+                                 chat_id: selected_chat.id.clone(),
+                                 message_id,
+                                 emoji,
+                                 add,
+                             });
+                         }
+                         if let Some(temp_id) = pending_discarded {
+                             // A send already in flight may still go through, the bubble just stops waiting for it
+                             self.resolve_pending(temp_id);
+                             self.messages.retain(|m| m.id != temp_id);
+                         }
+                         if let Some((index, retry)) = failed_action {
+                             let failed = self.failed_messages.remove(index);
+                             if retry {
+                                 // Retries go through the outbox, so they survive a dropped connection like any send
+                                 let message = QueuedMessage::new(failed.chat_id, failed.text, failed.markdown, self.next_temp_id);
+                                 self.next_temp_id -= 1;
+                                 let bubble = self.pending_bubble(&message);
+                                 self.messages.push(bubble);
+                                 self.scroll_to_bottom = true;
+                                 self.outbox.push(message.clone());
+                                 self.save_outbox();
+                                 if self.connection_state == ConnectionState::Connected {
+                                     self.send_queued(message);
+                                 }
+                             } else {
+                                 self.message_input = failed.text;
+                             }
+                         }
+
+                         let max_offset = output.content_size.y - output.inner_rect.height();
+                         self.at_bottom = output.state.offset.y >= max_offset - BOTTOM_THRESHOLD;
+                         if !self.at_bottom && self.search_results.is_none() {
+                             egui::Area::new(ui.id().with("jump_to_latest"))
+                                 .fixed_pos(output.inner_rect.right_bottom() - egui::vec2(8.0, 8.0))
+                                 .pivot(egui::Align2::RIGHT_BOTTOM)
+                                 .order(egui::Order::Foreground)
+                                 .show(ui.ctx(), |ui| {
+                                     if ui.button("⬇ Jump to latest").clicked() {
+                                         self.scroll_to_bottom = true;
+                                     }
+                                 });
+                         }
+
+                         // Load older messages when scrolling up past the top
+                         let scrolling_up = ui.input(|i| i.smooth_scroll_delta.y > 0.0);
+                         let hovered = ui.rect_contains_pointer(output.inner_rect);
+                         if hovered && scrolling_up && output.state.offset.y <= 0.0
+                             && self.search_results.is_none()
+                             && !self.loading_older && !self.reached_beginning
+                             && let Some(oldest) = self.messages.iter().map(|m| m.id).min()
+                         {
+                             self.loading_older = true;
+                             let _ = self.tx.try_send(GuiAction::LoadOlderMessages {
+                                 chat_id: selected_chat.id.clone(),
+                                 before_id: oldest,
+                             });
+                         }
                          
+                         if let Some(users) = self.typing.get(&selected_chat.id) {
+                             let mut names: Vec<&str> = users.keys().map(String::as_str).collect();
+                             names.sort();
+                             let verb = if names.len() == 1 { "is" } else { "are" };
+                             ui.weak(format!("{} {} typing…", names.join(", "), verb));
+                         }
+
                          ui.separator();
                          
                          // Input Area
+                         if self.editing_message_id.is_some() {
+                             ui.horizontal(|ui| {
+                                 ui.weak("Editing message");
+                                 if ui.small_button("Cancel").clicked() {
+                                     self.editing_message_id = None;
+                                     self.message_input.clear();
+                                 }
+                             });
+                         }
+                         let mut voice_done = None;
+                         if let Some(recording) = &self.recording {
+                             // Nothing more gets recorded past the limit, so it's sent as it is
+                             if recording.recorder.elapsed() >= voice::MAX_DURATION {
+                                 voice_done = Some(true);
+                             }
+                             ui.horizontal(|ui| {
+                                 let seconds = recording.recorder.elapsed().as_secs();
+                                 ui.colored_label(ui.visuals().error_fg_color, "⏺");
+                                 ui.label(format!("Recording {}:{:02}", seconds / 60, seconds % 60));
+                                 if recording.held {
+                                     ui.weak("Let go to send, slide away to cancel");
+                                 } else {
+                                     if ui.button("Send").clicked() {
+                                         voice_done = Some(true);
+                                     }
+                                     if ui.button("✕ Cancel").clicked() {
+                                         voice_done = Some(false);
+                                     }
+                                 }
+                             });
+                             ui.ctx().request_repaint_after(Duration::from_millis(200));
+                         }
+                         if let Some((bytes, total)) = self.upload {
+                             ui.horizontal(|ui| {
+                                 let progress = if total > 0 { bytes as f32 / total as f32 } else { 0.0 };
+                                 let bar = egui::ProgressBar::new(progress)
+                                     .text(format!("Uploading {} / {} KB", bytes / 1024, total / 1024))
+                                     .desired_width(ui.available_width() - 70.0);
+                                 ui.add(bar);
+                                 if ui.button("Cancel").clicked() {
+                                     let _ = self.tx.try_send(GuiAction::CancelUpload);
+                                     self.upload = None;
+                                     self.file_queue.clear();
+                                     self.notify(Severity::Info, "Upload cancelled.");
+                                 }
+                             });
+                         }
                          ui.horizontal(|ui| {
-                             ui.text_edit_singleline(&mut self.message_input);
-                             if ui.button("Send").clicked() {
+                             // Enter sends, Shift+Enter falls through to the text edit as a newline
+                             let input_id = ui.id().with("message_input");
+                             let enter_pressed = ui.memory(|m| m.has_focus(input_id))
+                                 && ui.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Enter));
+                             let response = ui.add(
+                                 egui::TextEdit::multiline(&mut self.message_input)
+                                     .id(input_id)
+                                     .desired_rows(1)
+                                     .hint_text("Enter to send, Shift+Enter for new line"),
+                             );
+                             if let Some(emoji) = self.emoji_picker(ui) {
+                                 insert_at_cursor(ui.ctx(), input_id, &mut self.message_input, emoji);
+                             }
+                             if response.changed() && self.editing_message_id.is_none() {
+                                 if self.message_input.is_empty() {
+                                     self.last_typing_sent = None;
+                                 } else if self.last_typing_sent.is_none_or(|sent| sent.elapsed() >= TYPING_THROTTLE) {
+                                     let _ = self.tx.try_send(GuiAction::SetTyping(selected_chat.id.clone()));
+                                     self.last_typing_sent = Some(Instant::now());
+                                 }
+                             }
+                             let can_send = self.flood_wait_until.is_none();
+                             if (ui.add_enabled(can_send, egui::Button::new("Send")).clicked() || enter_pressed) && can_send {
                                  let text = self.message_input.clone();
-                                 if !text.is_empty() {
-                                     let _ = self.tx.try_send(GuiAction::SendMessage {
+                                 if !text.trim().is_empty() {
+                                     if let Some(message_id) = self.editing_message_id.take() {
+                                         let _ = self.tx.try_send(GuiAction::EditMessage {
+                                             chat_id: selected_chat.id.clone(),
+                                             message_id,
+                                             new_text: text,
+                                         });
+                                         self.status_message = "Editing message...".to_string();
+                                     } else {
+                                         let message = QueuedMessage::new(
+                                             selected_chat.id.clone(),
+                                             text,
+                                             self.config.send_markdown,
+                                             self.next_temp_id,
+                                         );
+                                         self.next_temp_id -= 1;
+                                         let bubble = self.pending_bubble(&message);
+                                         self.messages.push(bubble);
+                                         self.scroll_to_bottom = true;
+                                         if self.connection_state == ConnectionState::Connected {
+                                             self.send_queued(message);
+                                         } else {
+                                             self.outbox.push(message);
+                                             self.save_outbox();
+                                             self.notify(Severity::Info, "Offline, the message will be sent once reconnected.");
+                                         }
+                                     }
+                                     self.message_input.clear();
+                                     self.last_typing_sent = None;
+                                 }
+                                 response.request_focus();
+                             }
+                             let mut schedule_at = None;
+                             ui.add_enabled_ui(can_send && self.editing_message_id.is_none(), |ui| {
+                                 ui.menu_button("🕓", |ui| {
+                                     if self.message_input.trim().is_empty() {
+                                         ui.weak("Type a message to schedule it");
+                                     } else {
+                                         if ui.button("In 1 hour").clicked() {
+                                             schedule_at = Some(Utc::now() + chrono::Duration::hours(1));
+                                         }
+                                         if ui.button("Tomorrow 9:00").clicked() {
+                                             let tomorrow = Local::now().date_naive() + chrono::Days::new(1);
+                                             schedule_at = tomorrow
+                                                 .and_hms_opt(9, 0, 0)
+                                                 .and_then(|naive| naive.and_local_timezone(Local).earliest())
+                                                 .map(|at| at.with_timezone(&Utc));
+                                         }
+                                         ui.horizontal(|ui| {
+                                             ui.add(
+                                                 egui::TextEdit::singleline(&mut self.schedule_input)
+                                                     .desired_width(120.0)
+                                                     .hint_text("YYYY-MM-DD HH:MM"),
+                                             );
+                                             if ui.button("Schedule").clicked() {
+                                                 match chrono::NaiveDateTime::parse_from_str(self.schedule_input.trim(), "%Y-%m-%d %H:%M")
+                                                     .ok()
+                                                     .and_then(|naive| naive.and_local_timezone(Local).earliest())
+                                                 {
+                                                     Some(at) => schedule_at = Some(at.with_timezone(&Utc)),
+                                                     None => self.notify(Severity::Error, "Enter the time as YYYY-MM-DD HH:MM"),
+                                                 }
+                                             }
+                                         });
+                                     }
+                                     ui.separator();
+                                     if ui.button("View scheduled").clicked() {
+                                         self.scheduled = Some(ScheduledList {
+                                             chat_id: selected_chat.id.clone(),
+                                             messages: Vec::new(),
+                                             loading: true,
+                                         });
+                                         let _ = self.tx.try_send(GuiAction::LoadScheduled(selected_chat.id.clone()));
+                                         ui.close();
+                                     }
+                                     if schedule_at.is_some() {
+                                         ui.close();
+                                     }
+                                 }).response.on_hover_text("Send later");
+                             });
+                             if let Some(at) = schedule_at {
+                                 if at <= Utc::now() {
+                                     self.notify(Severity::Error, "Pick a time in the future");
+                                 } else if self
+                                     .tx
+                                     .try_send(GuiAction::ScheduleMessage {
                                          chat_id: selected_chat.id.clone(),
-                                         text,
-                                     });
+                                         text: self.message_input.trim().to_string(),
+                                         markdown: self.config.send_markdown,
+                                         at,
+                                     })
+                                     .is_err()
+                                 {
+                                     // The text stays in the input so nothing is lost
+                                     self.notify(Severity::Error, "Couldn't schedule the message, please try again.");
+                                 } else {
                                      self.message_input.clear();
-                                     self.status_message = "Sending message...".to_string();
+                                     self.schedule_input.clear();
+                                     self.status_message = "Scheduling message...".to_string();
+                                 }
+                             }
+                             if ui.button("Attach Photo").clicked()
+                                 && let Some(path) = rfd::FileDialog::new()
+                                     .add_filter("Images", PHOTO_EXTENSIONS)
+                                     .pick_file()
+                             {
+                                 let _ = self.tx.try_send(GuiAction::SendPhoto {
+                                     chat_id: selected_chat.id.clone(),
+                                     path,
+                                     temporary: false,
+                                 });
+                                 self.status_message = "Uploading photo...".to_string();
+                             }
+                             if ui.add_enabled(self.upload.is_none(), egui::Button::new("Attach File")).clicked()
+                                 && let Some(path) = rfd::FileDialog::new().pick_file()
+                             {
+                                 let _ = self.tx.try_send(GuiAction::SendDocument {
+                                     chat_id: selected_chat.id.clone(),
+                                     path,
+                                 });
+                                 self.upload = Some((0, 0));
+                                 self.status_message = "Uploading file...".to_string();
+                             }
+                             let mic = ui
+                                 .add_enabled(
+                                     self.upload.is_none() && self.recording.as_ref().is_none_or(|r| r.held),
+                                     egui::Button::new("🎤").sense(egui::Sense::click_and_drag()),
+                                 )
+                                 .on_hover_text("Click to record a voice message, or hold to record and let go to send");
+                             if self.recording.is_none() && mic.hovered() && ui.input(|i| i.pointer.primary_pressed()) {
+                                 match Recorder::start() {
+                                     Ok(recorder) => self.recording = Some(VoiceRecording { recorder, held: true }),
+                                     Err(e) => self.notify(Severity::Error, e),
+                                 }
+                             }
+                             if let Some(recording) = &mut self.recording
+                                 && recording.held
+                                 && ui.input(|i| i.pointer.primary_released())
+                             {
+                                 recording.held = false;
+                                 // A quick click leaves it recording until Send or Cancel
+                                 if recording.recorder.elapsed() >= HOLD_TO_RECORD {
+                                     let over = ui.input(|i| i.pointer.interact_pos()).is_some_and(|pos| mic.rect.contains(pos));
+                                     voice_done = Some(over);
                                  }
                              }
                          });
+                         if let Some(send) = voice_done
+                             && let Some(recording) = self.recording.take()
+                         {
+                             self.finish_recording(recording, send, &selected_chat.id);
+                         }
+                         ui.horizontal(|ui| {
+                             if ui.checkbox(&mut self.config.send_markdown, "Markdown").changed() {
+                                 self.config.save();
+                             }
+                             if self.config.send_markdown {
+                                 ui.weak("**bold**  _italic_  `code`");
+                             }
+                         });
                     } else {
-                        ui.horizontal(|ui| {
-                            if ui.button("Refresh Chats").clicked() {
-                                let _ = self.tx.try_send(GuiAction::RefreshChats);
-                            }
+                        if let Some(profile) = &self.profile {
+                            ui.horizontal(|ui| {
+                                let name = ui.strong(&profile.name);
+                                if let Some(phone) = &profile.phone {
+                                    name.on_hover_text(format!("+{}", phone));
+                                }
+                                if let Some(username) = &profile.username {
+                                    ui.weak(format!("@{}", username));
+                                }
+                            });
+                        }
+                        let refresh = ui.horizontal(|ui| {
+                            let label = if self.config.live_updates { "Refresh Chats" } else { "⟳ Refresh" };
+                            let refresh = ui.button(label).clicked();
                             if ui.button("Logout").clicked() {
-                                let _ = self.tx.try_send(GuiAction::Logout);
+                                self.confirm_logout = true;
+                            }
+                            refresh
+                        }).inner;
+                        if refresh {
+                            self.refresh_all();
+                            let _ = self.tx.try_send(GuiAction::LoadFolders);
+                        }
+                        self.account_switcher(ui);
+                        ui.collapsing("Settings", |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Messages to load:");
+                                ui.text_edit_singleline(&mut self.message_limit);
+                                if ui.button("Apply").clicked() {
+                                    match self.message_limit.parse::<usize>() {
+                                        Ok(limit) if (1..=1000).contains(&limit) => {
+                                            let _ = self.tx.try_send(GuiAction::SetMessageLimit(limit));
+                                            self.notify(Severity::Success, format!("Message limit set to {}.", limit));
+                                        }
+                                        _ => {
+                                            self.notify(Severity::Error, "Invalid message limit (must be between 1 and 1000)");
+                                        }
+                                    }
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Theme:");
+                                let before = self.config.theme;
+                                ui.radio_value(&mut self.config.theme, Theme::System, "System");
+                                ui.radio_value(&mut self.config.theme, Theme::Light, "Light");
+                                ui.radio_value(&mut self.config.theme, Theme::Dark, "Dark");
+                                if self.config.theme != before {
+                                    apply_theme(ui.ctx(), self.config.theme);
+                                    self.config.save();
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("UI scale:");
+                                let mut scale = self.config.ui_scale;
+                                let slider = ui
+                                    .add(
+                                        egui::Slider::new(&mut scale, MIN_UI_SCALE..=MAX_UI_SCALE)
+                                            .step_by(UI_SCALE_STEP as f64)
+                                            .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)),
+                                    )
+                                    .on_hover_text("Ctrl+Plus / Ctrl+Minus to zoom, Ctrl+0 to reset");
+                                // Rescaling mid-drag would move the slider out from under the pointer
+                                if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                                    self.set_ui_scale(ui.ctx(), scale);
+                                } else if slider.changed() {
+                                    self.config.ui_scale = scale;
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Font:");
+                                match &self.config.font {
+                                    Some(path) => {
+                                        let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+                                        ui.label(name).on_hover_text(path.display().to_string());
+                                    }
+                                    None => {
+                                        ui.weak("Default");
+                                    }
+                                }
+                                let mut font = None;
+                                if ui.button("Choose…").on_hover_text("A TTF, OTF or TTC font for text egui's font doesn't cover").clicked()
+                                    && let Some(path) = rfd::FileDialog::new().add_filter("Fonts", &["ttf", "otf", "ttc"]).pick_file()
+                                {
+                                    font = Some(Some(path));
+                                }
+                                if self.config.font.is_some() && ui.button("Reset").clicked() {
+                                    font = Some(None);
+                                }
+                                if let Some(font) = font {
+                                    match fonts::install(ui.ctx(), font.as_deref()) {
+                                        Ok(()) => {
+                                            self.config.font = font;
+                                            self.config.save();
+                                        }
+                                        Err(e) => {
+                                            // Put back the font that was working
+                                            let _ = fonts::install(ui.ctx(), self.config.font.as_deref());
+                                            self.notify(Severity::Error, e);
+                                        }
+                                    }
+                                }
+                            });
+                            if ui.checkbox(&mut self.config.notifications_enabled, "Desktop notifications").changed() {
+                                self.config.save();
+                            }
+                            if ui
+                                .add_enabled(
+                                    self.tray_error.is_none(),
+                                    egui::Checkbox::new(&mut self.config.minimize_to_tray, "Minimize to tray when closed"),
+                                )
+                                .on_hover_text("Closing the window keeps the app running in the system tray, Quit from its menu exits")
+                                .on_disabled_hover_text(format!(
+                                    "The system tray isn't available: {}",
+                                    self.tray_error.as_deref().unwrap_or_default()
+                                ))
+                                .changed()
+                            {
+                                if self.config.minimize_to_tray {
+                                    self.enable_tray(ui.ctx());
+                                } else if let Some(tray) = &self.tray {
+                                    tray.set_visible(false);
+                                }
+                                self.config.save();
+                            }
+                            if ui
+                                .checkbox(&mut self.config.live_updates, "Live updates")
+                                .on_hover_text("Turned off, new messages only show up after pressing Refresh, which saves battery and data")
+                                .changed()
+                            {
+                                let _ = self.tx.try_send(GuiAction::SetLiveUpdates(self.config.live_updates));
+                                self.config.save();
                             }
+                            if ui
+                                .add_enabled(
+                                    self.config.live_updates,
+                                    egui::Checkbox::new(&mut self.config.pause_when_inactive, "Pause updates in the background"),
+                                )
+                                .on_hover_text("While the window is minimized or in the tray nothing new is shown, notifications included, until it's back")
+                                .changed()
+                            {
+                                self.config.save();
+                            }
+                            if ui.checkbox(&mut self.config.restore_last_chat, "Reopen last chat on launch").changed() {
+                                self.config.save();
+                            }
+                            if ui
+                                .checkbox(&mut self.config.auto_read, "Mark chats as read when opened")
+                                .on_hover_text("When off, chats stay unread until \"Mark as read\" is picked in the chat list")
+                                .changed()
+                            {
+                                self.config.save();
+                            }
+                            ui.collapsing("Proxy", |ui| {
+                                self.proxy_editor(ui);
+                                if ui.button("Apply").clicked() {
+                                    match self.proxy_input.parse() {
+                                        Ok(proxy) => {
+                                            self.status_message = match &proxy {
+                                                Some(proxy) => format!("Checking {}:{}...", proxy.host, proxy.port),
+                                                None => "Checking the direct connection...".to_string(),
+                                            };
+                                            // Saved once the backend has reached Telegram with it
+                                            let _ = self.tx.try_send(GuiAction::SetProxy(proxy));
+                                        }
+                                        Err(e) => self.notify(Severity::Error, e.to_string()),
+                                    }
+                                }
+                            });
                         });
                         ui.separator();
+                        if self.chats.is_empty() && self.config.login_mode == LoginMode::Bot {
+                            ui.weak("Bots can't list their chats. Chats appear here once someone messages the bot.");
+                        }
+                        // Bots have no contacts
+                        if self.config.login_mode == LoginMode::User {
+                            ui.horizontal(|ui| {
+                                ui.selectable_value(&mut self.show_contacts, false, "💬 Chats");
+                                ui.selectable_value(&mut self.show_contacts, true, "👤 Contacts");
+                            });
+                            if self.show_contacts {
+                                self.contacts_view(ui);
+                                return;
+                            }
+                        }
+                        let filter_id = ui.id().with("chat_filter");
+                        ui.horizontal(|ui| {
+                            ui.label("🔍");
+                            let search = ui.add(
+                                egui::TextEdit::singleline(&mut self.chat_filter).id(filter_id).hint_text("Search chats"),
+                            );
+                            if search.changed() {
+                                self.chat_filter_changed_at = Some(Instant::now());
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("✉");
+                            let response = ui.add(
+                                egui::TextEdit::singleline(&mut self.new_chat).hint_text("New chat: @username or +phone"),
+                            );
+                            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            let query = self.new_chat.trim();
+                            if (ui.button("Open").clicked() || submitted) && !query.is_empty() {
+                                let target = if query.starts_with('+') {
+                                    normalize_phone(query)
+                                } else {
+                                    Ok(query.trim_start_matches('@').to_string())
+                                };
+                                match target {
+                                    Ok(target) => {
+                                        self.status_message = format!("Looking up {}...", query);
+                                        let _ = self.tx.try_send(GuiAction::OpenUsername(target));
+                                        self.new_chat.clear();
+                                    }
+                                    Err(e) => self.notify(Severity::Error, e.to_string()),
+                                }
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("🔗");
+                            let response = ui.add(
+                                egui::TextEdit::singleline(&mut self.invite_link).hint_text("Join: t.me/+invite link"),
+                            );
+                            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            let link = self.invite_link.trim();
+                            if (ui.button("Join").clicked() || submitted) && !link.is_empty() {
+                                self.status_message = "Joining chat...".to_string();
+                                let _ = self.tx.try_send(GuiAction::JoinChat(link.to_string()));
+                                self.invite_link.clear();
+                            }
+                        });
+                        if !self.folders.is_empty() {
+                            ui.horizontal_wrapped(|ui| {
+                                ui.selectable_value(&mut self.selected_folder, None, "All");
+                                for folder in &self.folders {
+                                    ui.selectable_value(&mut self.selected_folder, Some(folder.id), &folder.title);
+                                }
+                            });
+                        }
+                        let archive_toggled = ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.unread_first, "Show unread chats first");
+                            ui.toggle_value(&mut self.show_archived, "🗄 Archived").changed()
+                        }).inner;
+                        if archive_toggled {
+                            self.chats.clear();
+                            self.status_message = "Loading chats...".to_string();
+                            self.refresh_chats();
+                        }
+                        // Saved Messages is pinned above the list rather than listed wherever its dialog falls
+                        let me = self
+                            .profile
+                            .as_ref()
+                            .filter(|_| self.config.login_mode == LoginMode::User && !self.show_archived)
+                            .map(|p| p.id.clone());
+                        if let Some(me) = &me {
+                            let label = egui::RichText::new(format!("🔖 {}", SAVED_MESSAGES)).strong();
+                            if ui.selectable_label(false, label).on_hover_text("Messages you sent to yourself").clicked() {
+                                let chat = self.saved_messages(me);
+                                self.open_chat(chat);
+                            }
+                            ui.separator();
+                        }
+                        // Folders only match the chats loaded so far, so the rest of the list follows while one is open
+                        if self.selected_folder.is_some()
+                            && !self.show_archived
+                            && !self.all_chats_loaded
+                            && self.chats_loading.is_none()
+                            && !self.chats.is_empty()
+                            && self.flood_wait_until.is_none()
+                            && self.connection_state == ConnectionState::Connected
+                        {
+                            self.chats_loading = Some(self.chats.len());
+                            let _ = self.tx.try_send(GuiAction::LoadMoreChats);
+                        }
+                        let filter = self.chat_filter.trim().to_lowercase();
+                        let folder = self.folders.iter().find(|f| Some(f.id) == self.selected_folder);
+                        let mut order: Vec<usize> = (0..self.chats.len())
+                            .filter(|&i| me.as_ref() != Some(&self.chats[i].id))
+                            .filter(|&i| name_matches(&self.chats[i].name, &filter))
+                            .filter(|&i| folder.is_none_or(|folder| folder.contains(&self.chats[i])))
+                            .collect();
+                        if self.unread_first {
+                            order.sort_by_key(|&i| self.chats[i].unread_count == 0);
+                        }
+                        // Pinned chats stay on top either way, the sort keeps the order within each group
+                        order.sort_by_key(|&i| !self.chats[i].pinned);
+
+                        // Arrow keys move the highlight unless another text field is being typed in
+                        let mut opened = None;
+                        let mut moved = false;
+                        let mut copied_toast = None;
+                        if let Some(index) = self.selected_index
+                            && index >= order.len()
+                        {
+                            self.selected_index = order.len().checked_sub(1);
+                        }
+                        let keys_free = ui.memory(|m| m.focused().is_none_or(|id| id == filter_id));
+                        if keys_free && !order.is_empty() {
+                            let (down, up, enter) = ui.input_mut(|i| {
+                                (
+                                    i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                                    i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                                    i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                                )
+                            });
+                            let last = order.len() - 1;
+                            if down {
+                                self.selected_index = Some(self.selected_index.map_or(0, |i| (i + 1).min(last)));
+                                moved = true;
+                            }
+                            if up {
+                                self.selected_index = Some(self.selected_index.map_or(0, |i| i.saturating_sub(1)));
+                                moved = true;
+                            }
+                            if enter && let Some(index) = self.selected_index {
+                                opened = Some(order[index]);
+                            }
+                        }
+
                         egui::ScrollArea::vertical().show(ui, |ui| {
-                            for chat in &self.chats {
-                                if ui.button(&chat.name).clicked() {
-                                    self.selected_chat = Some(chat.clone());
-                                    self.status_message = format!("Loading messages for {}...", chat.name);
-                                    let _ = self.tx.try_send(GuiAction::SelectChat(chat.id.clone()));
+                            for (position, &i) in order.iter().enumerate() {
+                                let chat = &mut self.chats[i];
+                                let highlighted = self.selected_index == Some(position);
+                                let mut frame = egui::Frame::new();
+                                if highlighted {
+                                    frame = frame.fill(ui.visuals().selection.bg_fill.gamma_multiply(0.4));
+                                }
+                                let row = frame.show(ui, |ui| {
+                                    ui.set_min_width(ui.available_width());
+                                    ui.horizontal(|ui| {
+                                        avatar(ui, self.avatars.get(&chat.id), &chat.name, AVATAR_SIZE);
+                                        ui.vertical(|ui| {
+                                            ui.horizontal(|ui| {
+                                                if chat.pinned {
+                                                    ui.weak("📌").on_hover_text("Pinned");
+                                                }
+                                                ui.strong(&chat.name);
+                                                if chat.muted {
+                                                    ui.weak("🔇").on_hover_text("Muted");
+                                                }
+                                                if chat.unread_count > 0 {
+                                                    ui.label(
+                                                        egui::RichText::new(chat.unread_count.to_string())
+                                                            .small()
+                                                            .color(egui::Color32::WHITE)
+                                                            .background_color(egui::Color32::from_rgb(0, 136, 204)),
+                                                    );
+                                                }
+                                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                                    ui.small(&chat.last_message_date);
+                                                });
+                                            });
+                                            if let Some(draft) = self.drafts.get(&chat.id).or(chat.draft.as_ref()).filter(|d| !d.is_empty()) {
+                                                ui.horizontal(|ui| {
+                                                    ui.colored_label(ui.visuals().error_fg_color, "Draft:");
+                                                    ui.weak(preview_line(draft));
+                                                });
+                                            } else {
+                                                ui.weak(preview_line(&chat.last_message));
+                                            }
+                                        });
+                                    });
+                                });
+                                if highlighted && moved {
+                                    ui.scroll_to_rect(row.response.rect, None);
+                                }
+                                let response = ui
+                                    .interact(row.response.rect, ui.id().with(("chat_row", &chat.id)), egui::Sense::click())
+                                    .on_hover_cursor(egui::CursorIcon::PointingHand);
+                                response.context_menu(|ui| {
+                                    ui.menu_button("Copy", |ui| {
+                                        let mut copied = None;
+                                        if ui.button("Copy chat ID").clicked() {
+                                            copied = Some(chat.id.clone());
+                                        }
+                                        if let Some(username) = &chat.username
+                                            && ui.button("Copy username").clicked()
+                                        {
+                                            copied = Some(username.clone());
+                                        }
+                                        // Without a username only users can be mentioned, by ID
+                                        if (chat.username.is_some() || !chat.is_group) && ui.button("Copy @mention").clicked() {
+                                            copied = Some(chat.mention());
+                                        }
+                                        if let Some(text) = copied {
+                                            copied_toast = Some(format!("Copied {}", text));
+                                            ui.ctx().copy_text(text);
+                                            ui.close();
+                                        }
+                                    });
+                                    if chat.unread_count > 0 && ui.button("Mark as read").clicked() {
+                                        let _ = self.tx.try_send(GuiAction::MarkRead(chat.id.clone()));
+                                        ui.close();
+                                    }
+                                    let label = if chat.muted { "Unmute" } else { "Mute" };
+                                    if ui.button(label).clicked() {
+                                        let _ = self.tx.try_send(GuiAction::ToggleMute(chat.id.clone()));
+                                        ui.close();
+                                    }
+                                    let label = if chat.pinned { "Unpin" } else { "Pin to top" };
+                                    if self.config.login_mode == LoginMode::User && ui.button(label).clicked() {
+                                        let _ = self.tx.try_send(GuiAction::TogglePinChat(chat.id.clone()));
+                                        ui.close();
+                                    }
+                                    let (label, action) = if self.show_archived {
+                                        ("Unarchive", GuiAction::UnarchiveChat(chat.id.clone()))
+                                    } else {
+                                        ("Archive", GuiAction::ArchiveChat(chat.id.clone()))
+                                    };
+                                    if ui.button(label).clicked() {
+                                        let _ = self.tx.try_send(action);
+                                        ui.close();
+                                    }
+                                    if chat.is_group && ui.button("Leave chat").clicked() {
+                                        self.confirm_leave = Some(chat.clone());
+                                        ui.close();
+                                    }
+                                    let exporting = self.export.as_ref().is_some_and(|e| e.progress.is_some());
+                                    if ui
+                                        .add_enabled(!exporting, egui::Button::new("Export chat…"))
+                                        .on_disabled_hover_text("Another chat is being exported")
+                                        .clicked()
+                                    {
+                                        self.export = Some(ChatExport { chat: chat.clone(), format: ExportFormat::Json, progress: None });
+                                        ui.close();
+                                    }
+                                    if ui.button("Clear history…").clicked() {
+                                        self.confirm_clear = Some(ClearRequest { chat: chat.clone(), delete_chat: false, for_everyone: false });
+                                        ui.close();
+                                    }
+                                    // Groups and channels leave the list by leaving them
+                                    if !chat.is_group && ui.button("Delete chat…").clicked() {
+                                        self.confirm_clear = Some(ClearRequest { chat: chat.clone(), delete_chat: true, for_everyone: false });
+                                        ui.close();
+                                    }
+                                });
+                                ui.separator();
+                                if response.clicked() {
+                                    self.selected_index = Some(position);
+                                    opened = Some(i);
+                                }
+                            }
+                            if !self.show_archived {
+                                if self.chats_loading.is_some() {
+                                    ui.horizontal(|ui| {
+                                        ui.spinner();
+                                        ui.weak("Loading chats...");
+                                    });
+                                } else if self.all_chats_loaded {
+                                    ui.weak("All chats loaded");
+                                } else if !self.chats.is_empty() && ui.button("Load more chats").clicked() {
+                                    // New chats go after the ones already listed
+                                    self.chats_loading = Some(self.chats.len());
+                                    let _ = self.tx.try_send(GuiAction::LoadMoreChats);
                                 }
                             }
                         });
+                        if let Some(text) = copied_toast {
+                            self.notify(Severity::Success, text);
+                        }
+                        if let Some(i) = opened {
+                            self.open_chat(self.chats[i].clone());
+                        }
                     }
                 }
             }
         });
     }
 }
+
+/// Switches the visuals to `theme`, asking the OS for its preference in `System` mode.
+fn apply_theme(ctx: &egui::Context, theme: Theme) {
+    let dark = match theme {
+        Theme::System => matches!(dark_light::detect(), Ok(dark_light::Mode::Dark)),
+        Theme::Light => false,
+        Theme::Dark => true,
+    };
+    // Pin egui's own theme as well, otherwise it swaps styles when the OS theme changes
+    ctx.set_theme(if dark { egui::Theme::Dark } else { egui::Theme::Light });
+    ctx.set_visuals(if dark { egui::Visuals::dark() } else { egui::Visuals::light() });
+}
+
+/// Zooms the whole interface, text, images and avatars alike since they're all sized in points.
+fn apply_ui_scale(ctx: &egui::Context, scale: f32) {
+    ctx.set_zoom_factor(scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE));
+}
+
+/// Strips spaces and dashes from a phone number and checks it is in international format.
+fn normalize_phone(input: &str) -> Result<String, &'static str> {
+    let phone: String = input.chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+    let Some(digits) = phone.strip_prefix('+') else {
+        return Err("Start with + and the country code");
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err("Only digits may follow the +");
+    }
+    // E.164 numbers are at most 15 digits long, and none are shorter than 7
+    if !(7..=15).contains(&digits.len()) {
+        return Err("Phone number has the wrong length");
+    }
+    Ok(phone)
+}
+
+/// Inserts `insert` at the text edit's cursor and moves the cursor past it.
+fn insert_at_cursor(ctx: &egui::Context, id: egui::Id, text: &mut String, insert: &str) {
+    let mut state = egui::TextEdit::load_state(ctx, id).unwrap_or_default();
+    let index = state.cursor.char_range().map_or(text.chars().count(), |range| range.primary.index);
+    let byte = text.char_indices().nth(index).map_or(text.len(), |(i, _)| i);
+    text.insert_str(byte, insert);
+    let cursor = egui::text::CCursor::new(index + insert.chars().count());
+    state.cursor.set_char_range(Some(egui::text::CCursorRange::one(cursor)));
+    state.store(ctx, id);
+    ctx.memory_mut(|m| m.request_focus(id));
+}
+
+/// Applies our reaction change locally until the server reports the real counts.
+///
+/// Mirrors the server: without Premium adding a reaction replaces the one we had.
+fn toggle_reaction(reactions: &mut Vec<Reaction>, emoji: &str, add: bool) {
+    for reaction in reactions.iter_mut().filter(|r| r.chosen) {
+        reaction.chosen = false;
+        reaction.count -= 1;
+    }
+    if add {
+        match reactions.iter_mut().find(|r| r.emoji == emoji) {
+            Some(reaction) => {
+                reaction.chosen = true;
+                reaction.count += 1;
+            }
+            None => reactions.push(Reaction { emoji: emoji.to_string(), count: 1, chosen: true }),
+        }
+    }
+    reactions.retain(|r| r.count > 0);
+}
+
+fn is_photo(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| PHOTO_EXTENSIONS.iter().any(|photo| ext.eq_ignore_ascii_case(photo)))
+}
+
+/// Turns a chat name into a file name, replacing the characters that can't be in one.
+fn file_name_from(name: &str) -> String {
+    name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+/// Whether a chat called `name` matches `query`, which is already lowercase.
+fn name_matches(name: &str, query: &str) -> bool {
+    query.is_empty() || name.to_lowercase().contains(query)
+}
+
+/// Flattens `text` onto a single line and cuts it down to `PREVIEW_CHARS` characters.
+fn preview_line(text: &str) -> String {
+    let flat = text.replace(['\r', '\n'], " ");
+    if flat.chars().count() <= PREVIEW_CHARS {
+        return flat;
+    }
+    let mut preview: String = flat.chars().take(PREVIEW_CHARS).collect();
+    preview.push('…');
+    preview
+}
+
+/// The text with its spoilers blocked out, for previews where they can't be clicked open.
+pub fn mask_spoilers(text: &str, entities: &[TextEntity]) -> String {
+    text.char_indices()
+        .map(|(i, c)| {
+            let hidden = entities.iter().any(|e| e.kind == EntityKind::Spoiler && e.range.contains(&i));
+            if hidden && !c.is_whitespace() { SPOILER_MASK } else { c }
+        })
+        .collect()
+}
+
+/// Formats `date` in local time: just the time for today, "Yesterday HH:MM", or the full date.
+pub fn format_timestamp(date: DateTime<Utc>) -> String {
+    let local = date.with_timezone(&Local);
+    let today = Local::now().date_naive();
+    if local.date_naive() == today {
+        local.format("%H:%M").to_string()
+    } else if today.pred_opt() == Some(local.date_naive()) {
+        local.format("Yesterday %H:%M").to_string()
+    } else {
+        local.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+/// A line with "New messages" across it, above the first unread message.
+fn unread_divider(ui: &mut egui::Ui) {
+    let color = ui.visuals().selection.bg_fill;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 20.0), egui::Sense::hover());
+    let galley = ui.painter().layout_no_wrap("New messages".to_string(), egui::TextStyle::Small.resolve(ui.style()), color);
+    let size = galley.size();
+    let center = rect.center();
+    let gap = size.x / 2.0 + 8.0;
+    let stroke = egui::Stroke::new(1.0, color);
+    ui.painter().hline(rect.left()..=center.x - gap, center.y, stroke);
+    ui.painter().hline(center.x + gap..=rect.right(), center.y, stroke);
+    ui.painter().galley(center - size / 2.0, galley, color);
+}
+
+/// Header shown above the first message of each day.
+fn day_label(day: NaiveDate) -> String {
+    let today = Local::now().date_naive();
+    if day == today {
+        "Today".to_string()
+    } else if today.pred_opt() == Some(day) {
+        "Yesterday".to_string()
+    } else {
+        day.format("%A, %B %-d, %Y").to_string()
+    }
+}
+
+/// Background of the user's own messages, Telegram's bubble colors for each theme.
+fn own_message_fill(visuals: &egui::Visuals) -> egui::Color32 {
+    if visuals.dark_mode {
+        egui::Color32::from_rgb(43, 82, 120)
+    } else {
+        egui::Color32::from_rgb(225, 245, 254)
+    }
+}
+
+/// Text to show for a message outside the chat view, using a placeholder for media without a caption.
+fn message_summary(message: &MessageInfo) -> String {
+    match &message.media {
+        _ if !message.text.is_empty() => mask_spoilers(&message.text, &message.entities),
+        Some(MediaInfo::Photo) => "📷 Photo".to_string(),
+        Some(MediaInfo::Document { name, .. }) => format!("📄 {}", name),
+        Some(MediaInfo::Sticker { emoji, .. }) => format!("{} Sticker", emoji),
+        Some(MediaInfo::Animation) => "🎞 GIF".to_string(),
+        Some(MediaInfo::Poll(poll)) => format!("📊 {}", poll.question),
+        None => String::new(),
+    }
+}
+
+/// Moves the messages of each album next to each other in ID order, so they render as one bubble.
+fn group_albums(messages: &mut Vec<MessageInfo>) {
+    let mut first_seen = HashMap::new();
+    let mut keyed: Vec<_> = std::mem::take(messages)
+        .into_iter()
+        .enumerate()
+        .map(|(index, message)| {
+            let position = message.grouped_id.map_or(index, |id| *first_seen.entry(id).or_insert(index));
+            ((position, message.id), message)
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    *messages = keyed.into_iter().map(|(_, message)| message).collect();
+}
+
+/// Shows a desktop notification without blocking the UI thread on the notification daemon.
+fn show_notification(summary: String, body: String) {
+    std::thread::spawn(move || {
+        let result = notify_rust::Notification::new()
+            .appname("Telegram Rust Client")
+            .summary(&summary)
+            .body(&body)
+            .show();
+        if let Err(e) = result {
+            log::warn!("Failed to show notification: {}", e);
+        }
+    });
+}
+
+/// Draws a round avatar, or the initials of `name` on a colored circle when there is no photo.
+fn avatar(ui: &mut egui::Ui, texture: Option<&egui::TextureHandle>, name: &str, size: f32) {
+    if let Some(texture) = texture {
+        ui.add(
+            egui::Image::new(texture)
+                .fit_to_exact_size(egui::vec2(size, size))
+                .corner_radius(egui::CornerRadius::same((size / 2.0) as u8)),
+        );
+        return;
+    }
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+    let color = AVATAR_COLORS[name.chars().map(|c| c as usize).sum::<usize>() % AVATAR_COLORS.len()];
+    let initials: String = name
+        .split_whitespace()
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+    let painter = ui.painter();
+    painter.circle_filled(rect.center(), size / 2.0, color);
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        initials,
+        egui::FontId::proportional(size * 0.45),
+        egui::Color32::WHITE,
+    );
+}
+
+/// Something in a message's text that was clicked and needs handling outside of it.
+enum TextClick {
+    /// A `tg://` link, which opens a chat instead of the browser.
+    Link(String),
+    /// A hidden spoiler, by where it starts in the text.
+    Spoiler(usize),
+}
+
+/// Renders `text` with its Telegram formatting, splitting it wherever an entity starts or ends.
+///
+/// Spoilers stay blocked out unless `revealed` says the one starting there was clicked open.
+fn formatted_text(
+    ui: &mut egui::Ui,
+    text: &str,
+    entities: &[TextEntity],
+    revealed: impl Fn(usize) -> bool,
+) -> Option<TextClick> {
+    let mut entities = entities.to_vec();
+    // Not every client marks up links, so bare URLs are picked up from the text as well
+    for range in find_urls(text) {
+        let linked = entities.iter().any(|e| {
+            matches!(e.kind, EntityKind::Url | EntityKind::TextUrl(_))
+                && e.range.start < range.end
+                && range.start < e.range.end
+        });
+        if !linked {
+            entities.push(TextEntity { range, kind: EntityKind::Url });
+        }
+    }
+    if entities.is_empty() {
+        ui.label(text);
+        return None;
+    }
+    let mut bounds: Vec<usize> = entities
+        .iter()
+        .flat_map(|entity| [entity.range.start, entity.range.end])
+        .chain([0, text.len()])
+        .filter(|&i| text.is_char_boundary(i))
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut clicked = None;
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        for span in bounds.windows(2) {
+            let (start, end) = (span[0], span[1]);
+            let covering: Vec<&TextEntity> =
+                entities.iter().filter(|e| e.range.start <= start && end <= e.range.end).collect();
+            let mut shown = text[start..end].to_string();
+            let mut url = None;
+            let mut spoiler = None;
+            for entity in &covering {
+                match &entity.kind {
+                    EntityKind::Spoiler if !revealed(entity.range.start) => spoiler = Some(entity.range.start),
+                    EntityKind::Url => {
+                        url = Some(text[entity.range.clone()].to_string());
+                        if shown.chars().count() > MAX_LINK_CHARS {
+                            shown = shown.chars().take(MAX_LINK_CHARS).collect();
+                            shown.push('…');
+                        }
+                    }
+                    EntityKind::TextUrl(target) => url = Some(target.clone()),
+                    _ => {}
+                }
+            }
+            let mut rich = egui::RichText::new(shown);
+            for entity in &covering {
+                rich = match &entity.kind {
+                    EntityKind::Bold => rich.strong(),
+                    EntityKind::Italic => rich.italics(),
+                    EntityKind::Underline => rich.underline(),
+                    EntityKind::Strikethrough => rich.strikethrough(),
+                    EntityKind::Code | EntityKind::Pre => {
+                        rich.monospace().background_color(ui.visuals().code_bg_color)
+                    }
+                    EntityKind::CustomEmoji(_) => rich.background_color(ui.visuals().faint_bg_color),
+                    EntityKind::Url | EntityKind::TextUrl(_) | EntityKind::Spoiler => rich,
+                };
+            }
+            if let Some(spoiler_start) = spoiler {
+                // Text and background the same colour, so it reads as a solid block
+                let block = ui.visuals().widgets.inactive.bg_fill;
+                let label = egui::Label::new(rich.color(block).background_color(block)).sense(egui::Sense::click());
+                if ui.add(label).on_hover_cursor(egui::CursorIcon::PointingHand).on_hover_text("Spoiler").clicked() {
+                    clicked = Some(TextClick::Spoiler(spoiler_start));
+                }
+                continue;
+            }
+            if covering.iter().any(|e| matches!(e.kind, EntityKind::CustomEmoji(_))) {
+                // Fetching the custom emoji itself isn't supported yet, so its stand-in is marked instead
+                ui.label(rich).on_hover_text("Custom emoji");
+                continue;
+            }
+            match url {
+                Some(url) if url.starts_with("tg://") => {
+                    if ui.link(rich).on_hover_text(&url).clicked() {
+                        clicked = Some(TextClick::Link(url));
+                    }
+                }
+                Some(url) => {
+                    ui.hyperlink_to(rich, &url).on_hover_text(&url);
+                }
+                None => {
+                    ui.label(rich);
+                }
+            }
+        }
+    });
+    clicked
+}
+
+/// Finds URLs with a known scheme in plain text, minus trailing punctuation.
+fn find_urls(text: &str) -> Vec<std::ops::Range<usize>> {
+    let mut urls = Vec::new();
+    let mut word_start = None;
+    // A trailing space flushes the last word
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        if !c.is_whitespace() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        let Some(start) = word_start.take() else {
+            continue;
+        };
+        let word = &text[start..i];
+        let Some((offset, scheme)) = URL_SCHEMES
+            .iter()
+            .filter_map(|scheme| word.find(scheme).map(|offset| (offset, scheme)))
+            .min()
+        else {
+            continue;
+        };
+        let url = word[offset..].trim_end_matches(|c: char| ".,;:!?)]}'\"".contains(c));
+        if url.len() > scheme.len() {
+            urls.push(start + offset..start + offset + url.len());
+        }
+    }
+    urls
+}
+
+/// Lays out `text` with every case-insensitive occurrence of `query` highlighted.
+fn highlight_matches(text: &str, query: &str, style: &egui::Style) -> egui::text::LayoutJob {
+    let font = egui::TextStyle::Body.resolve(style);
+    let normal = egui::TextFormat {
+        font_id: font.clone(),
+        color: style.visuals.text_color(),
+        ..Default::default()
+    };
+    let highlighted = egui::TextFormat {
+        font_id: font,
+        color: egui::Color32::BLACK,
+        background: egui::Color32::from_rgb(255, 214, 0),
+        ..Default::default()
+    };
+
+    let mut job = egui::text::LayoutJob::default();
+    let lower = text.to_lowercase();
+    let needle = query.to_lowercase();
+    // Lowercasing can change byte lengths outside ASCII, in which case offsets no longer line up
+    if needle.is_empty() || lower.len() != text.len() {
+        job.append(text, 0.0, normal);
+        return job;
+    }
+    let mut last = 0;
+    for (start, _) in lower.match_indices(&needle) {
+        if start < last || !text.is_char_boundary(start) {
+            continue;
+        }
+        let end = start + needle.len();
+        job.append(&text[last..start], 0.0, normal.clone());
+        job.append(&text[start..end], 0.0, highlighted.clone());
+        last = end;
+    }
+    job.append(&text[last..], 0.0, normal);
+    job
+}
+
+/// Renders `data` as a black-on-white QR code with a quiet zone around it.
+fn qr_image(data: &str) -> Option<egui::ColorImage> {
+    const SCALE: usize = 6;
+    const QUIET_ZONE: usize = 4;
+
+    let code = qrcode::QrCode::new(data).ok()?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + QUIET_ZONE * 2) * SCALE;
+    let mut image = egui::ColorImage::new([side, side], vec![egui::Color32::WHITE; side * side]);
+    for (i, color) in colors.iter().enumerate() {
+        if *color != qrcode::Color::Dark {
+            continue;
+        }
+        let x0 = (i % modules + QUIET_ZONE) * SCALE;
+        let y0 = (i / modules + QUIET_ZONE) * SCALE;
+        for y in y0..y0 + SCALE {
+            for x in x0..x0 + SCALE {
+                image.pixels[y * side + x] = egui::Color32::BLACK;
+            }
+        }
+    }
+    Some(image)
+}
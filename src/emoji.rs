@@ -0,0 +1,342 @@
+//! Curated emoji table for the picker next to the message input.
+//!
+//! egui's bundled fonts only cover part of Unicode's emoji, and draw them in monochrome. Entries they
+//! lack show up as empty boxes until an emoji-capable font such as Noto Color Emoji is added as a
+//! fallback with `egui::Context::set_fonts`.
+
+/// `(category, name, emoji)`, grouped by category in display order.
+pub const EMOJI: &[(&str, &str, &str)] = &[
+    ("Smileys", "grinning", "😀"),
+    ("Smileys", "smiley", "😃"),
+    ("Smileys", "smile", "😄"),
+    ("Smileys", "grin", "😁"),
+    ("Smileys", "laughing", "😆"),
+    ("Smileys", "sweat smile", "😅"),
+    ("Smileys", "joy", "😂"),
+    ("Smileys", "rofl", "🤣"),
+    ("Smileys", "blush", "😊"),
+    ("Smileys", "innocent", "😇"),
+    ("Smileys", "slightly smiling", "🙂"),
+    ("Smileys", "upside down", "🙃"),
+    ("Smileys", "wink", "😉"),
+    ("Smileys", "relieved", "😌"),
+    ("Smileys", "heart eyes", "😍"),
+    ("Smileys", "smiling hearts", "🥰"),
+    ("Smileys", "kissing heart", "😘"),
+    ("Smileys", "kissing", "😗"),
+    ("Smileys", "yum", "😋"),
+    ("Smileys", "stuck out tongue", "😛"),
+    ("Smileys", "winking tongue", "😜"),
+    ("Smileys", "zany", "🤪"),
+    ("Smileys", "squinting tongue", "😝"),
+    ("Smileys", "money mouth", "🤑"),
+    ("Smileys", "hugging", "🤗"),
+    ("Smileys", "hand over mouth", "🤭"),
+    ("Smileys", "shushing", "🤫"),
+    ("Smileys", "thinking", "🤔"),
+    ("Smileys", "zipper mouth", "🤐"),
+    ("Smileys", "raised eyebrow", "🤨"),
+    ("Smileys", "neutral", "😐"),
+    ("Smileys", "expressionless", "😑"),
+    ("Smileys", "no mouth", "😶"),
+    ("Smileys", "smirk", "😏"),
+    ("Smileys", "unamused", "😒"),
+    ("Smileys", "roll eyes", "🙄"),
+    ("Smileys", "grimacing", "😬"),
+    ("Smileys", "lying", "🤥"),
+    ("Smileys", "pensive", "😔"),
+    ("Smileys", "sleepy", "😪"),
+    ("Smileys", "drooling", "🤤"),
+    ("Smileys", "sleeping", "😴"),
+    ("Smileys", "mask", "😷"),
+    ("Smileys", "thermometer", "🤒"),
+    ("Smileys", "head bandage", "🤕"),
+    ("Smileys", "nauseated", "🤢"),
+    ("Smileys", "vomiting", "🤮"),
+    ("Smileys", "sneezing", "🤧"),
+    ("Smileys", "hot", "🥵"),
+    ("Smileys", "cold", "🥶"),
+    ("Smileys", "woozy", "🥴"),
+    ("Smileys", "dizzy face", "😵"),
+    ("Smileys", "exploding head", "🤯"),
+    ("Smileys", "cowboy", "🤠"),
+    ("Smileys", "partying", "🥳"),
+    ("Smileys", "sunglasses", "😎"),
+    ("Smileys", "nerd", "🤓"),
+    ("Smileys", "monocle", "🧐"),
+    ("Smileys", "confused", "😕"),
+    ("Smileys", "worried", "😟"),
+    ("Smileys", "slightly frowning", "🙁"),
+    ("Smileys", "open mouth", "😮"),
+    ("Smileys", "hushed", "😯"),
+    ("Smileys", "astonished", "😲"),
+    ("Smileys", "flushed", "😳"),
+    ("Smileys", "pleading", "🥺"),
+    ("Smileys", "frowning", "😦"),
+    ("Smileys", "anguished", "😧"),
+    ("Smileys", "fearful", "😨"),
+    ("Smileys", "cold sweat", "😰"),
+    ("Smileys", "disappointed relieved", "😥"),
+    ("Smileys", "cry", "😢"),
+    ("Smileys", "sob", "😭"),
+    ("Smileys", "scream", "😱"),
+    ("Smileys", "confounded", "😖"),
+    ("Smileys", "persevering", "😣"),
+    ("Smileys", "disappointed", "😞"),
+    ("Smileys", "sweat", "😓"),
+    ("Smileys", "weary", "😩"),
+    ("Smileys", "tired", "😫"),
+    ("Smileys", "yawning", "🥱"),
+    ("Smileys", "triumph", "😤"),
+    ("Smileys", "rage", "😡"),
+    ("Smileys", "angry", "😠"),
+    ("Smileys", "cursing", "🤬"),
+    ("Smileys", "smiling imp", "😈"),
+    ("Smileys", "skull", "💀"),
+    ("Smileys", "poop", "💩"),
+    ("Smileys", "clown", "🤡"),
+    ("Smileys", "ghost", "👻"),
+    ("Smileys", "alien", "👽"),
+    ("Smileys", "robot", "🤖"),
+    ("Gestures", "wave", "👋"),
+    ("Gestures", "raised back of hand", "🤚"),
+    ("Gestures", "raised hand", "✋"),
+    ("Gestures", "vulcan salute", "🖖"),
+    ("Gestures", "ok hand", "👌"),
+    ("Gestures", "pinching hand", "🤏"),
+    ("Gestures", "victory", "✌"),
+    ("Gestures", "crossed fingers", "🤞"),
+    ("Gestures", "love you", "🤟"),
+    ("Gestures", "metal", "🤘"),
+    ("Gestures", "call me", "🤙"),
+    ("Gestures", "point left", "👈"),
+    ("Gestures", "point right", "👉"),
+    ("Gestures", "point up", "👆"),
+    ("Gestures", "point down", "👇"),
+    ("Gestures", "thumbs up", "👍"),
+    ("Gestures", "thumbs down", "👎"),
+    ("Gestures", "fist", "✊"),
+    ("Gestures", "punch", "👊"),
+    ("Gestures", "clap", "👏"),
+    ("Gestures", "raised hands", "🙌"),
+    ("Gestures", "open hands", "👐"),
+    ("Gestures", "palms up", "🤲"),
+    ("Gestures", "handshake", "🤝"),
+    ("Gestures", "pray", "🙏"),
+    ("Gestures", "writing hand", "✍"),
+    ("Gestures", "muscle", "💪"),
+    ("Gestures", "eyes", "👀"),
+    ("Gestures", "brain", "🧠"),
+    ("Gestures", "facepalm", "🤦"),
+    ("Gestures", "shrug", "🤷"),
+    ("Hearts", "red heart", "❤"),
+    ("Hearts", "orange heart", "🧡"),
+    ("Hearts", "yellow heart", "💛"),
+    ("Hearts", "green heart", "💚"),
+    ("Hearts", "blue heart", "💙"),
+    ("Hearts", "purple heart", "💜"),
+    ("Hearts", "black heart", "🖤"),
+    ("Hearts", "white heart", "🤍"),
+    ("Hearts", "brown heart", "🤎"),
+    ("Hearts", "broken heart", "💔"),
+    ("Hearts", "two hearts", "💕"),
+    ("Hearts", "revolving hearts", "💞"),
+    ("Hearts", "heartbeat", "💓"),
+    ("Hearts", "growing heart", "💗"),
+    ("Hearts", "sparkling heart", "💖"),
+    ("Hearts", "cupid", "💘"),
+    ("Hearts", "gift heart", "💝"),
+    ("Hearts", "kiss", "💋"),
+    ("Animals", "dog", "🐶"),
+    ("Animals", "cat", "🐱"),
+    ("Animals", "mouse", "🐭"),
+    ("Animals", "hamster", "🐹"),
+    ("Animals", "rabbit", "🐰"),
+    ("Animals", "fox", "🦊"),
+    ("Animals", "bear", "🐻"),
+    ("Animals", "panda", "🐼"),
+    ("Animals", "koala", "🐨"),
+    ("Animals", "tiger", "🐯"),
+    ("Animals", "lion", "🦁"),
+    ("Animals", "cow", "🐮"),
+    ("Animals", "pig", "🐷"),
+    ("Animals", "frog", "🐸"),
+    ("Animals", "monkey", "🐵"),
+    ("Animals", "see no evil", "🙈"),
+    ("Animals", "hear no evil", "🙉"),
+    ("Animals", "speak no evil", "🙊"),
+    ("Animals", "chicken", "🐔"),
+    ("Animals", "penguin", "🐧"),
+    ("Animals", "bird", "🐦"),
+    ("Animals", "unicorn", "🦄"),
+    ("Animals", "bee", "🐝"),
+    ("Animals", "butterfly", "🦋"),
+    ("Animals", "snail", "🐌"),
+    ("Animals", "turtle", "🐢"),
+    ("Animals", "snake", "🐍"),
+    ("Animals", "octopus", "🐙"),
+    ("Animals", "fish", "🐟"),
+    ("Animals", "dolphin", "🐬"),
+    ("Animals", "whale", "🐳"),
+    ("Animals", "shark", "🦈"),
+    ("Animals", "seedling", "🌱"),
+    ("Animals", "evergreen tree", "🌲"),
+    ("Animals", "palm tree", "🌴"),
+    ("Animals", "cactus", "🌵"),
+    ("Animals", "four leaf clover", "🍀"),
+    ("Animals", "maple leaf", "🍁"),
+    ("Animals", "rose", "🌹"),
+    ("Animals", "sunflower", "🌻"),
+    ("Animals", "tulip", "🌷"),
+    ("Food", "apple", "🍎"),
+    ("Food", "pear", "🍐"),
+    ("Food", "orange", "🍊"),
+    ("Food", "lemon", "🍋"),
+    ("Food", "banana", "🍌"),
+    ("Food", "watermelon", "🍉"),
+    ("Food", "grapes", "🍇"),
+    ("Food", "strawberry", "🍓"),
+    ("Food", "cherries", "🍒"),
+    ("Food", "peach", "🍑"),
+    ("Food", "pineapple", "🍍"),
+    ("Food", "avocado", "🥑"),
+    ("Food", "tomato", "🍅"),
+    ("Food", "hot pepper", "🌶"),
+    ("Food", "corn", "🌽"),
+    ("Food", "bread", "🍞"),
+    ("Food", "cheese", "🧀"),
+    ("Food", "egg", "🥚"),
+    ("Food", "bacon", "🥓"),
+    ("Food", "hamburger", "🍔"),
+    ("Food", "fries", "🍟"),
+    ("Food", "pizza", "🍕"),
+    ("Food", "hot dog", "🌭"),
+    ("Food", "taco", "🌮"),
+    ("Food", "burrito", "🌯"),
+    ("Food", "spaghetti", "🍝"),
+    ("Food", "sushi", "🍣"),
+    ("Food", "ramen", "🍜"),
+    ("Food", "ice cream", "🍦"),
+    ("Food", "doughnut", "🍩"),
+    ("Food", "cookie", "🍪"),
+    ("Food", "birthday cake", "🎂"),
+    ("Food", "chocolate bar", "🍫"),
+    ("Food", "popcorn", "🍿"),
+    ("Food", "coffee", "☕"),
+    ("Food", "tea", "🍵"),
+    ("Food", "beer", "🍺"),
+    ("Food", "cheers", "🍻"),
+    ("Food", "wine", "🍷"),
+    ("Food", "cocktail", "🍸"),
+    ("Activities", "soccer", "⚽"),
+    ("Activities", "basketball", "🏀"),
+    ("Activities", "football", "🏈"),
+    ("Activities", "baseball", "⚾"),
+    ("Activities", "tennis", "🎾"),
+    ("Activities", "volleyball", "🏐"),
+    ("Activities", "ping pong", "🏓"),
+    ("Activities", "trophy", "🏆"),
+    ("Activities", "medal", "🏅"),
+    ("Activities", "dart", "🎯"),
+    ("Activities", "video game", "🎮"),
+    ("Activities", "game die", "🎲"),
+    ("Activities", "jigsaw", "🧩"),
+    ("Activities", "guitar", "🎸"),
+    ("Activities", "microphone", "🎤"),
+    ("Activities", "headphones", "🎧"),
+    ("Activities", "art", "🎨"),
+    ("Activities", "movie camera", "🎥"),
+    ("Activities", "tada", "🎉"),
+    ("Activities", "confetti ball", "🎊"),
+    ("Activities", "balloon", "🎈"),
+    ("Activities", "gift", "🎁"),
+    ("Activities", "christmas tree", "🎄"),
+    ("Activities", "jack o lantern", "🎃"),
+    ("Travel", "car", "🚗"),
+    ("Travel", "taxi", "🚕"),
+    ("Travel", "bus", "🚌"),
+    ("Travel", "bike", "🚲"),
+    ("Travel", "train", "🚆"),
+    ("Travel", "airplane", "✈"),
+    ("Travel", "rocket", "🚀"),
+    ("Travel", "ship", "🚢"),
+    ("Travel", "house", "🏠"),
+    ("Travel", "office", "🏢"),
+    ("Travel", "hospital", "🏥"),
+    ("Travel", "school", "🏫"),
+    ("Travel", "earth", "🌍"),
+    ("Travel", "mountain", "⛰"),
+    ("Travel", "beach", "🏖"),
+    ("Travel", "sun", "☀"),
+    ("Travel", "cloud", "☁"),
+    ("Travel", "rain", "🌧"),
+    ("Travel", "snowflake", "❄"),
+    ("Travel", "rainbow", "🌈"),
+    ("Travel", "zap", "⚡"),
+    ("Travel", "fire", "🔥"),
+    ("Travel", "droplet", "💧"),
+    ("Travel", "moon", "🌙"),
+    ("Travel", "star", "⭐"),
+    ("Travel", "sparkles", "✨"),
+    ("Objects", "phone", "📱"),
+    ("Objects", "laptop", "💻"),
+    ("Objects", "keyboard", "⌨"),
+    ("Objects", "camera", "📷"),
+    ("Objects", "tv", "📺"),
+    ("Objects", "watch", "⌚"),
+    ("Objects", "hourglass", "⌛"),
+    ("Objects", "alarm clock", "⏰"),
+    ("Objects", "battery", "🔋"),
+    ("Objects", "bulb", "💡"),
+    ("Objects", "flashlight", "🔦"),
+    ("Objects", "money bag", "💰"),
+    ("Objects", "credit card", "💳"),
+    ("Objects", "gem", "💎"),
+    ("Objects", "wrench", "🔧"),
+    ("Objects", "hammer", "🔨"),
+    ("Objects", "gear", "⚙"),
+    ("Objects", "lock", "🔒"),
+    ("Objects", "key", "🔑"),
+    ("Objects", "bell", "🔔"),
+    ("Objects", "envelope", "✉"),
+    ("Objects", "package", "📦"),
+    ("Objects", "memo", "📝"),
+    ("Objects", "book", "📖"),
+    ("Objects", "pencil", "✏"),
+    ("Objects", "paperclip", "📎"),
+    ("Objects", "pushpin", "📌"),
+    ("Objects", "scissors", "✂"),
+    ("Objects", "calendar", "📅"),
+    ("Objects", "chart", "📈"),
+    ("Objects", "mag", "🔍"),
+    ("Objects", "pill", "💊"),
+    ("Symbols", "check mark", "✅"),
+    ("Symbols", "heavy check", "✔"),
+    ("Symbols", "cross mark", "❌"),
+    ("Symbols", "warning", "⚠"),
+    ("Symbols", "no entry", "⛔"),
+    ("Symbols", "question", "❓"),
+    ("Symbols", "exclamation", "❗"),
+    ("Symbols", "hundred", "💯"),
+    ("Symbols", "recycle", "♻"),
+    ("Symbols", "infinity", "♾"),
+    ("Symbols", "copyright", "©"),
+    ("Symbols", "registered", "®"),
+    ("Symbols", "trade mark", "™"),
+    ("Symbols", "arrow up", "⬆"),
+    ("Symbols", "arrow down", "⬇"),
+    ("Symbols", "arrow left", "⬅"),
+    ("Symbols", "arrow right", "➡"),
+    ("Symbols", "repeat", "🔁"),
+    ("Symbols", "play", "▶"),
+    ("Symbols", "pause", "⏸"),
+    ("Symbols", "red circle", "🔴"),
+    ("Symbols", "green circle", "🟢"),
+    ("Symbols", "blue circle", "🔵"),
+    ("Symbols", "music", "🎵"),
+    ("Symbols", "zzz", "💤"),
+    ("Symbols", "speech balloon", "💬"),
+    ("Symbols", "new", "🆕"),
+    ("Symbols", "free", "🆓"),
+    ("Symbols", "sos", "🆘"),
+];
@@ -0,0 +1,68 @@
+use eframe::egui;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Fonts covering Chinese, Japanese and Korean, first one found wins.
+const CJK_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+];
+/// Emoji fonts with outlines, egui can't draw the bitmap ones like Noto Color Emoji or Apple Color Emoji.
+const EMOJI_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/noto/NotoEmoji-Regular.ttf",
+    "/usr/share/fonts/noto/NotoEmoji-Regular.ttf",
+    "/usr/share/fonts/google-noto-emoji/NotoEmoji-Regular.ttf",
+    "C:\\Windows\\Fonts\\seguiemj.ttf",
+];
+
+/// Sets up egui's fonts with `custom` first and any CJK and emoji fonts on the system as fallbacks.
+///
+/// Only a problem with `custom` is an error, the built-in fonts are used without it.
+pub fn install(ctx: &egui::Context, custom: Option<&Path>) -> Result<(), String> {
+    let mut fonts = egui::FontDefinitions::default();
+    let mut result = Ok(());
+    if let Some(path) = custom {
+        match load(path) {
+            Ok(data) => {
+                add(&mut fonts, "custom", data);
+                // Ahead of the default font, so it's the one text is drawn in
+                for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+                    let names = fonts.families.entry(family).or_default();
+                    names.retain(|name| name != "custom");
+                    names.insert(0, "custom".to_string());
+                }
+            }
+            Err(e) => result = Err(e),
+        }
+    }
+    for (name, candidates) in [("cjk", CJK_FONTS), ("emoji", EMOJI_FONTS)] {
+        match candidates.iter().find_map(|path| load(Path::new(path)).ok()) {
+            Some(data) => add(&mut fonts, name, data),
+            None => log::info!("No {} font found on this system", name),
+        }
+    }
+    ctx.set_fonts(fonts);
+    result
+}
+
+/// Registers `data` as the last fallback of both font families.
+fn add(fonts: &mut egui::FontDefinitions, name: &str, data: Vec<u8>) {
+    fonts.font_data.insert(name.to_string(), Arc::new(egui::FontData::from_owned(data)));
+    for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+        fonts.families.entry(family).or_default().push(name.to_string());
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    // egui panics on fonts it can't parse, so they're parsed here first the same way egui will.
+    // That's the first face for collections too, which is the one egui loads.
+    match ab_glyph::FontRef::try_from_slice_and_index(&data, 0) {
+        Ok(_) => Ok(data),
+        Err(e) => Err(format!("{} is not a usable TrueType or OpenType font: {}", path.display(), e)),
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Local};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Oldest lines are dropped past this, enough to cover a session worth reporting.
+const MAX_LINES: usize = 5000;
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for LogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:<5} [{}] {}", self.time.format("%H:%M:%S%.3f"), self.level, self.target, self.message)
+    }
+}
+
+/// The most recent log lines, shared between the logger and the log panel.
+pub type LogBuffer = Arc<Mutex<VecDeque<LogLine>>>;
+
+/// Prints like `SimpleLogger` and keeps a copy of each line for the log panel.
+struct BufferedLogger {
+    inner: SimpleLogger,
+    buffer: LogBuffer,
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+        let line = LogLine {
+            time: Local::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        // A panic while holding the lock shouldn't take logging down with it
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.len() == MAX_LINES {
+            buffer.pop_front();
+        }
+        buffer.push_back(line);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the global logger, returning the buffer its lines are collected in.
+pub fn init() -> LogBuffer {
+    let inner = SimpleLogger::new().with_level(LevelFilter::Debug);
+    let buffer = LogBuffer::default();
+    log::set_max_level(inner.max_level());
+    let logger = Box::leak(Box::new(BufferedLogger { inner, buffer: buffer.clone() }));
+    log::set_logger(logger).unwrap();
+    buffer
+}
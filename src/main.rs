@@ -1,24 +1,105 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod animation;
 mod app;
-use app::{TelegramApp, GuiAction, BackendEvent, ChatInfo, MessageInfo};
-use grammers_client::{Client, SignInError};
-use grammers_mtsender::SenderPool;
+mod config;
+mod emoji;
+mod fonts;
+mod logs;
+mod outbox;
+mod tray;
+mod voice;
+use config::{Config, ProxyConfig, DEFAULT_WINDOW_SIZE};
+use tray::NativeWindow;
+use app::{TelegramApp, GuiAction, BackendEvent, BackendError, ErrorKind, ExportFormat, ChatInfo, ContactInfo, FolderInfo, MemberInfo, Presence, MessageInfo, Reaction, ScheduledMessage, MediaInfo, PollInfo, PollOption, WebpageInfo, TextEntity, EntityKind, format_timestamp, mask_spoilers};
+use base64::Engine;
+use grammers_client::{Client, InputMessage, InvocationError, PeerMap, SignInError, UpdatesConfiguration};
+use grammers_client::client::dialogs::DialogIter;
+use grammers_client::grammers_tl_types as tl;
+use grammers_mtsender::{ConnectionParams, SenderPool, SenderPoolHandle};
+use grammers_session::Session;
+use grammers_session::storages::SqliteSession;
+use grammers_session::defs::{PeerAuth, PeerId, PeerInfo, PeerRef, UpdateState, UpdatesState};
+use grammers_session::updates::{State, UpdatesLike};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
-use simple_logger::SimpleLogger;
 
-use grammers_client::types::Peer;
+use grammers_client::types::{Attribute, ChatPhoto, Downloadable, Media, Message, Participant, PasswordToken, Peer, Photo, Role, User};
 
 struct BackgroundState {
     api_hash: String,
-    login_token: Option<grammers_client::types::LoginToken>,
+    login_token: Option<CodeLogin>,
     password_token: Option<grammers_client::types::PasswordToken>,
     chat_map: std::collections::HashMap<String, Peer>,
+    /// Senders of loaded messages, kept so their avatars can be downloaded.
+    senders: std::collections::HashMap<String, Peer>,
+    message_limit: usize,
+    qr_login: bool,
+    qr_url: Option<String>,
+    is_bot: bool,
+    me: Option<User>,
+    /// The document being uploaded, kept so the upload can be cancelled.
+    upload: Option<tokio::task::AbortHandle>,
+    /// The chat being exported, kept so the export can be cancelled.
+    export: Option<tokio::task::AbortHandle>,
+    /// The main chat list, kept between pages so "Load more chats" picks up where it stopped.
+    dialogs: Option<DialogIter>,
+    /// The chat folders, matched again whenever another page of chats arrives.
+    folders: Vec<tl::enums::DialogFilter>,
 }
 
+impl BackgroundState {
+    fn new(api_hash: String, message_limit: usize) -> Self {
+        Self {
+            api_hash,
+            login_token: None,
+            password_token: None,
+            chat_map: std::collections::HashMap::new(),
+            senders: std::collections::HashMap::new(),
+            message_limit,
+            qr_login: false,
+            qr_url: None,
+            is_bot: false,
+            me: None,
+            upload: None,
+            export: None,
+            dialogs: None,
+            folders: Vec::new(),
+        }
+    }
+}
+
+const DEFAULT_MESSAGE_LIMIT: usize = 50;
+/// Where the only session lived before multiple accounts were supported.
+const LEGACY_SESSION_FILE: &str = "session.session";
+/// Subdirectory of the data directory holding one session file per account.
+const SESSION_DIR: &str = "sessions";
+const MEDIA_CACHE_DIR: &str = "media_cache";
+const AVATAR_CACHE_DIR: &str = "avatar_cache";
+const MEMBER_PAGE_SIZE: usize = 200;
+const CHAT_PAGE_SIZE: usize = 50;
+/// How many exported messages pass between progress reports.
+const EXPORT_PROGRESS_STEP: usize = 100;
+/// Animated stickers are Lottie (TGS) or WEBM files, only these decode as an image.
+const STATIC_STICKER_MIME: &str = "image/webp";
+const QR_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Telegram asks some numbers to pay for the login code, which only the official apps support.
+const PAYMENT_REQUIRED: &str = "Telegram requires a payment to send a login code to this number, use an official app";
+/// How long closing the window waits for the update state to be saved.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+const PROXY_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Telegram's archive is dialog folder 1, the main chat list is folder 0.
+const ARCHIVE_FOLDER_ID: i32 = 1;
+const MAIN_FOLDER_ID: i32 = 0;
+/// Telegram's datacenters, each of which the session keeps its own authorization key for.
+const PRIMARY_DC_IDS: std::ops::RangeInclusive<i32> = 1..=5;
+
 fn main() -> eframe::Result<()> {
-    SimpleLogger::new().with_level(log::LevelFilter::Debug).init().unwrap();
+    let logs = logs::init();
     
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -30,91 +111,346 @@ fn main() -> eframe::Result<()> {
     let (gui_tx, gui_rx) = mpsc::channel(100);
     let (bg_tx, bg_rx) = mpsc::channel(100);
 
+    let session_dir = session_dir_arg().unwrap_or_else(default_session_dir);
+    log::info!("Using session directory {}", session_dir.display());
+
     // Spawn background task
     rt.spawn(async move {
-        background_loop(bg_tx, gui_rx).await;
+        background_loop(bg_tx, gui_rx, session_dir).await;
     });
 
+    let config = Config::load();
+    let mut viewport = eframe::egui::ViewportBuilder::default()
+        .with_inner_size(config.window.map_or(DEFAULT_WINDOW_SIZE, |window| window.size));
+    if let Some(position) = config.window.and_then(|window| window.position) {
+        viewport = viewport.with_position(position);
+    }
     let options = eframe::NativeOptions {
-        viewport: eframe::egui::ViewportBuilder::default()
-            .with_inner_size([400.0, 600.0]),
+        viewport,
         ..Default::default()
     };
 
-    eframe::run_native(
-        "Telegram Rust Client",
+    let result = eframe::run_native(
+        app::APP_TITLE,
         options,
-        Box::new(|_cc| Ok(Box::new(TelegramApp::new(gui_tx, bg_rx)))),
-    )
+        Box::new(|cc| Ok(Box::new(TelegramApp::new(&cc.egui_ctx, NativeWindow::new(cc), config, gui_tx, bg_rx, logs)))),
+    );
+    // Give tasks still writing to disk a moment instead of cutting them off mid-write
+    drop(_enter);
+    rt.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    result
+}
+
+/// Reads the `--session <dir>` flag, a directory to keep the session files in instead of the default.
+fn session_dir_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--session" {
+            return args.next().map(PathBuf::from);
+        }
+    }
+    None
+}
+
+fn default_session_dir() -> PathBuf {
+    directories::ProjectDirs::from("", "", "telegram_client")
+        .map_or_else(|| PathBuf::from("."), |dirs| dirs.data_dir().join(SESSION_DIR))
+}
+
+/// A folder under the user's cache directory, or the working directory if there's none.
+fn cache_dir(name: &str) -> PathBuf {
+    directories::ProjectDirs::from("", "", "telegram_client")
+        .map_or_else(|| PathBuf::from(name), |dirs| dirs.cache_dir().join(name))
 }
 
-async fn background_loop(tx: mpsc::Sender<BackendEvent>, mut rx: mpsc::Receiver<GuiAction>) {
+async fn background_loop(tx: mpsc::Sender<BackendEvent>, mut rx: mpsc::Receiver<GuiAction>, session_dir: PathBuf) {
     // 1. Wait for configuration
-    let (api_id, api_hash) = loop {
+    let mut active = true;
+    let (mut api_id, api_hash, mut account, mut proxy, live) = loop {
         match rx.recv().await {
-             Some(GuiAction::Configure { api_id, api_hash }) => break (api_id, api_hash),
+             Some(GuiAction::Configure { api_id, api_hash, account, proxy, live_updates }) => {
+                 break (api_id, api_hash, account, proxy, live_updates);
+             }
+             Some(GuiAction::Shutdown) => {
+                 let _ = tx.send(BackendEvent::ShutdownComplete).await;
+                 return;
+             }
+             // The proxy can be tested before it's saved along with the API credentials
+             Some(GuiAction::TestProxy { host, port }) => spawn_proxy_test(tx.clone(), host, port),
+             // The window can lose focus before the first connection
+             Some(GuiAction::SetActive(now_active)) => active = now_active,
              Some(_) => {
-                 let _ = tx.send(BackendEvent::Error("Please configure API ID first".to_string())).await;
+                 let _ = tx.send(BackendEvent::Error("Please configure API ID first".into())).await;
              }
              None => return,
         }
     };
 
     // 2. Initialize Client
-    let session_path = "session.session";
-    let session = grammers_session::storages::SqliteSession::open(session_path).unwrap();
-    let session = Arc::new(session);
-    let pool = SenderPool::new(session.clone(), api_id);
-    let client = Client::new(&pool);
-    let _pool_handle = pool.handle.clone();
-    
-    tokio::spawn(async move {
-        pool.runner.run().await
-    });
-
-    let mut state = BackgroundState {
-        api_hash: api_hash.clone(),
-        login_token: None,
-        password_token: None,
-        chat_map: std::collections::HashMap::new(),
+    let mut session = open_session_or_memory(&tx, &session_dir, account).await;
+    let (ended_tx, mut conn_rx) = mpsc::unbounded_channel();
+    let (updates_tx, mut update_rx) = mpsc::unbounded_channel();
+    let (synced_tx, mut synced_rx) = mpsc::unbounded_channel();
+    let (probed_tx, mut probed_rx) = mpsc::unbounded_channel();
+    let (lost_tx, mut lost_rx) = mpsc::unbounded_channel();
+    let (proxy_checked_tx, mut proxy_checked_rx) = mpsc::unbounded_channel();
+    // Only the latest proxy asked for is applied, earlier checks finishing late are ignored
+    let mut proxy_check = 0;
+    let mut conn_events = ConnectionEvents {
+        ended: ended_tx,
+        updates: updates_tx,
+        synced: synced_tx,
+        probed: probed_tx,
+        lost: lost_tx,
+        active,
+        live,
     };
+    let mut generation = 0;
+    let (mut client, mut pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
 
-    let _ = tx.send(BackendEvent::Configured).await;
+    let mut state = BackgroundState::new(api_hash, DEFAULT_MESSAGE_LIMIT);
+    let mut reconnect: Option<Reconnect> = None;
 
-    if let Ok(true) = client.is_authorized().await {
-        let _ = tx.send(BackendEvent::LoggedIn).await;
+    match report_connection(&client, &tx, &mut state, proxy.as_ref()).await {
+        ConnectionCheck::Ready => {}
+        // Stay offline until the GUI asks to retry, a wrong setting won't fix itself
+        ConnectionCheck::Failed => {
+            pool_handle.quit();
+            generation += 1;
+        }
+        ConnectionCheck::Unreachable => {
+            pool_handle.quit();
+            generation += 1;
+            reconnect = Some(Reconnect::configuring());
+        }
     }
 
     // 3. Main Loop
-    while let Some(action) = rx.recv().await {
+    let mut qr_poll = tokio::time::interval(QR_POLL_INTERVAL);
+    qr_poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        let next_attempt = reconnect.as_ref().and_then(|reconnect| reconnect.at);
+        let action = tokio::select! {
+            action = rx.recv() => match action {
+                Some(action) => action,
+                None => break,
+            },
+            // Re-export the QR token while it's shown, which both refreshes expired tokens and detects scans
+            _ = qr_poll.tick(), if state.qr_login => GuiAction::LoginQr,
+            Some(ended) = conn_rx.recv() => {
+                // Runners from pools we already replaced also end, only the current one matters
+                if ended != generation {
+                    continue;
+                }
+                generation += 1;
+                match &mut reconnect {
+                    // The attempt being checked died before it answered
+                    Some(reconnect) => reconnect.retry_later(),
+                    None => {
+                        let _ = tx.send(BackendEvent::Disconnected).await;
+                        reconnect = Some(Reconnect::new());
+                    }
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(next_attempt.unwrap_or_else(tokio::time::Instant::now)), if next_attempt.is_some() => {
+                if let Some(reconnect) = &mut reconnect {
+                    reconnect.at = None;
+                }
+                let _ = tx.send(BackendEvent::Reconnecting).await;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+                spawn_probe(client.clone(), generation, &conn_events);
+                continue;
+            }
+            Some((probed, result)) = probed_rx.recv() => {
+                // Answers for connections that were replaced meanwhile don't say anything about the current one
+                let Some(configuring) = reconnect.as_ref().map(|reconnect| reconnect.configuring) else {
+                    continue;
+                };
+                if probed != generation {
+                    continue;
+                }
+                match result {
+                    Ok(authorized) if configuring => {
+                        reconnect = None;
+                        report_configured(&client, &tx, &mut state, authorized).await;
+                    }
+                    Ok(_) => {
+                        reconnect = None;
+                        let _ = tx.send(BackendEvent::Reconnected).await;
+                    }
+                    // Only an unreachable Telegram is worth waiting for, anything else means the settings are wrong
+                    Err(e) if configuring && error_kind(&e) != ErrorKind::Network => {
+                        pool_handle.quit();
+                        generation += 1;
+                        reconnect = None;
+                        let _ = tx.send(BackendEvent::ConnectionFailed(connection_error(&e, proxy.as_ref()))).await;
+                    }
+                    Err(e) => {
+                        pool_handle.quit();
+                        generation += 1;
+                        if let Some(reconnect) = &mut reconnect {
+                            log::warn!("Reconnect failed, retrying in {:?}: {}", reconnect.delay, e);
+                            reconnect.retry_later();
+                        }
+                    }
+                }
+                continue;
+            }
+            Some((update_generation, (update, _, peers))) = update_rx.recv() => {
+                // Updates still queued from a replaced connection may belong to another account
+                if update_generation == generation {
+                    handle_update(&client, &state, update, &peers, &tx).await;
+                }
+                continue;
+            }
+            Some(lost) = lost_rx.recv() => {
+                if lost == generation {
+                    let _ = tx.send(BackendEvent::SessionExpired).await;
+                }
+                continue;
+            }
+            Some((check, checked_proxy, result)) = proxy_checked_rx.recv() => {
+                if check != proxy_check {
+                    continue;
+                }
+                match result {
+                    Ok(()) => {
+                        proxy = checked_proxy;
+                        pool_handle.quit();
+                        generation += 1;
+                        // Connecting goes through the usual retries, the proxy is known to work by now
+                        match &mut reconnect {
+                            Some(reconnect) => reconnect.at = Some(tokio::time::Instant::now()),
+                            None => reconnect = Some(Reconnect::new()),
+                        }
+                        let _ = tx.send(BackendEvent::ProxyApplied(Ok(proxy.clone()))).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(BackendEvent::ProxyApplied(Err(e))).await;
+                    }
+                }
+                continue;
+            }
+        };
         match action {
             GuiAction::Login(phone) => {
-                 match client.request_login_code(&phone, &state.api_hash).await {
-                     Ok(token) => {
-                         state.login_token = Some(token);
-                         let _ = tx.send(BackendEvent::CodeSent).await;
+                 match send_login_code(&client, &session, &pool_handle, &phone, api_id, &state.api_hash).await {
+                     Ok(sent) => {
+                         let next_method = sent.next_type.as_ref().map(|t| code_type_name(t).to_string());
+                         state.login_token = Some(CodeLogin { phone, phone_code_hash: sent.phone_code_hash });
+                         let _ = tx.send(BackendEvent::CodeSent { next_method }).await;
                      }
                      Err(e) => {
-                         let _ = tx.send(BackendEvent::Error(e.to_string())).await;
+                         let _ = tx.send(login_error(e)).await;
                      }
                  }
             }
+            GuiAction::LoginQr => {
+                if !state.qr_login {
+                    state.qr_login = true;
+                    qr_poll.reset();
+                }
+                match poll_qr_login(&client, &session, &pool_handle, api_id, &state.api_hash).await {
+                    Ok(LoginStatus::Pending(url)) => {
+                        if state.qr_url.as_ref() != Some(&url) {
+                            state.qr_url = Some(url.clone());
+                            let _ = tx.send(BackendEvent::QrToken(url)).await;
+                        }
+                    }
+                    Ok(LoginStatus::LoggedIn) => {
+                        state.qr_login = false;
+                        state.qr_url = None;
+                        let _ = tx.send(BackendEvent::LoggedIn).await;
+                    }
+                    Ok(LoginStatus::PasswordRequired(ptoken)) => {
+                        state.qr_login = false;
+                        state.qr_url = None;
+                        let hint = ptoken.hint().map(|hint| hint.to_string());
+                        state.password_token = Some(*ptoken);
+                        let _ = tx.send(BackendEvent::PasswordRequired(hint)).await;
+                    }
+                    Err(e) => {
+                        state.qr_login = false;
+                        state.qr_url = None;
+                        let _ = tx.send(BackendEvent::Error(format!("QR login failed: {}", e).into())).await;
+                    }
+                }
+            }
+            GuiAction::CancelQrLogin => {
+                state.qr_login = false;
+                state.qr_url = None;
+            }
+            GuiAction::LoginBot(token) => {
+                match client.bot_sign_in(&token, &state.api_hash).await {
+                    Ok(_) => {
+                        state.is_bot = true;
+                        let _ = tx.send(BackendEvent::LoggedIn).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, e.to_string())).await;
+                    }
+                }
+            }
             GuiAction::SendCode(code) => {
-                if let Some(token) = &state.login_token {
-                    match client.sign_in(token, &code).await {
+                if let Some(login) = &state.login_token {
+                    match sign_in_with_code(&client, &session, login, &code).await {
+                        Ok(LoginStatus::PasswordRequired(ptoken)) => {
+                            state.login_token = None;
+                            let hint = ptoken.hint().map(|hint| hint.to_string());
+                            state.password_token = Some(*ptoken);
+                            let _ = tx.send(BackendEvent::PasswordRequired(hint)).await;
+                        }
                         Ok(_) => {
-                             let _ = tx.send(BackendEvent::LoggedIn).await;
+                            state.login_token = None;
+                            let _ = tx.send(BackendEvent::LoggedIn).await;
                         }
-                        Err(SignInError::PasswordRequired(ptoken)) => {
-                            state.password_token = Some(ptoken);
-                            let _ = tx.send(BackendEvent::PasswordRequired).await;
+                        Err(e) => {
+                            let _ = tx.send(login_error(e)).await;
                         }
-                         Err(e) => {
-                             let _ = tx.send(BackendEvent::Error(e.to_string())).await;
-                         }
                     }
                 } else {
-                    let _ = tx.send(BackendEvent::Error("No login token found".to_string())).await;
+                    let _ = tx.send(BackendEvent::Error("No login token found".into())).await;
+                }
+            }
+            GuiAction::ResendCode => {
+                let Some(login) = &mut state.login_token else {
+                    let _ = tx.send(BackendEvent::Error("No login token found".into())).await;
+                    continue;
+                };
+                let request = tl::functions::auth::ResendCode {
+                    phone_number: login.phone.clone(),
+                    phone_code_hash: login.phone_code_hash.clone(),
+                    reason: None,
+                };
+                match client.invoke(&request).await {
+                    Ok(tl::enums::auth::SentCode::Code(sent)) => {
+                        login.phone_code_hash = sent.phone_code_hash;
+                        let next_method = sent.next_type.as_ref().map(|t| code_type_name(t).to_string());
+                        let _ = tx.send(BackendEvent::CodeSent { next_method }).await;
+                    }
+                    Ok(tl::enums::auth::SentCode::Success(_)) => {
+                        let _ = tx.send(BackendEvent::Error("Unexpected login without a code".into())).await;
+                    }
+                    Ok(tl::enums::auth::SentCode::PaymentRequired(_)) => {
+                        let _ = tx.send(BackendEvent::Error(PAYMENT_REQUIRED.into())).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to resend code: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::CancelLogin => {
+                state.password_token = None;
+                // Invalidates the code on Telegram's side too, so it can't be used to finish this login later
+                if let Some(login) = state.login_token.take() {
+                    let request = tl::functions::auth::CancelCode {
+                        phone_number: login.phone,
+                        phone_code_hash: login.phone_code_hash,
+                    };
+                    if let Err(e) = client.invoke(&request).await {
+                        log::warn!("Failed to cancel login code: {}", e);
+                    }
                 }
             }
             GuiAction::SendPassword(password) => {
@@ -124,90 +460,3079 @@ async fn background_loop(tx: mpsc::Sender<BackendEvent>, mut rx: mpsc::Receiver<
                              let _ = tx.send(BackendEvent::LoggedIn).await;
                          }
                          Err(e) => {
-                             let _ = tx.send(BackendEvent::Error(e.to_string())).await;
+                             let _ = tx.send(sign_in_error(e)).await;
+                             // The token was used up by the attempt, another one is needed to try again
+                             match password_token(&client).await {
+                                 Ok(ptoken) => state.password_token = Some(ptoken),
+                                 Err(e) => log::warn!("Failed to get a new password token: {}", e),
+                             }
                          }
                      }
                  } else {
-                     let _ = tx.send(BackendEvent::Error("No password token found".to_string())).await;
+                     let _ = tx.send(BackendEvent::Error("No password token found".into())).await;
                  }
             }
+            GuiAction::FetchMe => {
+                match client.get_me().await {
+                    Ok(me) => {
+                        // Saved Messages is the chat with ourselves, opened like any other
+                        let id = PeerId::user(me.bare_id()).to_string();
+                        state.chat_map.insert(id.clone(), Peer::User(me.clone()));
+                        let _ = tx.send(BackendEvent::MeLoaded {
+                            id,
+                            name: me.full_name(),
+                            username: me.username().map(str::to_string),
+                            phone: me.phone().map(str::to_string),
+                        }).await;
+                        state.me = Some(me);
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to load profile: {}", e))).await;
+                    }
+                }
+            }
             GuiAction::RefreshChats => {
+                // Bots aren't allowed to call messages.getDialogs
+                if state.is_bot {
+                    let _ = tx.send(BackendEvent::ChatsLoaded(Vec::new())).await;
+                    continue;
+                }
+                let _ = tx.send(BackendEvent::ChatsLoading).await;
+                state.dialogs = Some(client.iter_dialogs());
+                load_chat_page(&tx, &mut state).await;
+            }
+            GuiAction::LoadMoreChats => {
+                if state.is_bot {
+                    continue;
+                }
+                load_chat_page(&tx, &mut state).await;
+            }
+            GuiAction::OpenUsername(query) => match resolve_chat(&client, &query).await {
+                Ok(Some(peer)) => {
+                    let info = chat_info(&peer);
+                    state.chat_map.insert(info.id.clone(), peer);
+                    let _ = tx.send(BackendEvent::ChatOpened(info)).await;
+                }
+                Ok(None) => {
+                    let _ = tx.send(BackendEvent::Error(format!("No one uses {}", query).into())).await;
+                }
+                Err(InvocationError::Rpc(rpc)) if matches!(rpc.name.as_str(), "USERNAME_NOT_OCCUPIED" | "PHONE_NOT_OCCUPIED") => {
+                    let _ = tx.send(BackendEvent::Error(format!("No one uses {}", query).into())).await;
+                }
+                Err(InvocationError::Rpc(rpc)) if rpc.name == "USERNAME_INVALID" => {
+                    let _ = tx.send(BackendEvent::Error(format!("{} isn't a valid username", query).into())).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(flood_wait_or(&e, format!("Failed to look up {}: {}", query, e))).await;
+                }
+            },
+            GuiAction::LoadArchivedChats => {
+                if state.is_bot {
+                    let _ = tx.send(BackendEvent::ChatsLoaded(Vec::new())).await;
+                    continue;
+                }
+                // iter_dialogs only walks the main list, so the archive is fetched directly
+                let request = tl::functions::messages::GetDialogs {
+                    exclude_pinned: false,
+                    folder_id: Some(ARCHIVE_FOLDER_ID),
+                    offset_date: 0,
+                    offset_id: 0,
+                    offset_peer: tl::enums::InputPeer::Empty,
+                    limit: 50,
+                    hash: 0,
+                };
+                let (dialogs, messages, users, chats) = match client.invoke(&request).await {
+                    Ok(tl::enums::messages::Dialogs::Dialogs(d)) => (d.dialogs, d.messages, d.users, d.chats),
+                    Ok(tl::enums::messages::Dialogs::Slice(d)) => (d.dialogs, d.messages, d.users, d.chats),
+                    Ok(tl::enums::messages::Dialogs::NotModified(_)) => Default::default(),
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to load archived chats: {}", e))).await;
+                        continue;
+                    }
+                };
+                let peers = PeerMap::new(users, chats);
                 let mut chat_infos = Vec::new();
-                let mut dialogs = client.iter_dialogs();
-                while let Ok(Some(dialog)) = dialogs.next().await {
-                    let chat = dialog.peer();
-                    let name = chat.name().unwrap_or("Unknown").to_string();
-                    let id = chat.id().to_string();
-                    
-                    state.chat_map.insert(id.clone(), chat.clone());
-                    
+                for dialog in dialogs {
+                    let tl::enums::Dialog::Dialog(dialog) = dialog else {
+                        continue;
+                    };
+                    let Some(chat) = peers.get(dialog.peer.into()) else {
+                        continue;
+                    };
+                    let last = messages.iter().find_map(|message| match message {
+                        tl::enums::Message::Message(message)
+                            if message.id == dialog.top_message && PeerId::from(message.peer_id.clone()) == chat.id() =>
+                        {
+                            Some(message)
+                        }
+                        _ => None,
+                    });
+                    let (last_message, last_message_date) = match last {
+                        Some(message) => {
+                            let date = chrono::DateTime::from_timestamp(message.date as i64, 0).unwrap_or_default();
+                            let text = if message.message.is_empty() && message.media.is_some() {
+                                "Media".to_string()
+                            } else {
+                                let entities = text_entities(&message.message, message.entities.as_deref().unwrap_or_default());
+                                mask_spoilers(&message.message, &entities)
+                            };
+                            (text, format_timestamp(date))
+                        }
+                        None => (String::new(), String::new()),
+                    };
+                    state.chat_map.insert(chat.id().to_string(), chat.clone());
                     chat_infos.push(ChatInfo {
-                        name,
-                        id,
+                        unread_count: dialog.unread_count,
+                        read_inbox_max_id: dialog.read_inbox_max_id,
+                        read_outbox_max_id: dialog.read_outbox_max_id,
+                        last_message,
+                        last_message_date,
+                        muted: is_muted(&dialog.notify_settings),
+                        mute_known: true,
+                        draft: draft_text(dialog.draft.as_ref()),
+                        pinned: dialog.pinned,
+                        ..chat_info(chat)
                     });
-                    
-                    if chat_infos.len() >= 50 { break; }
                 }
                 let _ = tx.send(BackendEvent::ChatsLoaded(chat_infos)).await;
             }
-            GuiAction::SelectChat(chat_id) => {
+            GuiAction::ArchiveChat(chat_id) => set_archived(&client, &tx, &state, chat_id, true).await,
+            GuiAction::UnarchiveChat(chat_id) => set_archived(&client, &tx, &state, chat_id, false).await,
+            GuiAction::LoadFolders => {
+                // Bots have no dialog filters
+                if state.is_bot {
+                    continue;
+                }
+                match client.invoke(&tl::functions::messages::GetDialogFilters {}).await {
+                    Ok(tl::enums::messages::DialogFilters::Filters(filters)) => {
+                        state.folders = filters.filters;
+                        send_folders(&tx, &state).await;
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to load chat folders: {}", e);
+                    }
+                }
+            }
+            GuiAction::SearchChats(query) => {
+                // contacts.search isn't available to bots either
+                if state.is_bot {
+                    continue;
+                }
+                match search_chats(&client, &mut state, query).await {
+                    Ok(chats) => {
+                        let _ = tx.send(BackendEvent::ChatsLoaded(chats)).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to search chats: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::SearchForwardTargets(query) => {
+                if state.is_bot {
+                    continue;
+                }
+                match search_chats(&client, &mut state, query.clone()).await {
+                    Ok(chats) => {
+                        let _ = tx.send(BackendEvent::ForwardTargetsFound { query, chats }).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to search chats: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::OpenDeepLink(link) => match resolve_deep_link(&client, &state.chat_map, &link).await {
+                Ok(peer) => {
+                    let info = chat_info(&peer);
+                    state.chat_map.insert(info.id.clone(), peer);
+                    let _ = tx.send(BackendEvent::ChatOpened(info)).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(BackendEvent::Error(e.into())).await;
+                }
+            },
+            GuiAction::JoinChat(link) => match join_chat(&client, &link).await {
+                Ok(JoinOutcome::Joined(peer, already_member)) => {
+                    let chat = chat_info(&peer);
+                    state.chat_map.insert(chat.id.clone(), *peer);
+                    let _ = tx.send(BackendEvent::ChatJoined { chat, already_member }).await;
+                }
+                Ok(JoinOutcome::RequestSent(title)) => {
+                    let _ = tx.send(BackendEvent::JoinRequestSent(title)).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(BackendEvent::Error(e.into())).await;
+                }
+            },
+            GuiAction::SelectChat { chat_id, mark_read } => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    // Later changes arrive as updates, this covers the time before the chat was opened
+                    if let Peer::User(user) = peer
+                        && !user.is_bot()
+                    {
+                        let status = presence(user.status());
+                        let _ = tx.send(BackendEvent::UserStatus { user_id: chat_id.clone(), status }).await;
+                    }
+                    let msgs = fetch_messages(&client, &tx, peer, 0, state.message_limit, &mut state.senders, &state.chat_map).await;
+                    let _ = tx.send(BackendEvent::MessagesLoaded(msgs)).await;
+                    if mark_read {
+                        mark_read_and_report(&client, &tx, peer, chat_id.clone()).await;
+                    }
+                    let emoji = allowed_reactions(&client, peer).await;
+                    let _ = tx.send(BackendEvent::AllowedReactions { chat_id: chat_id.clone(), emoji }).await;
+                } else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::ToggleReaction { chat_id, message_id, emoji, add } => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                // Without Premium a message holds one reaction of ours, so adding replaces the old one
+                let reaction = if add { vec![tl::types::ReactionEmoji { emoticon: emoji }.into()] } else { Vec::new() };
+                let request = tl::functions::messages::SendReaction {
+                    big: false,
+                    add_to_recent: add,
+                    peer: peer.clone(),
+                    msg_id: message_id,
+                    reaction: Some(reaction),
+                };
+                let updates = match client.invoke(&request).await {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to react: {}", e))).await;
+                        // Undo the optimistic change with what the server actually has
+                        let request = tl::functions::messages::GetMessagesReactions { peer, id: vec![message_id] };
+                        match client.invoke(&request).await {
+                            Ok(updates) => updates,
+                            Err(e) => {
+                                log::warn!("Failed to reload reactions: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                for (message_id, reactions) in reaction_updates(updates) {
+                    let _ = tx.send(BackendEvent::ReactionsUpdated { chat_id: chat_id.clone(), message_id, reactions }).await;
+                }
+            }
+            GuiAction::VotePoll { chat_id, message_id, option_indices } => {
+                let Some(peer) = state.chat_map.get(&chat_id).cloned() else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match vote_poll(&client, &peer, message_id, &option_indices).await {
+                    Ok(Some(poll)) => {
+                        let _ = tx.send(BackendEvent::PollUpdated { chat_id, message_id, poll }).await;
+                    }
+                    Ok(None) => {
+                        let _ = tx.send(BackendEvent::Error("This poll is no longer available".into())).await;
+                    }
+                    Err(InvocationError::Rpc(rpc)) if rpc.name == "MESSAGE_POLL_CLOSED" => {
+                        let _ = tx.send(BackendEvent::Error("This poll is closed".into())).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to vote: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::LoadContacts => match client.invoke(&tl::functions::contacts::GetContacts { hash: 0 }).await {
+                Ok(tl::enums::contacts::Contacts::Contacts(list)) => {
+                    let peers = PeerMap::new(list.users, Vec::new());
+                    let mut contacts: Vec<ContactInfo> = list
+                        .contacts
+                        .iter()
+                        .filter_map(|contact| {
+                            let tl::enums::Contact::Contact(contact) = contact;
+                            let peer = peers.get(tl::enums::Peer::User(tl::types::PeerUser { user_id: contact.user_id }).into())?;
+                            state.chat_map.insert(peer.id().to_string(), peer.clone());
+                            contact_info(peer)
+                        })
+                        .collect();
+                    contacts.sort_by_key(|c| c.name.to_lowercase());
+                    let _ = tx.send(BackendEvent::ContactsLoaded(contacts)).await;
+                }
+                // Only returned when a hash of the list we already have is sent
+                Ok(tl::enums::contacts::Contacts::NotModified) => {}
+                Err(e) => {
+                    let _ = tx.send(flood_wait_or(&e, format!("Failed to load contacts: {}", e))).await;
+                }
+            },
+            GuiAction::AddContact { phone, first_name, last_name } => {
+                let contact = tl::types::InputPhoneContact { client_id: outbox::random_id(), phone: phone.clone(), first_name, last_name };
+                let request = tl::functions::contacts::ImportContacts { contacts: vec![contact.into()] };
+                match client.invoke(&request).await {
+                    Ok(tl::enums::contacts::ImportedContacts::Contacts(imported)) => {
+                        let peers = PeerMap::new(imported.users, Vec::new());
+                        let added = imported.imported.first().and_then(|contact| {
+                            let tl::enums::ImportedContact::Contact(contact) = contact;
+                            peers.get(tl::enums::Peer::User(tl::types::PeerUser { user_id: contact.user_id }).into())
+                        });
+                        match added.and_then(|peer| Some((peer, contact_info(peer)?))) {
+                            Some((peer, contact)) => {
+                                state.chat_map.insert(contact.id.clone(), peer.clone());
+                                let _ = tx.send(BackendEvent::ContactAdded(contact)).await;
+                            }
+                            None if !imported.retry_contacts.is_empty() => {
+                                let _ = tx.send(BackendEvent::Error("Too many contacts added recently, try again later".into())).await;
+                            }
+                            // Telegram doesn't say why, but it's either no account or privacy settings
+                            None => {
+                                let _ = tx.send(BackendEvent::Error(format!(
+                                    "Can't add {}: there is no Telegram account with that number, or its owner doesn't let others find them by it",
+                                    phone
+                                ).into())).await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to add contact: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::SaveMessage { chat_id, message_ids } => {
+                let (Some(source), Some(me)) = (state.chat_map.get(&chat_id), state.me.clone()) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match client.forward_messages(&Peer::User(me), &message_ids, source).await {
+                    Ok(_) => {
+                        let _ = tx.send(BackendEvent::MessageSaved).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to save message: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::ForwardMessages { chat_id, message_ids, to } => {
+                let (Some(source), Some(destination)) = (state.chat_map.get(&chat_id), state.chat_map.get(&to)) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match client.forward_messages(destination, &message_ids, source).await {
+                    Ok(_) => {
+                        let _ = tx.send(BackendEvent::MessagesForwarded { chat_id, message_ids, to }).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to forward: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::MarkRead(chat_id) => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    mark_read_and_report(&client, &tx, peer, chat_id).await;
+                }
+            }
+            GuiAction::LeaveChat(chat_id) => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                let result = match peer {
+                    tl::enums::InputPeer::Channel(channel) => client
+                        .invoke(&tl::functions::channels::LeaveChannel {
+                            channel: tl::types::InputChannel { channel_id: channel.channel_id, access_hash: channel.access_hash }.into(),
+                        })
+                        .await
+                        .map(drop),
+                    tl::enums::InputPeer::Chat(chat) => client
+                        .invoke(&tl::functions::messages::DeleteChatUser {
+                            revoke_history: false,
+                            chat_id: chat.chat_id,
+                            user_id: tl::enums::InputUser::UserSelf,
+                        })
+                        .await
+                        .map(drop),
+                    _ => {
+                        let _ = tx.send(BackendEvent::Error("Only groups and channels can be left".into())).await;
+                        continue;
+                    }
+                };
+                match result {
+                    Ok(()) => {
+                        state.chat_map.remove(&chat_id);
+                        let _ = tx.send(BackendEvent::ChatLeft(chat_id)).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to leave chat: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::ClearHistory { chat_id, delete_chat, for_everyone } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match delete_history(&client, peer, delete_chat, for_everyone).await {
+                    Ok(()) => {
+                        let _ = tx.send(BackendEvent::HistoryCleared { chat_id, chat_deleted: delete_chat }).await;
+                    }
+                    Err(InvocationError::Rpc(rpc)) if rpc.name == "CHAT_ADMIN_REQUIRED" => {
+                        let _ = tx.send(BackendEvent::Error("Only admins can clear the history of this chat".into())).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to delete messages: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::ToggleMute(chat_id) => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match toggle_mute(&client, peer).await {
+                    Ok(muted) => {
+                        let _ = tx.send(BackendEvent::MuteChanged { chat_id, muted }).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to change notifications: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::TogglePinChat(chat_id) => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match toggle_pin(&client, peer).await {
+                    Ok(pinned) => {
+                        let _ = tx.send(BackendEvent::PinChanged { chat_id, pinned }).await;
+                    }
+                    Err(InvocationError::Rpc(rpc)) if rpc.name == "PINNED_DIALOGS_TOO_MUCH" => {
+                        let message = "You've pinned as many chats as Telegram allows, unpin one first".into();
+                        let _ = tx.send(BackendEvent::Error(message)).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to pin chat: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::SearchMessages { chat_id, query } => {
                 if let Some(peer) = state.chat_map.get(&chat_id) {
-                    let mut msgs = Vec::new();
-                    let mut messages = client.iter_messages(peer).limit(50);
+                    let mut results = Vec::new();
+                    let mut messages = client.search_messages(peer).query(&query).limit(state.message_limit);
                     while let Ok(Some(message)) = messages.next().await {
-                        let sender = message.sender().map(|s| s.name().unwrap_or("Unknown").to_string()).unwrap_or("Unknown".to_string());
-                        msgs.push(MessageInfo {
-                            id: message.id(),
-                            text: message.text().to_string(),
-                            sender,
-                            date: message.date().to_string(),
-                        });
+                        results.push(message_info(&client, &tx, &message, |id| known_name(id, &state.chat_map, &state.senders)));
                     }
-                    msgs.reverse();
-                    let _ = tx.send(BackendEvent::MessagesLoaded(msgs)).await;
+                    let _ = tx.send(BackendEvent::SearchResults(results)).await;
                 } else {
-                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".to_string())).await;
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::LoadOlderMessages { chat_id, before_id } => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    let msgs = fetch_messages(&client, &tx, peer, before_id, state.message_limit, &mut state.senders, &state.chat_map).await;
+                    let _ = tx.send(BackendEvent::OlderMessagesLoaded(msgs)).await;
+                } else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::SendMessage { chat_id, text, markdown, temp_id, random_id } => {
+                // Queued messages can outlive the chat list, so fall back to the peers the session remembers
+                let peer = state.chat_map.get(&chat_id).and_then(input_peer);
+                let Some(peer) = peer.or_else(|| cached_input_peer(&session, &chat_id)) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    let _ = tx.send(BackendEvent::SendFailed { temp_id }).await;
+                    continue;
+                };
+                match send_text(&client, peer, &text, markdown, random_id, None).await {
+                    Ok(Some(real_id)) => {
+                        let _ = tx.send(BackendEvent::MessageSent { temp_id, real_id }).await;
+                    }
+                    Ok(None) => {
+                        let _ = tx.send(BackendEvent::MessageDelivered { temp_id }).await;
+                    }
+                    // A retry of something that already went through before the connection dropped
+                    Err(InvocationError::Rpc(rpc)) if rpc.name == "RANDOM_ID_DUPLICATE" => {
+                        let _ = tx.send(BackendEvent::MessageDelivered { temp_id }).await;
+                    }
+                    Err(e @ InvocationError::Rpc(_)) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to send: {}", e))).await;
+                        let _ = tx.send(BackendEvent::SendFailed { temp_id }).await;
+                    }
+                    // Anything other than an RPC error means the connection is in trouble, so wait for it
+                    Err(e) => {
+                        log::warn!("Queueing message after send failed: {}", e);
+                        let _ = tx.send(BackendEvent::MessageQueued { temp_id }).await;
+                    }
+                }
+            }
+            GuiAction::ScheduleMessage { chat_id, text, markdown, at } => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                let schedule_date = Some(at.timestamp() as i32);
+                match send_text(&client, peer, &text, markdown, outbox::random_id(), schedule_date).await {
+                    Ok(_) => {
+                        let _ = tx.send(BackendEvent::MessageScheduled { chat_id, at }).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to schedule message: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::LoadScheduled(chat_id) => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                send_scheduled(&client, &tx, chat_id, peer).await;
+            }
+            GuiAction::CancelScheduled { chat_id, message_id } => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                let request = tl::functions::messages::DeleteScheduledMessages { peer: peer.clone(), id: vec![message_id] };
+                if let Err(e) = client.invoke(&request).await {
+                    let _ = tx.send(flood_wait_or(&e, format!("Failed to cancel scheduled message: {}", e))).await;
                 }
+                send_scheduled(&client, &tx, chat_id, peer).await;
             }
-            GuiAction::SendMessage { chat_id, text } => {
+            GuiAction::DeleteMessages { chat_id, message_ids } => {
                 if let Some(peer) = state.chat_map.get(&chat_id) {
-                    match client.send_message(peer, text).await {
+                    match client.delete_messages(peer, &message_ids).await {
                         Ok(_) => {
-                             // Refresh messages
-                            let mut msgs = Vec::new();
-                            let mut messages = client.iter_messages(peer).limit(50);
-                            while let Ok(Some(message)) = messages.next().await {
-                                let sender = message.sender().map(|s| s.name().unwrap_or("Unknown").to_string()).unwrap_or("Unknown".to_string());
-                                msgs.push(MessageInfo {
-                                    id: message.id(),
-                                    text: message.text().to_string(),
-                                    sender,
-                                    date: message.date().to_string(),
-                                });
-                            }
-                            msgs.reverse();
-                            let _ = tx.send(BackendEvent::MessagesLoaded(msgs)).await;
+                            let _ = tx.send(BackendEvent::MessagesDeleted(message_ids)).await;
                         }
                         Err(e) => {
-                            let _ = tx.send(BackendEvent::Error(format!("Failed to send: {}", e))).await;
+                            let _ = tx.send(flood_wait_or(&e, format!("Failed to delete: {}", e))).await;
                         }
                     }
+                } else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::PinMessage { chat_id, message_id, silent } => {
+                set_pinned(&client, &tx, &state, &chat_id, message_id, silent, false).await;
+            }
+            GuiAction::UnpinMessage { chat_id, message_id } => {
+                set_pinned(&client, &tx, &state, &chat_id, message_id, true, true).await;
+            }
+            GuiAction::FetchAvatar { peer_id } => {
+                let Some(peer) = state.chat_map.get(&peer_id).or_else(|| state.senders.get(&peer_id)) else {
+                    continue;
+                };
+                spawn_avatar_download(client.clone(), tx.clone(), peer_id, peer.clone());
+            }
+            GuiAction::FetchAnimation { chat_id, message_id } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    continue;
+                };
+                spawn_animation_download(client.clone(), tx.clone(), peer.clone(), message_id);
+            }
+            GuiAction::FetchSticker { chat_id, message_id, file_id } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    continue;
+                };
+                spawn_sticker_download(client.clone(), tx.clone(), peer.clone(), message_id, file_id);
+            }
+            GuiAction::FetchPreviewImage { chat_id, message_id } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    continue;
+                };
+                spawn_preview_download(client.clone(), tx.clone(), peer.clone(), message_id);
+            }
+            GuiAction::LoadMembers { chat_id, offset } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                match load_members(&client, peer, offset).await {
+                    Ok((total, members, all_loaded)) => {
+                        let _ = tx.send(BackendEvent::MembersLoaded { chat_id, offset, total, members, all_loaded }).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to load members: {}", e))).await;
+                        // Stops the list from asking for the same page again
+                        let event = BackendEvent::MembersLoaded { chat_id, offset, total: 0, members: Vec::new(), all_loaded: true };
+                        let _ = tx.send(event).await;
+                    }
                 }
             }
-            GuiAction::Logout => {
-                match client.sign_out().await {
-                    Ok(_) => {
-                        state.login_token = None;
-                        state.password_token = None;
-                        state.chat_map.clear();
-                        let _ = tx.send(BackendEvent::LoggedOut).await;
+            GuiAction::GetPinned(chat_id) => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    let _ = tx.send(BackendEvent::PinnedMessage(pinned_message(&client, &tx, peer, &state).await)).await;
+                }
+            }
+            GuiAction::FetchMessage { chat_id, id } => {
+                let Some(peer) = state.chat_map.get(&chat_id) else {
+                    continue;
+                };
+                // Only the quote line is needed, `message_info` would start downloading its media too
+                let quote = match client.get_messages_by_id(peer, &[id]).await {
+                    Ok(messages) => Ok(messages.into_iter().next().flatten().map(|m| {
+                        let sender = m.sender().and_then(|s| s.name().map(str::to_string)).unwrap_or("Unknown".to_string());
+                        format!("{}: {}", sender, message_preview(&m))
+                    })),
+                    Err(e) => Err(e.to_string()),
+                };
+                let _ = tx.send(BackendEvent::MessageFetched { chat_id, id, quote }).await;
+            }
+            GuiAction::EditMessage { chat_id, message_id, new_text } => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                    continue;
+                };
+                // Sent raw, grammers' edit_message drops the edited message Telegram answers with
+                let request = tl::functions::messages::EditMessage {
+                    no_webpage: false,
+                    invert_media: false,
+                    peer,
+                    id: message_id,
+                    message: Some(new_text.clone()),
+                    media: None,
+                    reply_markup: None,
+                    entities: None,
+                    schedule_date: None,
+                    quick_reply_shortcut_id: None,
+                };
+                match client.invoke(&request).await {
+                    Ok(updates) => {
+                        let event = match edited_message(updates) {
+                            Some(message) => edited_event(message, true),
+                            None => BackendEvent::MessageEdited {
+                                chat_id,
+                                id: message_id,
+                                text: new_text,
+                                entities: Vec::new(),
+                                edit_date: Some(chrono::Utc::now()),
+                                own: true,
+                            },
+                        };
+                        let _ = tx.send(event).await;
                     }
                     Err(e) => {
-                        let _ = tx.send(BackendEvent::Error(format!("Failed to log out: {}", e))).await;
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to edit: {}", e))).await;
                     }
                 }
             }
-            _ => {}
+            GuiAction::SendPhoto { chat_id, path, temporary } => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    let uploaded = client.upload_file(&path).await;
+                    if temporary {
+                        let _ = tokio::fs::remove_file(&path).await;
+                    }
+                    let uploaded = match uploaded {
+                        Ok(uploaded) => uploaded,
+                        Err(e) => {
+                            let _ = tx.send(BackendEvent::Error(format!("Failed to upload: {}", e).into())).await;
+                            continue;
+                        }
+                    };
+                    match client.send_message(peer, InputMessage::new().photo(uploaded)).await {
+                        Ok(_) => {
+                            let msgs = fetch_messages(&client, &tx, peer, 0, state.message_limit, &mut state.senders, &state.chat_map).await;
+                            let _ = tx.send(BackendEvent::MessagesLoaded(msgs)).await;
+                        }
+                        Err(e) => {
+                            let _ = tx.send(flood_wait_or(&e, format!("Failed to send: {}", e))).await;
+                        }
+                    }
+                } else {
+                    if temporary {
+                        let _ = tokio::fs::remove_file(&path).await;
+                    }
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::SendDocument { chat_id, path } => {
+                // The GUI waits for an answer either way
+                let error = if state.upload.as_ref().is_some_and(|upload| !upload.is_finished()) {
+                    "Another file is still uploading"
+                } else if let Some(peer) = state.chat_map.get(&chat_id) {
+                    state.upload = Some(spawn_document_upload(client.clone(), tx.clone(), peer.clone(), chat_id, path));
+                    continue;
+                } else {
+                    "Chat not found in cache"
+                };
+                let _ = tx.send(BackendEvent::UploadFinished { voice: false, error: Some(error.to_string()) }).await;
+            }
+            GuiAction::SendVoice { chat_id, samples, sample_rate } => {
+                let error = if state.upload.as_ref().is_some_and(|upload| !upload.is_finished()) {
+                    "Another file is still uploading"
+                } else if let Some(peer) = state.chat_map.get(&chat_id) {
+                    state.upload = Some(spawn_voice_upload(client.clone(), tx.clone(), peer.clone(), chat_id, samples, sample_rate));
+                    continue;
+                } else {
+                    "Chat not found in cache"
+                };
+                let _ = tx.send(BackendEvent::UploadFinished { voice: true, error: Some(error.to_string()) }).await;
+            }
+            GuiAction::CancelUpload => {
+                if let Some(upload) = state.upload.take() {
+                    upload.abort();
+                }
+            }
+            GuiAction::ExportChat { chat_id, format, path } => {
+                // Only one export reports to the dialog at a time
+                if let Some(export) = state.export.take() {
+                    export.abort();
+                }
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    state.export = Some(spawn_chat_export(client.clone(), tx.clone(), peer.clone(), format, path));
+                } else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::CancelExport => {
+                if let Some(export) = state.export.take() {
+                    export.abort();
+                }
+            }
+            GuiAction::DownloadMedia { chat_id, message_id, path } => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    spawn_media_download(client.clone(), tx.clone(), peer.clone(), chat_id, message_id, path);
+                } else {
+                    let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+                }
+            }
+            GuiAction::FetchFullPhoto { chat_id, message_id } => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    spawn_full_photo_download(client.clone(), tx.clone(), peer.clone(), message_id);
+                } else {
+                    let _ = tx.send(BackendEvent::FullPhotoFailed { message_id, error: "Chat not found in cache".to_string() }).await;
+                }
+            }
+            GuiAction::SaveDraft { chat_id, text } => {
+                let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+                    continue;
+                };
+                let request = tl::functions::messages::SaveDraft {
+                    no_webpage: false,
+                    invert_media: false,
+                    reply_to: None,
+                    peer,
+                    message: text,
+                    entities: None,
+                    media: None,
+                    effect: None,
+                    suggested_post: None,
+                };
+                // Drafts are a convenience, a failure isn't worth interrupting the user for
+                if let Err(e) = client.invoke(&request).await {
+                    log::warn!("Failed to save draft: {}", e);
+                }
+            }
+            GuiAction::SetTyping(chat_id) => {
+                if let Some(peer) = state.chat_map.get(&chat_id) {
+                    let client = client.clone();
+                    let peer = peer.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = client.action(&peer).oneshot(tl::enums::SendMessageAction::SendMessageTypingAction).await {
+                            log::debug!("Failed to send typing action: {}", e);
+                        }
+                    });
+                }
+            }
+            GuiAction::SwitchAccount(new_account) => {
+                pool_handle.quit();
+                // The old runner reports its end too, bumping the generation makes the loop ignore it
+                generation += 1;
+                reconnect = None;
+                account = new_account;
+                session = open_session_or_memory(&tx, &session_dir, account).await;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+                state = BackgroundState::new(state.api_hash, state.message_limit);
+                let _ = tx.send(BackendEvent::AccountSwitched).await;
+                if let Ok(true) = client.is_authorized().await {
+                    state.is_bot = client.get_me().await.map(|me| me.is_bot()).unwrap_or(false);
+                    let _ = tx.send(BackendEvent::LoggedIn).await;
+                }
+            }
+            GuiAction::Configure { api_id: new_api_id, api_hash, account: new_account, proxy: new_proxy, live_updates } => {
+                pool_handle.quit();
+                generation += 1;
+                reconnect = None;
+                account = new_account;
+                api_id = new_api_id;
+                proxy = new_proxy;
+                conn_events.live = live_updates;
+                session = open_session_or_memory(&tx, &session_dir, account).await;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+                state = BackgroundState::new(api_hash, state.message_limit);
+                match report_connection(&client, &tx, &mut state, proxy.as_ref()).await {
+                    ConnectionCheck::Ready => {}
+                    ConnectionCheck::Failed => {
+                        pool_handle.quit();
+                        generation += 1;
+                    }
+                    ConnectionCheck::Unreachable => {
+                        pool_handle.quit();
+                        generation += 1;
+                        reconnect = Some(Reconnect::configuring());
+                    }
+                }
+            }
+            GuiAction::SetMessageLimit(limit) => {
+                state.message_limit = limit;
+            }
+            GuiAction::Logout => {
+                match client.sign_out().await {
+                    Ok(_) => {
+                        state.login_token = None;
+                        state.password_token = None;
+                        state.chat_map.clear();
+                        state.dialogs = None;
+                        state.folders.clear();
+                        state.is_bot = false;
+                        state.me = None;
+                        let _ = tx.send(BackendEvent::LoggedOut).await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(flood_wait_or(&e, format!("Failed to log out: {}", e))).await;
+                    }
+                }
+            }
+            GuiAction::SetProxy(new_proxy) => {
+                proxy_check += 1;
+                spawn_proxy_check(session.clone(), api_id, new_proxy, proxy_check, proxy_checked_tx.clone());
+            }
+            GuiAction::TestProxy { host, port } => spawn_proxy_test(tx.clone(), host, port),
+            GuiAction::SetLiveUpdates(live) if live != conn_events.live => {
+                let listening = conn_events.listening();
+                conn_events.live = live;
+                if conn_events.listening() == listening {
+                    continue;
+                }
+                // The listener belongs to the connection, so a new one is the way to start or stop it
+                pool_handle.quit();
+                if listening && reconnect.is_none() {
+                    wait_for_sync(&mut synced_rx, generation).await;
+                }
+                generation += 1;
+                reconnect = None;
+                let _ = tx.send(BackendEvent::Reconnecting).await;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+                let _ = tx.send(BackendEvent::Reconnected).await;
+            }
+            GuiAction::SetActive(active) if active != conn_events.active => {
+                let listening = conn_events.listening();
+                conn_events.active = active;
+                if conn_events.listening() == listening {
+                    continue;
+                }
+                // Paused connections go without a listener so nothing piles up,
+                // the one after the pause catches up from the state the last listener saved
+                pool_handle.quit();
+                if listening && reconnect.is_none() {
+                    wait_for_sync(&mut synced_rx, generation).await;
+                }
+                generation += 1;
+                reconnect = None;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+            }
+            GuiAction::ResetSession => {
+                // Stops the update listener too, it fails along with everything else on a dead session
+                pool_handle.quit();
+                // It saves the update state on its way out, which must not land in the fresh session
+                if conn_events.listening() {
+                    wait_for_sync(&mut synced_rx, generation).await;
+                }
+                generation += 1;
+                reconnect = None;
+                state = BackgroundState::new(state.api_hash, state.message_limit);
+                session = reset_session(&tx, &session_dir, account, session, client).await;
+                (client, pool_handle) = connect(&session, api_id, proxy.as_ref(), generation, &conn_events);
+            }
+            GuiAction::Shutdown => {
+                pool_handle.quit();
+                if conn_events.listening() {
+                    wait_for_sync(&mut synced_rx, generation).await;
+                }
+                let _ = tx.send(BackendEvent::ShutdownComplete).await;
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Waits for the update listener of `generation` to save the update state, which it does once its connection is gone.
+async fn wait_for_sync(synced_rx: &mut mpsc::UnboundedReceiver<u64>, generation: u64) {
+    let synced = async {
+        while let Some(synced) = synced_rx.recv().await {
+            if synced == generation {
+                break;
+            }
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, synced).await.is_err() {
+        log::warn!("Timed out saving the update state");
+    }
+}
+
+fn session_file_name(account: usize) -> String {
+    format!("session_{}.session", account)
+}
+
+/// Opens the session file of `account` in `dir`.
+///
+/// Sessions used to live in the working directory, those are moved over the first time they're needed.
+fn open_session(dir: &Path, account: usize) -> Result<Arc<SqliteSession>, String> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        log::warn!("Failed to create session directory {}: {}", dir.display(), e);
+    }
+    let file_name = session_file_name(account);
+    let path = dir.join(&file_name);
+    let legacy = if account == 0 && !Path::new(&file_name).exists() { LEGACY_SESSION_FILE } else { file_name.as_str() };
+    if !path.exists() && Path::new(legacy).exists() {
+        // Renaming fails across file systems, copying doesn't
+        let moved = std::fs::rename(legacy, &path)
+            .or_else(|_| std::fs::copy(legacy, &path).and_then(|_| std::fs::remove_file(legacy)));
+        match moved {
+            Ok(()) => log::info!("Moved {} to {}", legacy, path.display()),
+            Err(e) => log::warn!("Failed to migrate {}: {}", legacy, e),
+        }
+    }
+    SqliteSession::open(&path)
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to open session {}: {}", path.display(), e))
+}
+
+/// Opens the session of `account`, falling back to one in memory if its file can't be opened.
+///
+/// The app stays usable that way, but a login won't be remembered after closing it.
+async fn open_session_or_memory(tx: &mpsc::Sender<BackendEvent>, dir: &Path, account: usize) -> Arc<SqliteSession> {
+    match open_session(dir, account) {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("{}", e);
+            let message = format!("{}. Using a temporary session, you'll have to log in again next time.", e);
+            let _ = tx.send(BackendEvent::Error(message.into())).await;
+            Arc::new(SqliteSession::open(":memory:").expect("in-memory session"))
+        }
+    }
+}
+
+/// Starts `account` over with an empty session after Telegram revoked it, so the next connection starts logged out.
+///
+/// The file is deleted so no peers or update state of the old login carry over. On Windows that fails while
+/// downloads still hold it open, then its authorization keys and update state are cleared in place instead.
+async fn reset_session(
+    tx: &mpsc::Sender<BackendEvent>,
+    dir: &Path,
+    account: usize,
+    session: Arc<SqliteSession>,
+    client: Client,
+) -> Arc<SqliteSession> {
+    // The pool has quit, so apart from running downloads these are the last handles on the file.
+    // Windows can't delete open files, so the session is closed here unless a download still has it.
+    drop(client);
+    let session = Arc::try_unwrap(session).err();
+    let path = dir.join(session_file_name(account));
+    match std::fs::remove_file(&path) {
+        Ok(()) => return open_session_or_memory(tx, dir, account).await,
+        Err(e) => log::warn!("Failed to delete {}, clearing it instead: {}", path.display(), e),
+    }
+    let session = match session {
+        Some(session) => session,
+        None => open_session_or_memory(tx, dir, account).await,
+    };
+    for dc_id in PRIMARY_DC_IDS {
+        if let Some(mut dc_option) = session.dc_option(dc_id)
+            && dc_option.auth_key.is_some()
+        {
+            dc_option.auth_key = None;
+            session.set_dc_option(&dc_option);
+        }
+    }
+    session.set_update_state(UpdateState::All(UpdatesState::default()));
+    session
+}
+
+type RawUpdate = (tl::enums::Update, State, Arc<PeerMap>);
+
+/// Channels the per-connection tasks use to report back to the main loop.
+struct ConnectionEvents {
+    ended: mpsc::UnboundedSender<u64>,
+    /// Raw updates, tagged with the generation of the connection they arrived on.
+    updates: mpsc::UnboundedSender<(u64, RawUpdate)>,
+    /// The update listener of a connection saved its state and stopped.
+    synced: mpsc::UnboundedSender<u64>,
+    /// The update listener of a connection was told the session is no longer authorized.
+    lost: mpsc::UnboundedSender<u64>,
+    /// Whether a connection checked with `spawn_probe` reached Telegram, and if so whether it's logged in.
+    probed: mpsc::UnboundedSender<(u64, Result<bool, InvocationError>)>,
+    /// False while the window is in the background, which pauses the update listeners.
+    active: bool,
+    /// Whether connections get an update listener, off while the user refreshes by hand.
+    live: bool,
+}
+
+impl ConnectionEvents {
+    /// Whether new connections get an update listener.
+    fn listening(&self) -> bool {
+        self.live && self.active
+    }
+}
+
+fn sender_pool(session: &Arc<SqliteSession>, api_id: i32, proxy: Option<&ProxyConfig>) -> SenderPool {
+    match proxy {
+        Some(proxy) => {
+            let params = ConnectionParams { proxy_url: Some(proxy.url()), ..Default::default() };
+            SenderPool::with_configuration(session.clone(), api_id, params)
+        }
+        None => SenderPool::new(session.clone(), api_id),
+    }
+}
+
+/// Builds a fresh sender pool and client, reporting `generation` once its runner stops.
+fn connect(
+    session: &Arc<SqliteSession>,
+    api_id: i32,
+    proxy: Option<&ProxyConfig>,
+    generation: u64,
+    events: &ConnectionEvents,
+) -> (Client, SenderPoolHandle) {
+    let pool = sender_pool(session, api_id, proxy);
+    let client = Client::new(&pool);
+    let SenderPool { runner, handle, updates } = pool;
+    let ended_tx = events.ended.clone();
+    tokio::spawn(async move {
+        runner.run().await;
+        let _ = ended_tx.send(generation);
+    });
+    if events.listening() {
+        spawn_update_listener(client.clone(), updates, events, generation);
+    }
+    (client, handle)
+}
+
+/// Drives the update stream and forwards raw updates so the main loop can resolve them against its state.
+fn spawn_update_listener(
+    client: Client,
+    updates: mpsc::UnboundedReceiver<UpdatesLike>,
+    events: &ConnectionEvents,
+    generation: u64,
+) {
+    let update_tx = events.updates.clone();
+    let synced_tx = events.synced.clone();
+    let lost_tx = events.lost.clone();
+    tokio::spawn(async move {
+        let mut stream = client.stream_updates(updates, UpdatesConfiguration::default());
+        loop {
+            match stream.next_raw().await {
+                Ok(update) => {
+                    if update_tx.send((generation, update)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    if is_session_lost(&e) {
+                        log::warn!("Update listener stopped, the session was revoked: {}", e);
+                        let _ = lost_tx.send(generation);
+                    } else {
+                        log::debug!("Update listener stopped: {}", e);
+                    }
+                    break;
+                }
+            }
+        }
+        // Lets the next connection catch up on updates from where this one stopped
+        stream.sync_update_state();
+        let _ = synced_tx.send(generation);
+    });
+}
+
+async fn handle_update(
+    client: &Client,
+    state: &BackgroundState,
+    update: tl::enums::Update,
+    peers: &Arc<PeerMap>,
+    tx: &mpsc::Sender<BackendEvent>,
+) {
+    use tl::enums::Update as U;
+
+    let (chat_id, user_id, action) = match update {
+        U::NewMessage(update) => return handle_new_message(client, state, update.message, peers, tx).await,
+        U::NewChannelMessage(update) => return handle_new_message(client, state, update.message, peers, tx).await,
+        U::EditMessage(update) => return handle_edited_message(update.message, tx).await,
+        U::EditChannelMessage(update) => return handle_edited_message(update.message, tx).await,
+        U::UserTyping(typing) => (PeerId::user(typing.user_id), PeerId::user(typing.user_id), typing.action),
+        U::ChatUserTyping(typing) => (PeerId::chat(typing.chat_id), typing.from_id.into(), typing.action),
+        U::ChannelUserTyping(typing) => (PeerId::channel(typing.channel_id), typing.from_id.into(), typing.action),
+        U::MessageReactions(update) => {
+            let chat_id = PeerId::from(update.peer).to_string();
+            let reactions = reactions(&update.reactions);
+            let _ = tx.send(BackendEvent::ReactionsUpdated { chat_id, message_id: update.msg_id, reactions }).await;
+            return;
+        }
+        // Only private chats get read receipts for outgoing messages
+        U::ReadHistoryOutbox(update) if matches!(update.peer, tl::enums::Peer::User(_)) => {
+            let chat_id = PeerId::from(update.peer).to_string();
+            let _ = tx.send(BackendEvent::MessagesRead { chat_id, max_id: update.max_id }).await;
+            return;
+        }
+        U::UserStatus(update) => {
+            let user_id = PeerId::user(update.user_id).to_string();
+            let _ = tx.send(BackendEvent::UserStatus { user_id, status: presence(&update.status) }).await;
+            return;
+        }
+        _ => return,
+    };
+    if let tl::enums::SendMessageAction::SendMessageCancelAction = action {
+        return;
+    }
+    // Typing updates rarely carry the user, so fall back to the dialogs we already know about
+    let user_name = peers
+        .get(user_id)
+        .or_else(|| state.chat_map.get(&user_id.to_string()))
+        .and_then(|peer| peer.name())
+        .unwrap_or("Someone")
+        .to_string();
+    let _ = tx.send(BackendEvent::PeerTyping { chat_id: chat_id.to_string(), user_name }).await;
+}
+
+async fn handle_edited_message(message: tl::enums::Message, tx: &mpsc::Sender<BackendEvent>) {
+    let tl::enums::Message::Message(message) = message else {
+        return;
+    };
+    let _ = tx.send(edited_event(message, false)).await;
+}
+
+fn edited_event(message: tl::types::Message, own: bool) -> BackendEvent {
+    // Hidden edits, like a bot changing its buttons, still change the text but don't mark it as edited
+    let edit_date = message
+        .edit_date
+        .filter(|_| !message.edit_hide)
+        .and_then(|date| chrono::DateTime::from_timestamp(date as i64, 0));
+    BackendEvent::MessageEdited {
+        chat_id: PeerId::from(message.peer_id).to_string(),
+        id: message.id,
+        entities: text_entities(&message.message, message.entities.as_deref().unwrap_or_default()),
+        text: message.message,
+        edit_date,
+        own,
+    }
+}
+
+/// Pulls the edited message out of the reply to an edit request.
+fn edited_message(updates: tl::enums::Updates) -> Option<tl::types::Message> {
+    let updates = match updates {
+        tl::enums::Updates::Updates(updates) => updates.updates,
+        tl::enums::Updates::Combined(updates) => updates.updates,
+        tl::enums::Updates::UpdateShort(update) => vec![update.update],
+        _ => Vec::new(),
+    };
+    updates.into_iter().find_map(|update| match update {
+        tl::enums::Update::EditMessage(tl::types::UpdateEditMessage { message: tl::enums::Message::Message(message), .. })
+        | tl::enums::Update::EditChannelMessage(tl::types::UpdateEditChannelMessage {
+            message: tl::enums::Message::Message(message),
+            ..
+        }) => Some(message),
+        _ => None,
+    })
+}
+
+async fn handle_new_message(
+    client: &Client,
+    state: &BackgroundState,
+    message: tl::enums::Message,
+    peers: &Arc<PeerMap>,
+    tx: &mpsc::Sender<BackendEvent>,
+) {
+    if !matches!(message, tl::enums::Message::Message(_)) {
+        return;
+    }
+    let message = Message::from_raw(client, message, None, peers);
+    let mut info = message_info(client, tx, &message, |id| known_name(id, &state.chat_map, &state.senders));
+    // Match the name `message_info` reports for our own messages when the update doesn't carry us
+    if let Some(me) = state.me.as_ref().filter(|_| message.outgoing()) {
+        info.sender = me.full_name();
+        info.sender_id = PeerId::user(me.bare_id()).to_string();
+    }
+    let _ = tx.send(BackendEvent::NewMessage { chat_id: message.peer_id().to_string(), message: info }).await;
+}
+
+/// Tells the GUI whether a new connection works, logging in right away if the session already is.
+///
+/// `Configured` only goes out once Telegram answered, an unreachable Telegram is left for the caller to retry.
+async fn report_connection(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    state: &mut BackgroundState,
+    proxy: Option<&ProxyConfig>,
+) -> ConnectionCheck {
+    // Any request forces a connection to the home DC, which is what is being checked
+    match client.is_authorized().await {
+        Ok(authorized) => {
+            report_configured(client, tx, state, authorized).await;
+            ConnectionCheck::Ready
+        }
+        // A proxy that can't be reached is more likely wrong than down
+        Err(e) if proxy.is_none() && error_kind(&e) == ErrorKind::Network => {
+            log::warn!("Can't reach Telegram, retrying: {}", e);
+            ConnectionCheck::Unreachable
+        }
+        Err(e) => {
+            let _ = tx.send(BackendEvent::ConnectionFailed(connection_error(&e, proxy))).await;
+            ConnectionCheck::Failed
+        }
+    }
+}
+
+/// How the first connection with new settings went.
+enum ConnectionCheck {
+    /// Telegram answered and `Configured` was sent.
+    Ready,
+    /// The settings don't work, which was sent as `ConnectionFailed`.
+    Failed,
+    /// Telegram couldn't be reached, which may pass on its own.
+    Unreachable,
+}
+
+/// Tells the GUI the settings work, and who is logged in if anyone.
+async fn report_configured(client: &Client, tx: &mpsc::Sender<BackendEvent>, state: &mut BackgroundState, authorized: bool) {
+    let _ = tx.send(BackendEvent::Configured).await;
+    if authorized {
+        state.is_bot = client.get_me().await.map(|me| me.is_bot()).unwrap_or(false);
+        let _ = tx.send(BackendEvent::LoggedIn).await;
+    }
+}
+
+/// The `ConnectionFailed` message for `error`, blaming the proxy if there is one.
+fn connection_error(error: &InvocationError, proxy: Option<&ProxyConfig>) -> String {
+    match proxy {
+        Some(_) => proxy_error(error),
+        None => format!("Can't connect to Telegram: {}", error),
+    }
+}
+
+/// A dropped connection being brought back, one attempt at a time so the main loop keeps handling actions.
+struct Reconnect {
+    /// When the next attempt starts, `None` while one is being checked.
+    at: Option<tokio::time::Instant>,
+    /// How long to wait after the next failed attempt.
+    delay: Duration,
+    /// The settings never connected yet, so success is reported as `Configured`.
+    configuring: bool,
+}
+
+impl Reconnect {
+    fn new() -> Self {
+        Self { at: Some(tokio::time::Instant::now()), delay: Duration::from_secs(1), configuring: false }
+    }
+
+    /// Keeps trying new settings whose first connection found Telegram unreachable.
+    fn configuring() -> Self {
+        let mut reconnect = Self { configuring: true, ..Self::new() };
+        reconnect.retry_later();
+        reconnect
+    }
+
+    /// Schedules the next attempt after a failed one, backing off up to `MAX_RECONNECT_DELAY`.
+    fn retry_later(&mut self) {
+        self.at = Some(tokio::time::Instant::now() + self.delay);
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Checks in the background whether the connection of `generation` works, answering on `events.probed`.
+fn spawn_probe(client: Client, generation: u64, events: &ConnectionEvents) {
+    let probed_tx = events.probed.clone();
+    tokio::spawn(async move {
+        // Any request forces a connection to the home DC, so this tells us whether the network is back
+        let _ = probed_tx.send((generation, client.is_authorized().await));
+    });
+}
+
+async fn fetch_messages(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    peer: &Peer,
+    before_id: i32,
+    limit: usize,
+    senders: &mut std::collections::HashMap<String, Peer>,
+    chats: &std::collections::HashMap<String, Peer>,
+) -> Vec<MessageInfo> {
+    let mut msgs = Vec::new();
+    let mut messages = client.iter_messages(peer).offset_id(before_id).limit(limit);
+    loop {
+        let message = match messages.next().await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                // What arrived so far is still shown
+                let _ = tx.send(flood_wait_or(&e, format!("Failed to load messages: {}", e))).await;
+                break;
+            }
+        };
+        if let Some(sender) = message.sender() {
+            senders.insert(sender.id().to_string(), sender.clone());
+        }
+        msgs.push(message_info(client, tx, &message, |id| known_name(id, chats, senders)));
+    }
+    msgs.reverse();
+    msgs
+}
+
+async fn pinned_message(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    peer: &Peer,
+    state: &BackgroundState,
+) -> Option<MessageInfo> {
+    match client.get_pinned_message(peer).await {
+        Ok(message) => {
+            message.map(|message| message_info(client, tx, &message, |id| known_name(id, &state.chat_map, &state.senders)))
+        }
+        Err(e) => {
+            log::warn!("Failed to fetch pinned message: {}", e);
+            None
+        }
+    }
+}
+
+/// Pins or unpins a message, then reports the chat's pinned message again since it may have changed.
+async fn set_pinned(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    state: &BackgroundState,
+    chat_id: &str,
+    message_id: i32,
+    silent: bool,
+    unpin: bool,
+) {
+    let Some((peer, input)) = state.chat_map.get(chat_id).and_then(|peer| Some((peer, input_peer(peer)?))) else {
+        let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+        return;
+    };
+    let request = tl::functions::messages::UpdatePinnedMessage {
+        silent,
+        unpin,
+        pm_oneside: false,
+        peer: input,
+        id: message_id,
+    };
+    if let Err(e) = client.invoke(&request).await {
+        let action = if unpin { "unpin" } else { "pin" };
+        let _ = tx.send(flood_wait_or(&e, format!("Failed to {}: {}", action, e))).await;
+        return;
+    }
+    let _ = tx.send(BackendEvent::PinnedMessage(pinned_message(client, tx, peer, state).await)).await;
+}
+
+/// Sends a text message with a caller-chosen `random_id`, returning its ID if the response includes it.
+async fn send_text(
+    client: &Client,
+    peer: tl::enums::InputPeer,
+    text: &str,
+    markdown: bool,
+    random_id: i64,
+    schedule_date: Option<i32>,
+) -> Result<Option<i32>, InvocationError> {
+    // grammers picks its own random_id in send_message, which would defeat deduplication of retries
+    let (message, entities) = if markdown {
+        grammers_client::parsers::parse_markdown_message(text)
+    } else {
+        (text.to_string(), Vec::new())
+    };
+    let request = tl::functions::messages::SendMessage {
+        no_webpage: false,
+        silent: false,
+        background: false,
+        clear_draft: true,
+        noforwards: false,
+        update_stickersets_order: false,
+        invert_media: false,
+        allow_paid_floodskip: false,
+        peer,
+        reply_to: None,
+        message,
+        random_id,
+        reply_markup: None,
+        entities: (!entities.is_empty()).then_some(entities),
+        schedule_date,
+        send_as: None,
+        quick_reply_shortcut: None,
+        effect: None,
+        allow_paid_stars: None,
+        suggested_post: None,
+    };
+    let updates = client.invoke(&request).await?;
+    let id = match updates {
+        tl::enums::Updates::UpdateShortSentMessage(sent) => Some(sent.id),
+        tl::enums::Updates::Updates(updates) => sent_message_id(&updates.updates, random_id),
+        tl::enums::Updates::Combined(updates) => sent_message_id(&updates.updates, random_id),
+        _ => None,
+    };
+    Ok(id)
+}
+
+/// Reports the messages scheduled in a chat, soonest first.
+async fn send_scheduled(client: &Client, tx: &mpsc::Sender<BackendEvent>, chat_id: String, peer: tl::enums::InputPeer) {
+    let messages = match client.invoke(&tl::functions::messages::GetScheduledHistory { peer, hash: 0 }).await {
+        Ok(tl::enums::messages::Messages::Messages(messages)) => messages.messages,
+        Ok(tl::enums::messages::Messages::Slice(messages)) => messages.messages,
+        Ok(tl::enums::messages::Messages::ChannelMessages(messages)) => messages.messages,
+        Ok(tl::enums::messages::Messages::NotModified(_)) => Vec::new(),
+        Err(e) => {
+            let _ = tx.send(flood_wait_or(&e, format!("Failed to load scheduled messages: {}", e))).await;
+            return;
+        }
+    };
+    let mut messages: Vec<ScheduledMessage> = messages
+        .into_iter()
+        .filter_map(|message| match message {
+            // The date of a scheduled message is when it will be sent
+            tl::enums::Message::Message(message) => Some(ScheduledMessage {
+                id: message.id,
+                text: message.message,
+                at: chrono::DateTime::from_timestamp(message.date as i64, 0).unwrap_or_default(),
+            }),
+            _ => None,
+        })
+        .collect();
+    messages.sort_by_key(|message| message.at);
+    let _ = tx.send(BackendEvent::ScheduledLoaded { chat_id, messages }).await;
+}
+
+fn sent_message_id(updates: &[tl::enums::Update], random_id: i64) -> Option<i32> {
+    updates.iter().find_map(|update| match update {
+        tl::enums::Update::MessageId(update) if update.random_id == random_id => Some(update.id),
+        _ => None,
+    })
+}
+
+/// Converts a message for the GUI, fetching its photo thumbnail in the background.
+///
+/// `name_of` names the origin of forwards, which grammers doesn't resolve.
+fn message_info(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    message: &Message,
+    name_of: impl Fn(PeerId) -> Option<String>,
+) -> MessageInfo {
+    // Updates don't always carry the sender, `name_of` knows the ones seen before
+    let sender_id = message.sender().map(|s| s.id()).or_else(|| match &message.raw {
+        tl::enums::Message::Message(raw) if !message.outgoing() => Some(raw.from_id.clone().map_or(message.peer_id(), PeerId::from)),
+        _ => None,
+    });
+    let sender = match (message.sender(), sender_id) {
+        (Some(sender), _) => sender.name().unwrap_or("Unknown").to_string(),
+        (None, Some(id)) => name_of(id).unwrap_or_else(|| "Unknown".to_string()),
+        (None, None) => "Unknown".to_string(),
+    };
+    let sender_id = sender_id.map(|id| id.to_string()).unwrap_or_default();
+    let media = match message.media() {
+        Some(Media::Photo(photo)) => {
+            spawn_thumbnail_download(client.clone(), tx.clone(), message.id(), photo);
+            Some(MediaInfo::Photo)
+        }
+        Some(Media::Document(document)) => match &document.raw.document {
+            Some(tl::enums::Document::Document(raw)) if is_animation(raw) => Some(MediaInfo::Animation),
+            _ => Some(MediaInfo::Document {
+                name: document.name().to_string(),
+                size: document.size(),
+            }),
+        },
+        Some(Media::Sticker(sticker)) => Some(MediaInfo::Sticker {
+            file_id: sticker.document.id(),
+            emoji: sticker.emoji().to_string(),
+            animated: sticker.document.mime_type() != Some(STATIC_STICKER_MIME),
+        }),
+        Some(Media::Poll(poll)) => Some(MediaInfo::Poll(poll_info(&poll.raw, &poll.raw_results))),
+        _ => None,
+    };
+    MessageInfo {
+        id: message.id(),
+        text: message.text().to_string(),
+        entities: text_entities(message.text(), message.fmt_entities().map(Vec::as_slice).unwrap_or_default()),
+        reactions: match &message.raw {
+            tl::enums::Message::Message(raw) => raw.reactions.as_ref().map(reactions).unwrap_or_default(),
+            _ => Vec::new(),
+        },
+        reply_to: match &message.raw {
+            tl::enums::Message::Message(raw) => reply_to(raw),
+            _ => None,
+        },
+        webpage: match message.media() {
+            Some(Media::WebPage(page)) => webpage_info(&page.raw.webpage),
+            _ => None,
+        },
+        grouped_id: message.grouped_id(),
+        forward_from: message.forward_header().map(|header| {
+            forward_origin(&header, &name_of)
+        }),
+        pending: false,
+        sender,
+        sender_id,
+        date: message.date(),
+        date_label: format_timestamp(message.date()),
+        edit_date: message.edit_date(),
+        is_outgoing: message.outgoing(),
+        media,
+    }
+}
+
+/// The message `message` replies to, `None` when it's in another chat, where its ID means nothing here.
+fn reply_to(message: &tl::types::Message) -> Option<i32> {
+    let Some(tl::enums::MessageReplyHeader::Header(header)) = &message.reply_to else {
+        return None;
+    };
+    match &header.reply_to_peer_id {
+        Some(peer) if PeerId::from(peer.clone()) != PeerId::from(message.peer_id.clone()) => None,
+        _ => header.reply_to_msg_id,
+    }
+}
+
+/// The name of a chat or message sender seen before.
+fn known_name(
+    id: PeerId,
+    chats: &std::collections::HashMap<String, Peer>,
+    senders: &std::collections::HashMap<String, Peer>,
+) -> Option<String> {
+    let id = id.to_string();
+    chats.get(&id).or_else(|| senders.get(&id)).and_then(|peer| peer.name()).map(str::to_string)
+}
+
+/// The name of whoever a message was forwarded from, empty if `name_of` doesn't know them.
+fn forward_origin(header: &tl::enums::MessageFwdHeader, name_of: impl Fn(PeerId) -> Option<String>) -> String {
+    let tl::enums::MessageFwdHeader::Header(header) = header;
+    // Users who hide their account in forwards leave just a name
+    if let Some(name) = &header.from_name {
+        return name.clone();
+    }
+    header
+        .from_id
+        .clone()
+        .and_then(|peer| name_of(peer.into()))
+        .or_else(|| header.post_author.clone())
+        .unwrap_or_default()
+}
+
+fn reactions(reactions: &tl::enums::MessageReactions) -> Vec<Reaction> {
+    let tl::enums::MessageReactions::Reactions(reactions) = reactions;
+    reactions
+        .results
+        .iter()
+        .map(|result| {
+            let tl::enums::ReactionCount::Count(result) = result;
+            let emoji = match &result.reaction {
+                tl::enums::Reaction::Emoji(emoji) => emoji.emoticon.clone(),
+                tl::enums::Reaction::Paid => "⭐".to_string(),
+                // Custom emoji are stickers, there is no character to show for them
+                tl::enums::Reaction::CustomEmoji(_) | tl::enums::Reaction::Empty => "✨".to_string(),
+            };
+            Reaction { emoji, count: result.count, chosen: result.chosen_order.is_some() }
+        })
+        .collect()
+}
+
+/// Pulls the new reactions of each message out of the reply to a reaction request.
+fn reaction_updates(updates: tl::enums::Updates) -> Vec<(i32, Vec<Reaction>)> {
+    let updates = match updates {
+        tl::enums::Updates::Updates(updates) => updates.updates,
+        tl::enums::Updates::Combined(updates) => updates.updates,
+        tl::enums::Updates::UpdateShort(update) => vec![update.update],
+        _ => Vec::new(),
+    };
+    updates
+        .into_iter()
+        .filter_map(|update| match update {
+            tl::enums::Update::MessageReactions(update) => Some((update.msg_id, reactions(&update.reactions))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Link previews still being generated by Telegram have nothing to show yet.
+fn webpage_info(webpage: &tl::enums::WebPage) -> Option<WebpageInfo> {
+    let tl::enums::WebPage::Page(page) = webpage else {
+        return None;
+    };
+    Some(WebpageInfo {
+        url: page.url.clone(),
+        site_name: page.site_name.clone(),
+        title: page.title.clone(),
+        description: page.description.clone(),
+        has_photo: matches!(page.photo, Some(tl::enums::Photo::Photo(_))),
+    })
+}
+
+/// The photo of a link preview, which grammers doesn't wrap in a downloadable type.
+struct PreviewPhoto(tl::types::Photo);
+
+impl Downloadable for PreviewPhoto {
+    fn to_raw_input_location(&self) -> Option<tl::enums::InputFileLocation> {
+        // Same 320px box thumbnail as message photos, or whatever the largest size is
+        let mut sizes = self.0.sizes.iter().filter_map(|size| match size {
+            tl::enums::PhotoSize::Size(size) => Some(size.r#type.as_str()),
+            _ => None,
+        });
+        let thumb_size = sizes.clone().find(|&t| t == "m").or_else(|| sizes.next_back())?;
+        Some(
+            tl::types::InputPhotoFileLocation {
+                id: self.0.id,
+                access_hash: self.0.access_hash,
+                file_reference: self.0.file_reference.clone(),
+                thumb_size: thumb_size.to_string(),
+            }
+            .into(),
+        )
+    }
+}
+
+/// Whether a document is a GIF, which Telegram marks with the animated attribute.
+fn is_animation(document: &tl::types::Document) -> bool {
+    document.attributes.iter().any(|attribute| matches!(attribute, tl::enums::DocumentAttribute::Animated))
+}
+
+/// The thumbnail of a document, which grammers doesn't expose as downloadable.
+struct DocumentThumb(tl::types::Document);
+
+impl Downloadable for DocumentThumb {
+    fn to_raw_input_location(&self) -> Option<tl::enums::InputFileLocation> {
+        let mut sizes = self.0.thumbs.iter().flatten().filter_map(|size| match size {
+            tl::enums::PhotoSize::Size(size) => Some(size.r#type.as_str()),
+            _ => None,
+        });
+        let thumb_size = sizes.clone().find(|&t| t == "m").or_else(|| sizes.next_back())?;
+        Some(
+            tl::types::InputDocumentFileLocation {
+                id: self.0.id,
+                access_hash: self.0.access_hash,
+                file_reference: self.0.file_reference.clone(),
+                thumb_size: thumb_size.to_string(),
+            }
+            .into(),
+        )
+    }
+}
+
+fn spawn_animation_download(client: Client, tx: mpsc::Sender<BackendEvent>, peer: Peer, message_id: i32) {
+    tokio::spawn(async move {
+        let message = match client.get_messages_by_id(&peer, &[message_id]).await {
+            Ok(messages) => messages.into_iter().next().flatten(),
+            Err(e) => {
+                log::warn!("Failed to load animation of message {}: {}", message_id, e);
+                return;
+            }
+        };
+        let Some(Media::Document(document)) = message.and_then(|m| m.media()) else {
+            return;
+        };
+        let Some(tl::enums::Document::Document(raw)) = document.raw.document.clone() else {
+            return;
+        };
+        // Too big to be worth downloading and decoding, the thumbnail will do
+        let download = if document.size() > animation::MAX_SIZE {
+            Err(format!("{} bytes is too big to play", document.size()))
+        } else {
+            download_bytes(&client, &document).await.map_err(|e| e.to_string())
+        };
+        let result = match download {
+            Ok(bytes) => {
+                let mime_type = raw.mime_type.clone();
+                let scratch = std::env::temp_dir().join(format!("telegram_animation_{}_{}", peer.id(), message_id));
+                // Decoding runs ffmpeg and goes through every frame, too slow for the async threads
+                tokio::task::spawn_blocking(move || animation::decode(&bytes, &mime_type, &scratch))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(frames) => {
+                let _ = tx.send(BackendEvent::AnimationLoaded { message_id, frames }).await;
+            }
+            Err(e) => {
+                log::warn!("Failed to play animation of message {}, showing its thumbnail: {}", message_id, e);
+                match download_bytes(&client, &DocumentThumb(raw)).await {
+                    Ok(bytes) => {
+                        let _ = tx.send(BackendEvent::MediaDownloaded { message_id, bytes }).await;
+                    }
+                    Err(e) => log::warn!("Failed to download thumbnail of message {}: {}", message_id, e),
+                }
+            }
+        }
+    });
+}
+
+fn spawn_sticker_download(client: Client, tx: mpsc::Sender<BackendEvent>, peer: Peer, message_id: i32, file_id: i64) {
+    tokio::spawn(async move {
+        let message = match client.get_messages_by_id(&peer, &[message_id]).await {
+            Ok(messages) => messages.into_iter().next().flatten(),
+            Err(e) => {
+                log::warn!("Failed to load sticker of message {}: {}", message_id, e);
+                return;
+            }
+        };
+        let Some(Media::Sticker(sticker)) = message.and_then(|m| m.media()) else {
+            return;
+        };
+        match download_bytes(&client, &sticker.document).await {
+            Ok(bytes) => {
+                let _ = tx.send(BackendEvent::StickerDownloaded { file_id, bytes }).await;
+            }
+            Err(e) => {
+                log::warn!("Failed to download sticker {}: {}", file_id, e);
+            }
+        }
+    });
+}
+
+fn spawn_preview_download(client: Client, tx: mpsc::Sender<BackendEvent>, peer: Peer, message_id: i32) {
+    tokio::spawn(async move {
+        let message = match client.get_messages_by_id(&peer, &[message_id]).await {
+            Ok(messages) => messages.into_iter().next().flatten(),
+            Err(e) => {
+                log::warn!("Failed to load link preview of message {}: {}", message_id, e);
+                return;
+            }
+        };
+        let Some(Media::WebPage(page)) = message.and_then(|m| m.media()) else {
+            return;
+        };
+        let tl::enums::WebPage::Page(page) = page.raw.webpage else {
+            return;
+        };
+        let Some(tl::enums::Photo::Photo(photo)) = page.photo else {
+            return;
+        };
+        match download_bytes(&client, &PreviewPhoto(photo)).await {
+            Ok(bytes) => {
+                let _ = tx.send(BackendEvent::MediaDownloaded { message_id, bytes }).await;
+            }
+            Err(e) => {
+                log::warn!("Failed to download link preview of message {}: {}", message_id, e);
+            }
+        }
+    });
+}
+
+fn poll_info(poll: &tl::types::Poll, results: &tl::types::PollResults) -> PollInfo {
+    // Counts are missing until we've voted, unless the poll is closed
+    let counts = results.results.as_deref().unwrap_or_default();
+    let options = poll
+        .answers
+        .iter()
+        .map(|answer| {
+            let tl::enums::PollAnswer::Answer(answer) = answer;
+            let tl::enums::TextWithEntities::Entities(text) = &answer.text;
+            let count = counts.iter().find_map(|count| {
+                let tl::enums::PollAnswerVoters::Voters(count) = count;
+                (count.option == answer.option).then_some(count)
+            });
+            PollOption {
+                text: text.text.clone(),
+                voters: count.map_or(0, |c| c.voters),
+                chosen: count.is_some_and(|c| c.chosen),
+                correct: count.is_some_and(|c| c.correct),
+            }
+        })
+        .collect();
+    let tl::enums::TextWithEntities::Entities(question) = &poll.question;
+    PollInfo {
+        question: question.text.clone(),
+        options,
+        multiple_choice: poll.multiple_choice,
+        quiz: poll.quiz,
+        closed: poll.closed,
+        total_voters: results.total_voters.unwrap_or(0),
+        solution: results.solution.clone(),
+    }
+}
+
+/// Votes in the poll of `message_id`, returning the poll with its new results.
+///
+/// `None` means the message no longer holds a poll.
+async fn vote_poll(
+    client: &Client,
+    peer: &Peer,
+    message_id: i32,
+    option_indices: &[usize],
+) -> Result<Option<PollInfo>, InvocationError> {
+    let message = client.get_messages_by_id(peer, &[message_id]).await?.into_iter().next().flatten();
+    let (Some(Media::Poll(poll)), Some(input)) = (message.and_then(|m| m.media()), input_peer(peer)) else {
+        return Ok(None);
+    };
+    // Votes name options by their opaque byte IDs, not by position
+    let options = option_indices
+        .iter()
+        .filter_map(|&index| poll.raw.answers.get(index))
+        .map(|answer| {
+            let tl::enums::PollAnswer::Answer(answer) = answer;
+            answer.option.clone()
+        })
+        .collect();
+    let updates = client.invoke(&tl::functions::messages::SendVote { peer: input, msg_id: message_id, options }).await?;
+    let updates = match updates {
+        tl::enums::Updates::Updates(updates) => updates.updates,
+        tl::enums::Updates::Combined(updates) => updates.updates,
+        tl::enums::Updates::UpdateShort(update) => vec![update.update],
+        _ => Vec::new(),
+    };
+    let update = updates.into_iter().find_map(|update| match update {
+        tl::enums::Update::MessagePoll(update) => Some((update.poll, update.results)),
+        _ => None,
+    });
+    let (raw, results) = match update {
+        Some((raw, tl::enums::PollResults::Results(results))) => (raw, results),
+        None => (None, poll.raw_results.clone()),
+    };
+    let raw = match raw {
+        Some(tl::enums::Poll::Poll(raw)) => raw,
+        None => poll.raw.clone(),
+    };
+    Ok(Some(poll_info(&raw, &results)))
+}
+
+/// Reactions offered when a chat allows all of them, the standard set every account has.
+const DEFAULT_REACTIONS: &[&str] = &["👍", "👎", "❤", "🔥", "🥰", "👏", "😁", "🤔", "🤯", "😱", "😢", "🎉", "🤩", "🙏", "👌", "😍"];
+
+/// The emoji we may react with in `peer`, empty when reactions are turned off.
+async fn allowed_reactions(client: &Client, peer: &Peer) -> Vec<String> {
+    let defaults = || DEFAULT_REACTIONS.iter().map(|emoji| emoji.to_string()).collect();
+    let full = match input_peer(peer) {
+        Some(tl::enums::InputPeer::Chat(chat)) => {
+            client.invoke(&tl::functions::messages::GetFullChat { chat_id: chat.chat_id }).await
+        }
+        Some(tl::enums::InputPeer::Channel(channel)) => {
+            let channel = tl::types::InputChannel { channel_id: channel.channel_id, access_hash: channel.access_hash };
+            client.invoke(&tl::functions::channels::GetFullChannel { channel: channel.into() }).await
+        }
+        _ => return defaults(),
+    };
+    let tl::enums::messages::ChatFull::Full(full) = match full {
+        Ok(full) => full,
+        Err(e) => {
+            log::warn!("Failed to load chat reactions: {}", e);
+            return defaults();
+        }
+    };
+    let available = match full.full_chat {
+        tl::enums::ChatFull::Full(chat) => chat.available_reactions,
+        tl::enums::ChatFull::ChannelFull(channel) => channel.available_reactions,
+    };
+    match available {
+        None | Some(tl::enums::ChatReactions::All(_)) => defaults(),
+        Some(tl::enums::ChatReactions::None) => Vec::new(),
+        Some(tl::enums::ChatReactions::Some(some)) => some
+            .reactions
+            .into_iter()
+            .filter_map(|reaction| match reaction {
+                tl::enums::Reaction::Emoji(emoji) => Some(emoji.emoticon),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+/// Reports how long Telegram wants us to back off, if `error` is a FLOOD_WAIT.
+fn flood_wait(error: &InvocationError) -> Option<Duration> {
+    match error {
+        InvocationError::Rpc(rpc) if rpc.name == "FLOOD_WAIT" => Some(Duration::from_secs(rpc.value.unwrap_or(0).into())),
+        _ => None,
+    }
+}
+
+/// Explains why connecting through a proxy failed.
+fn proxy_error(error: &InvocationError) -> String {
+    let message = error.to_string();
+    // SOCKS5 handshake errors only come as I/O errors, so the message is all there is to go on
+    if message.to_lowercase().contains("auth") {
+        format!("The proxy rejected the username or password: {}", message)
+    } else {
+        format!("Can't connect through the proxy: {}", message)
+    }
+}
+
+/// Reaches Telegram through `proxy` on a throwaway connection, so a broken proxy never replaces one that works.
+fn spawn_proxy_check(
+    session: Arc<SqliteSession>,
+    api_id: i32,
+    proxy: Option<ProxyConfig>,
+    check: u64,
+    checked_tx: mpsc::UnboundedSender<(u64, Option<ProxyConfig>, Result<(), String>)>,
+) {
+    tokio::spawn(async move {
+        let pool = sender_pool(&session, api_id, proxy.as_ref());
+        let client = Client::new(&pool);
+        let SenderPool { runner, handle, .. } = pool;
+        tokio::spawn(runner.run());
+        let result = match tokio::time::timeout(PROXY_TEST_TIMEOUT, client.is_authorized()).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) if proxy.is_some() => Err(proxy_error(&e)),
+            Ok(Err(e)) => Err(format!("Can't connect to Telegram: {}", e)),
+            Err(_) => Err("Timed out reaching Telegram".to_string()),
+        };
+        handle.quit();
+        let _ = checked_tx.send((check, proxy, result));
+    });
+}
+
+/// Opens a plain TCP connection to the proxy, enough to tell a wrong address or a server that is down.
+fn spawn_proxy_test(tx: mpsc::Sender<BackendEvent>, host: String, port: u16) {
+    tokio::spawn(async move {
+        let started = std::time::Instant::now();
+        let result = match tokio::time::timeout(PROXY_TEST_TIMEOUT, tokio::net::TcpStream::connect((host.as_str(), port))).await {
+            Ok(Ok(_)) => Ok(started.elapsed()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("timed out".to_string()),
+        };
+        let _ = tx.send(BackendEvent::ProxyTested { host, port, result }).await;
+    });
+}
+
+/// Turns `error` into a `FloodWait` or `SessionExpired` event when it is one, otherwise into an `Error` with `message`.
+fn flood_wait_or(error: &InvocationError, message: String) -> BackendEvent {
+    match flood_wait(error) {
+        Some(wait) => BackendEvent::FloodWait(wait),
+        None if is_session_lost(error) => BackendEvent::SessionExpired,
+        None => BackendEvent::Error(BackendError { kind: error_kind(error), message }),
+    }
+}
+
+/// Errors meaning the session's authorization is gone for good, so only logging in again helps.
+fn is_session_lost(error: &InvocationError) -> bool {
+    matches!(
+        error,
+        InvocationError::Rpc(rpc) if matches!(
+            rpc.name.as_str(),
+            "AUTH_KEY_UNREGISTERED" | "AUTH_KEY_INVALID" | "AUTH_KEY_PERM_EMPTY" | "SESSION_REVOKED" | "SESSION_EXPIRED" | "USER_DEACTIVATED" | "USER_DEACTIVATED_BAN"
+        )
+    )
+}
+
+/// Sorts an RPC or connection failure into one of the kinds the GUI has advice for.
+fn error_kind(error: &InvocationError) -> ErrorKind {
+    match error {
+        InvocationError::Rpc(rpc) => match rpc.name.as_str() {
+            "FLOOD_WAIT" | "FLOOD_PREMIUM_WAIT" | "SLOWMODE_WAIT" => ErrorKind::FloodWait,
+            "PHONE_NUMBER_INVALID" => ErrorKind::PhoneInvalid,
+            "PHONE_NUMBER_BANNED" => ErrorKind::PhoneBanned,
+            "PHONE_CODE_INVALID" | "PHONE_CODE_EMPTY" => ErrorKind::CodeInvalid,
+            "PHONE_CODE_EXPIRED" => ErrorKind::CodeExpired,
+            "PASSWORD_HASH_INVALID" => ErrorKind::PasswordWrong,
+            "ACCESS_TOKEN_INVALID" | "ACCESS_TOKEN_EXPIRED" => ErrorKind::BotTokenInvalid,
+            // 401 is Telegram's code for everything that needs a new login
+            _ if rpc.code == 401 => ErrorKind::AuthRequired,
+            _ => ErrorKind::Other,
+        },
+        InvocationError::Io(_) | InvocationError::Transport(_) | InvocationError::Dropped => ErrorKind::Network,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Like `flood_wait_or`, for the errors of grammers' own login calls.
+fn sign_in_error(error: SignInError) -> BackendEvent {
+    let kind = match &error {
+        SignInError::Other(e) => return flood_wait_or(e, e.to_string()),
+        SignInError::InvalidCode => ErrorKind::CodeInvalid,
+        SignInError::InvalidPassword => ErrorKind::PasswordWrong,
+        _ => ErrorKind::Other,
+    };
+    BackendEvent::Error(BackendError { kind, message: error.to_string() })
+}
+
+/// Builds the input peer for a chat ID from the session's peer cache, `None` if it was never seen.
+fn cached_input_peer(session: &SqliteSession, chat_id: &str) -> Option<tl::enums::InputPeer> {
+    let id: i64 = chat_id.parse().ok()?;
+    let peer_id = match id {
+        1..=0xffffffffff => PeerId::user(id),
+        -999999999999..=-1 => PeerId::chat(-id),
+        _ => match -id - 1000000000000 {
+            bare @ (1..=997852516352 | 1002147483649..=3000000000000) => PeerId::channel(bare),
+            _ => return None,
+        },
+    };
+    let peer = session.peer(peer_id)?;
+    Some(PeerRef::from(peer).into())
+}
+
+/// Builds the input peer from the raw chat, `None` if it's empty or we lack its access hash.
+fn input_peer(peer: &Peer) -> Option<tl::enums::InputPeer> {
+    let channel = |channel_id, access_hash| tl::types::InputPeerChannel { channel_id, access_hash }.into();
+    match peer {
+        Peer::User(user) => match &user.raw {
+            tl::enums::User::User(user) if user.is_self => Some(tl::enums::InputPeer::PeerSelf),
+            tl::enums::User::User(user) => user
+                .access_hash
+                .map(|access_hash| tl::types::InputPeerUser { user_id: user.id, access_hash }.into()),
+            tl::enums::User::Empty(_) => None,
+        },
+        Peer::Channel(raw) => raw.raw.access_hash.map(|access_hash| channel(raw.raw.id, access_hash)),
+        Peer::Group(group) => match &group.raw {
+            tl::enums::Chat::Chat(chat) => Some(tl::types::InputPeerChat { chat_id: chat.id }.into()),
+            tl::enums::Chat::Forbidden(chat) => Some(tl::types::InputPeerChat { chat_id: chat.id }.into()),
+            tl::enums::Chat::Channel(raw) => raw.access_hash.map(|access_hash| channel(raw.id, access_hash)),
+            tl::enums::Chat::ChannelForbidden(raw) => Some(channel(raw.id, raw.access_hash)),
+            tl::enums::Chat::Empty(_) => None,
+        },
+    }
+}
+
+/// Sends the next page of the main chat list one chat at a time, so the list fills in as it arrives.
+async fn load_chat_page(tx: &mpsc::Sender<BackendEvent>, state: &mut BackgroundState) {
+    let Some(dialogs) = state.dialogs.as_mut() else {
+        let _ = tx.send(BackendEvent::ChatsLoadingFinished { all_loaded: true }).await;
+        return;
+    };
+    let mut loaded = 0;
+    let all_loaded = loop {
+        let dialog = match dialogs.next().await {
+            Ok(Some(dialog)) => dialog,
+            Ok(None) => break true,
+            Err(e) => {
+                // The iterator is kept, so loading more retries the same page
+                let _ = tx.send(flood_wait_or(&e, format!("Failed to load chats: {}", e))).await;
+                break false;
+            }
+        };
+        let chat = dialog.peer();
+        let (unread_count, muted, read_inbox_max_id, read_outbox_max_id) = match &dialog.raw {
+            tl::enums::Dialog::Dialog(raw) => {
+                (raw.unread_count, is_muted(&raw.notify_settings), raw.read_inbox_max_id, raw.read_outbox_max_id)
+            }
+            tl::enums::Dialog::Folder(_) => (0, false, 0, 0),
+        };
+        let (draft, pinned) = match &dialog.raw {
+            tl::enums::Dialog::Dialog(raw) => (draft_text(raw.draft.as_ref()), raw.pinned),
+            tl::enums::Dialog::Folder(_) => (None, false),
+        };
+        let (last_message, last_message_date) = match dialog.last_message.as_ref() {
+            Some(message) => (message_preview(message), format_timestamp(message.date())),
+            None => (String::new(), String::new()),
+        };
+        state.chat_map.insert(chat.id().to_string(), chat.clone());
+        let _ = tx
+            .send(BackendEvent::ChatLoaded(ChatInfo {
+                unread_count,
+                read_inbox_max_id,
+                read_outbox_max_id,
+                last_message,
+                last_message_date,
+                muted,
+                mute_known: true,
+                draft,
+                pinned,
+                ..chat_info(chat)
+            }))
+            .await;
+        loaded += 1;
+        if loaded >= CHAT_PAGE_SIZE {
+            break false;
+        }
+    };
+    if all_loaded {
+        state.dialogs = None;
+    }
+    // Folders can only hold chats that were loaded, so each page may add to them
+    if loaded > 0 && !state.folders.is_empty() {
+        send_folders(tx, state).await;
+    }
+    let _ = tx.send(BackendEvent::ChatsLoadingFinished { all_loaded }).await;
+}
+
+/// Marks everything in `peer` as read on the server, so other devices clear it too.
+async fn mark_read_and_report(client: &Client, tx: &mpsc::Sender<BackendEvent>, peer: &Peer, chat_id: String) {
+    match client.mark_as_read(peer).await {
+        Ok(()) => {
+            let _ = tx.send(BackendEvent::ChatRead(chat_id)).await;
+        }
+        Err(e) => log::warn!("Failed to mark chat as read: {}", e),
+    }
+}
+
+/// Deletes every message in `peer`, for everyone if `for_everyone`, keeping the dialog unless `delete_chat`.
+async fn delete_history(client: &Client, peer: &Peer, delete_chat: bool, for_everyone: bool) -> Result<(), InvocationError> {
+    let Some(input) = input_peer(peer) else {
+        return Ok(());
+    };
+    if let tl::enums::InputPeer::Channel(channel) = input {
+        // Channels delete up to a message ID rather than everything
+        let Some(latest) = client.iter_messages(peer).limit(1).next().await? else {
+            return Ok(());
+        };
+        let channel = tl::types::InputChannel { channel_id: channel.channel_id, access_hash: channel.access_hash };
+        client
+            .invoke(&tl::functions::channels::DeleteHistory { for_everyone, channel: channel.into(), max_id: latest.id() })
+            .await?;
+        return Ok(());
+    }
+    // Big histories are deleted in batches, with an offset reported while there is more to go
+    loop {
+        let request = tl::functions::messages::DeleteHistory {
+            just_clear: !delete_chat,
+            revoke: for_everyone,
+            peer: input.clone(),
+            max_id: 0,
+            min_date: None,
+            max_date: None,
+        };
+        let tl::enums::messages::AffectedHistory::History(affected) = client.invoke(&request).await?;
+        if affected.offset <= 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn contact_info(peer: &Peer) -> Option<ContactInfo> {
+    let Peer::User(user) = peer else {
+        return None;
+    };
+    Some(ContactInfo {
+        id: PeerId::user(user.bare_id()).to_string(),
+        name: user.full_name(),
+        username: user.username().map(str::to_string),
+        phone: user.phone().map(str::to_string),
+    })
+}
+
+/// The text of a draft saved on the server, `None` when there is none.
+fn draft_text(draft: Option<&tl::enums::DraftMessage>) -> Option<String> {
+    match draft {
+        Some(tl::enums::DraftMessage::Message(draft)) if !draft.message.is_empty() => Some(draft.message.clone()),
+        _ => None,
+    }
+}
+
+/// Describes a chat that isn't in the dialog list, so it has no preview or unread count.
+fn chat_info(peer: &Peer) -> ChatInfo {
+    ChatInfo {
+        name: peer.name().unwrap_or("Unknown").to_string(),
+        id: peer.id().to_string(),
+        username: peer.username().map(str::to_string),
+        unread_count: 0,
+        read_inbox_max_id: 0,
+        read_outbox_max_id: 0,
+        last_message: String::new(),
+        last_message_date: String::new(),
+        muted: false,
+        mute_known: false,
+        draft: None,
+        channel_id: channel_id(peer),
+        is_group: !matches!(peer, Peer::User(_)),
+        is_owner: is_owner(peer),
+        pinned: false,
+    }
+}
+
+/// Looks up a user or chat by `+phone` or username.
+/// Looks chats up on the server by name or username, caching them so they can be used right away.
+async fn search_chats(client: &Client, state: &mut BackgroundState, query: String) -> Result<Vec<ChatInfo>, InvocationError> {
+    let tl::enums::contacts::Found::Found(found) = client.invoke(&tl::functions::contacts::Search { q: query, limit: 50 }).await?;
+    let peers = PeerMap::new(found.users, found.chats);
+    let mut chat_infos = Vec::new();
+    for peer in found.my_results.into_iter().chain(found.results) {
+        let Some(chat) = peers.get(peer.into()) else {
+            continue;
+        };
+        let id = chat.id().to_string();
+        if chat_infos.iter().any(|c: &ChatInfo| c.id == id) {
+            continue;
+        }
+        state.chat_map.insert(id, chat.clone());
+        chat_infos.push(chat_info(chat));
+    }
+    Ok(chat_infos)
+}
+
+async fn resolve_chat(client: &Client, query: &str) -> Result<Option<Peer>, InvocationError> {
+    let Some(phone) = query.strip_prefix('+') else {
+        return client.resolve_username(query).await;
+    };
+    let tl::enums::contacts::ResolvedPeer::Peer(resolved) =
+        client.invoke(&tl::functions::contacts::ResolvePhone { phone: phone.to_string() }).await?;
+    let peers = PeerMap::new(resolved.users, resolved.chats);
+    Ok(peers.get(resolved.peer.into()).cloned())
+}
+
+/// Finds the chat a `tg://resolve?domain=` or `tg://user?id=` link points to.
+async fn resolve_deep_link(
+    client: &Client,
+    chat_map: &std::collections::HashMap<String, Peer>,
+    link: &str,
+) -> Result<Peer, String> {
+    let rest = link.strip_prefix("tg://").unwrap_or(link);
+    let (action, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let param = |key: &str| query.split('&').find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='));
+    match action {
+        "resolve" => {
+            let domain = param("domain").ok_or("Link has no username")?;
+            client
+                .resolve_username(domain)
+                .await
+                .map_err(|e| format!("Failed to resolve @{}: {}", domain, e))?
+                .ok_or_else(|| format!("@{} doesn't exist", domain))
+        }
+        "user" => {
+            let id: i64 = param("id").and_then(|id| id.parse().ok()).ok_or("Link has no user ID")?;
+            // Users can't be looked up by ID alone, only ones we have already seen have an access hash
+            chat_map
+                .values()
+                .find(|peer| peer.id() == PeerId::user(id))
+                .cloned()
+                .ok_or_else(|| "That user isn't in your chat list".to_string())
+        }
+        _ => Err(format!("Unsupported link: {}", link)),
+    }
+}
+
+enum JoinOutcome {
+    /// The chat, and whether we were in it before.
+    Joined(Box<Peer>, bool),
+    /// Title of a chat whose admins have to approve new members.
+    RequestSent(String),
+}
+
+/// Joins the chat behind an invite link or a public `t.me/name` link.
+async fn join_chat(client: &Client, link: &str) -> Result<JoinOutcome, String> {
+    let link = link.trim();
+    let hash = if let Some(hash) = link.strip_prefix("tg://join?invite=") {
+        hash
+    } else {
+        let rest = link.trim_start_matches("https://").trim_start_matches("http://");
+        let path = rest
+            .strip_prefix("t.me/")
+            .or_else(|| rest.strip_prefix("telegram.me/"))
+            .ok_or_else(|| format!("Not a Telegram link: {}", link))?;
+        match path.strip_prefix('+').or_else(|| path.strip_prefix("joinchat/")) {
+            Some(hash) => hash,
+            None => return join_public_chat(client, path.split(['/', '?']).next().unwrap_or(path)).await,
+        }
+    };
+    let hash = hash.split(['/', '?']).next().unwrap_or(hash);
+
+    let invite = client
+        .invoke(&tl::functions::messages::CheckChatInvite { hash: hash.to_string() })
+        .await
+        .map_err(join_error)?;
+    let title = match invite {
+        tl::enums::ChatInvite::Already(already) => {
+            let peer = chat_from_raw(vec![already.chat]).ok_or("Invite link points to an unknown chat")?;
+            return Ok(JoinOutcome::Joined(Box::new(peer), true));
+        }
+        tl::enums::ChatInvite::Invite(invite) => invite.title,
+        tl::enums::ChatInvite::Peek(peek) => chat_from_raw(vec![peek.chat])
+            .and_then(|peer| peer.name().map(str::to_string))
+            .unwrap_or_default(),
+    };
+    match client.invoke(&tl::functions::messages::ImportChatInvite { hash: hash.to_string() }).await {
+        Ok(updates) => {
+            let chats = match updates {
+                tl::enums::Updates::Updates(updates) => updates.chats,
+                tl::enums::Updates::Combined(updates) => updates.chats,
+                _ => Vec::new(),
+            };
+            let peer = chat_from_raw(chats).ok_or("Joined, but Telegram didn't say which chat")?;
+            Ok(JoinOutcome::Joined(Box::new(peer), false))
+        }
+        Err(InvocationError::Rpc(rpc)) if rpc.name == "INVITE_REQUEST_SENT" => Ok(JoinOutcome::RequestSent(title)),
+        Err(e) => Err(join_error(e)),
+    }
+}
+
+/// Joins a public channel or supergroup by its username.
+async fn join_public_chat(client: &Client, username: &str) -> Result<JoinOutcome, String> {
+    let peer = client
+        .resolve_username(username)
+        .await
+        .map_err(|e| format!("Failed to resolve @{}: {}", username, e))?
+        .ok_or_else(|| format!("@{} doesn't exist", username))?;
+    let Some(tl::enums::InputPeer::Channel(channel)) = input_peer(&peer) else {
+        return Err(format!("@{} isn't a channel or group you can join", username));
+    };
+    let request = tl::functions::channels::JoinChannel {
+        channel: tl::types::InputChannel { channel_id: channel.channel_id, access_hash: channel.access_hash }.into(),
+    };
+    match client.invoke(&request).await {
+        Ok(_) => Ok(JoinOutcome::Joined(Box::new(peer), false)),
+        Err(InvocationError::Rpc(rpc)) if rpc.name == "INVITE_REQUEST_SENT" => {
+            Ok(JoinOutcome::RequestSent(peer.name().unwrap_or(username).to_string()))
+        }
+        Err(e) => Err(join_error(e)),
+    }
+}
+
+fn join_error(error: InvocationError) -> String {
+    match &error {
+        InvocationError::Rpc(rpc) => match rpc.name.as_str() {
+            "USER_ALREADY_PARTICIPANT" => "You're already a member of this chat".to_string(),
+            "INVITE_HASH_EXPIRED" => "This invite link has expired".to_string(),
+            "INVITE_HASH_INVALID" | "INVITE_HASH_EMPTY" => "This invite link isn't valid".to_string(),
+            "CHANNELS_TOO_MUCH" => "You're in too many channels and groups to join another".to_string(),
+            _ => format!("Failed to join chat: {}", error),
+        },
+        _ => format!("Failed to join chat: {}", error),
+    }
+}
+
+/// Picks the first usable chat out of a raw chat list.
+fn chat_from_raw(chats: Vec<tl::enums::Chat>) -> Option<Peer> {
+    let ids: Vec<PeerId> = chats
+        .iter()
+        .map(|chat| match chat {
+            tl::enums::Chat::Empty(chat) => PeerId::chat(chat.id),
+            tl::enums::Chat::Chat(chat) => PeerId::chat(chat.id),
+            tl::enums::Chat::Forbidden(chat) => PeerId::chat(chat.id),
+            tl::enums::Chat::Channel(channel) => PeerId::channel(channel.id),
+            tl::enums::Chat::ChannelForbidden(channel) => PeerId::channel(channel.id),
+        })
+        .collect();
+    let peers = PeerMap::new(Vec::new(), chats);
+    ids.into_iter().find_map(|id| peers.get(id).cloned())
+}
+
+/// Moves a chat in or out of the archive folder.
+async fn set_archived(client: &Client, tx: &mpsc::Sender<BackendEvent>, state: &BackgroundState, chat_id: String, archived: bool) {
+    let Some(peer) = state.chat_map.get(&chat_id).and_then(input_peer) else {
+        let _ = tx.send(BackendEvent::Error("Chat not found in cache".into())).await;
+        return;
+    };
+    let folder_id = if archived { ARCHIVE_FOLDER_ID } else { MAIN_FOLDER_ID };
+    let request = tl::functions::folders::EditPeerFolders {
+        folder_peers: vec![tl::types::InputFolderPeer { peer, folder_id }.into()],
+    };
+    match client.invoke(&request).await {
+        Ok(_) => {
+            let _ = tx.send(BackendEvent::ArchiveChanged { chat_id, archived }).await;
+        }
+        Err(e) => {
+            let _ = tx.send(flood_wait_or(&e, format!("Failed to move chat: {}", e))).await;
+        }
+    }
+}
+
+async fn send_folders(tx: &mpsc::Sender<BackendEvent>, state: &BackgroundState) {
+    let folders = state.folders.iter().filter_map(|filter| folder_info(filter, state)).collect();
+    let _ = tx.send(BackendEvent::FoldersLoaded(folders)).await;
+}
+
+/// Works out which known chats belong to a dialog filter.
+///
+/// Returns `None` for the built-in "All chats" entry, which the GUI always shows as its own tab.
+fn folder_info(filter: &tl::enums::DialogFilter, state: &BackgroundState) -> Option<FolderInfo> {
+    let me = state.me.as_ref();
+    let ids = |peers: &[tl::enums::InputPeer]| -> Vec<PeerId> {
+        peers.iter().filter_map(|peer| input_peer_id(peer, me)).collect()
+    };
+    let (id, title, chat_ids, exclude_muted, exclude_read) = match filter {
+        tl::enums::DialogFilter::Default => return None,
+        tl::enums::DialogFilter::Filter(filter) => {
+            let include = [ids(&filter.pinned_peers), ids(&filter.include_peers)].concat();
+            let exclude = ids(&filter.exclude_peers);
+            let chat_ids = state
+                .chat_map
+                .values()
+                .filter(|peer| {
+                    let id = peer.id();
+                    if exclude.contains(&id) {
+                        return false;
+                    }
+                    include.contains(&id)
+                        || match peer {
+                            Peer::User(user) if user.is_bot() => filter.bots,
+                            Peer::User(user) if user.contact() => filter.contacts,
+                            Peer::User(_) => filter.non_contacts,
+                            Peer::Group(_) => filter.groups,
+                            Peer::Channel(_) => filter.broadcasts,
+                        }
+                })
+                .map(|peer| peer.id().to_string())
+                .collect();
+            (filter.id, text_with_entities(&filter.title), chat_ids, filter.exclude_muted, filter.exclude_read)
+        }
+        // Shared folders are only ever an explicit list of chats
+        tl::enums::DialogFilter::Chatlist(filter) => {
+            let include = [ids(&filter.pinned_peers), ids(&filter.include_peers)].concat();
+            let chat_ids = state
+                .chat_map
+                .values()
+                .filter(|peer| include.contains(&peer.id()))
+                .map(|peer| peer.id().to_string())
+                .collect();
+            (filter.id, text_with_entities(&filter.title), chat_ids, false, false)
+        }
+    };
+    Some(FolderInfo { id, title, chat_ids, exclude_muted, exclude_read })
+}
+
+fn text_with_entities(text: &tl::enums::TextWithEntities) -> String {
+    match text {
+        tl::enums::TextWithEntities::Entities(text) => text.text.clone(),
+    }
+}
+
+/// Returns the peer an input peer refers to, without needing an access hash.
+fn input_peer_id(peer: &tl::enums::InputPeer, me: Option<&User>) -> Option<PeerId> {
+    match peer {
+        tl::enums::InputPeer::Empty => None,
+        tl::enums::InputPeer::PeerSelf => me.map(|me| PeerId::user(me.bare_id())),
+        tl::enums::InputPeer::User(user) => Some(PeerId::user(user.user_id)),
+        tl::enums::InputPeer::UserFromMessage(user) => Some(PeerId::user(user.user_id)),
+        tl::enums::InputPeer::Chat(chat) => Some(PeerId::chat(chat.chat_id)),
+        tl::enums::InputPeer::Channel(channel) => Some(PeerId::channel(channel.channel_id)),
+        tl::enums::InputPeer::ChannelFromMessage(channel) => Some(PeerId::channel(channel.channel_id)),
+    }
+}
+
+/// Returns the member count, the page of members starting at `offset` and whether it was the last page.
+async fn load_members(client: &Client, peer: &Peer, offset: usize) -> Result<(usize, Vec<MemberInfo>, bool), InvocationError> {
+    let Some(tl::enums::InputPeer::Channel(channel)) = input_peer(peer) else {
+        // Small groups list all their members at once
+        let mut participants = client.iter_participants(peer);
+        let mut members = Vec::new();
+        while let Some(participant) = participants.next().await? {
+            members.push(member_info(&participant));
+        }
+        return Ok((members.len(), members, true));
+    };
+    let request = tl::functions::channels::GetParticipants {
+        channel: tl::types::InputChannel { channel_id: channel.channel_id, access_hash: channel.access_hash }.into(),
+        filter: tl::enums::ChannelParticipantsFilter::ChannelParticipantsRecent,
+        offset: offset as i32,
+        limit: MEMBER_PAGE_SIZE as i32,
+        hash: 0,
+    };
+    let tl::enums::channels::ChannelParticipants::Participants(page) = client.invoke(&request).await? else {
+        return Ok((offset, Vec::new(), true));
+    };
+    let users: std::collections::HashMap<i64, User> =
+        page.users.into_iter().map(User::from_raw).map(|user| (user.bare_id(), user)).collect();
+    // Telegram may count more members than it's willing to list, so only a short page ends the list
+    let all_loaded = page.participants.len() < MEMBER_PAGE_SIZE;
+    let members = page
+        .participants
+        .iter()
+        .filter_map(|participant| {
+            let (user_id, is_admin) = match participant {
+                tl::enums::ChannelParticipant::Participant(p) => (p.user_id, false),
+                tl::enums::ChannelParticipant::ParticipantSelf(p) => (p.user_id, false),
+                tl::enums::ChannelParticipant::Creator(p) => (p.user_id, true),
+                tl::enums::ChannelParticipant::Admin(p) => (p.user_id, true),
+                tl::enums::ChannelParticipant::Banned(_) | tl::enums::ChannelParticipant::Left(_) => return None,
+            };
+            let user = users.get(&user_id)?;
+            Some(MemberInfo { name: user.full_name(), username: user.username().map(str::to_string), is_admin })
+        })
+        .collect();
+    Ok((page.count.max(0) as usize, members, all_loaded))
+}
+
+fn member_info(participant: &Participant) -> MemberInfo {
+    MemberInfo {
+        name: participant.user.full_name(),
+        username: participant.user.username().map(str::to_string),
+        is_admin: matches!(participant.role, Role::Creator { .. } | Role::Admin { .. }),
+    }
+}
+
+fn presence(status: &tl::enums::UserStatus) -> Presence {
+    match status {
+        tl::enums::UserStatus::Online(_) => Presence::Online,
+        tl::enums::UserStatus::Offline(offline) => {
+            Presence::LastSeen(chrono::DateTime::from_timestamp(offline.was_online as i64, 0).unwrap_or_default())
+        }
+        tl::enums::UserStatus::Recently(_) => Presence::Recently,
+        tl::enums::UserStatus::LastWeek(_) => Presence::LastWeek,
+        tl::enums::UserStatus::LastMonth(_) => Presence::LastMonth,
+        // Users who hide their last seen from everyone report no status at all
+        tl::enums::UserStatus::Empty => Presence::LongAgo,
+    }
+}
+
+/// Returns the bare channel ID of broadcast channels and supergroups.
+fn channel_id(peer: &Peer) -> Option<i64> {
+    match peer {
+        Peer::Channel(channel) => Some(channel.raw.id),
+        Peer::Group(group) => match &group.raw {
+            tl::enums::Chat::Channel(channel) => Some(channel.id),
+            _ => None,
+        },
+        Peer::User(_) => None,
+    }
+}
+
+/// Whether we created the group or channel.
+fn is_owner(peer: &Peer) -> bool {
+    match peer {
+        Peer::Channel(channel) => channel.raw.creator,
+        Peer::Group(group) => match &group.raw {
+            tl::enums::Chat::Chat(chat) => chat.creator,
+            tl::enums::Chat::Channel(channel) => channel.creator,
+            _ => false,
+        },
+        Peer::User(_) => false,
+    }
+}
+
+fn is_muted(settings: &tl::enums::PeerNotifySettings) -> bool {
+    let tl::enums::PeerNotifySettings::Settings(settings) = settings;
+    settings.mute_until.is_some_and(|until| i64::from(until) > chrono::Utc::now().timestamp())
+}
+
+/// Flips the server-side mute state of a chat and returns whether it is muted now.
+async fn toggle_mute(client: &Client, peer: tl::enums::InputPeer) -> Result<bool, InvocationError> {
+    let notify_peer: tl::enums::InputNotifyPeer = tl::types::InputNotifyPeer { peer }.into();
+    let current = client
+        .invoke(&tl::functions::account::GetNotifySettings { peer: notify_peer.clone() })
+        .await?;
+    let muted = !is_muted(&current);
+    let settings = tl::types::InputPeerNotifySettings {
+        show_previews: None,
+        silent: None,
+        // Muting "forever" is how official clients do it, unmuting clears the deadline
+        mute_until: Some(if muted { i32::MAX } else { 0 }),
+        sound: None,
+        stories_muted: None,
+        stories_hide_sender: None,
+        stories_sound: None,
+    };
+    client
+        .invoke(&tl::functions::account::UpdateNotifySettings { peer: notify_peer, settings: settings.into() })
+        .await?;
+    Ok(muted)
+}
+
+/// Pins the chat if it isn't pinned yet and unpins it otherwise, returning whether it's pinned now.
+async fn toggle_pin(client: &Client, peer: tl::enums::InputPeer) -> Result<bool, InvocationError> {
+    let dialog_peer: tl::enums::InputDialogPeer = tl::types::InputDialogPeer { peer }.into();
+    // Our copy of the flag may be stale if the chat was pinned from another device
+    let current = client
+        .invoke(&tl::functions::messages::GetPeerDialogs { peers: vec![dialog_peer.clone()] })
+        .await?;
+    let tl::enums::messages::PeerDialogs::Dialogs(current) = current;
+    let pinned = !current.dialogs.iter().any(|dialog| matches!(dialog, tl::enums::Dialog::Dialog(d) if d.pinned));
+    client
+        .invoke(&tl::functions::messages::ToggleDialogPin { pinned, peer: dialog_peer })
+        .await?;
+    Ok(pinned)
+}
+
+/// Converts Telegram's entities, which count UTF-16 code units, into byte ranges of `text`.
+fn text_entities(text: &str, entities: &[tl::enums::MessageEntity]) -> Vec<TextEntity> {
+    use tl::enums::MessageEntity as E;
+
+    let byte_offset = |units: i32| {
+        let mut seen = 0;
+        for (i, c) in text.char_indices() {
+            if seen >= units as usize {
+                return i;
+            }
+            seen += c.len_utf16();
+        }
+        text.len()
+    };
+    entities
+        .iter()
+        .filter_map(|entity| {
+            let (offset, length, kind) = match entity {
+                E::Bold(e) => (e.offset, e.length, EntityKind::Bold),
+                E::Italic(e) => (e.offset, e.length, EntityKind::Italic),
+                E::Underline(e) => (e.offset, e.length, EntityKind::Underline),
+                E::Strike(e) => (e.offset, e.length, EntityKind::Strikethrough),
+                E::Code(e) => (e.offset, e.length, EntityKind::Code),
+                E::Pre(e) => (e.offset, e.length, EntityKind::Pre),
+                E::Url(e) => (e.offset, e.length, EntityKind::Url),
+                E::TextUrl(e) => (e.offset, e.length, EntityKind::TextUrl(e.url.clone())),
+                E::Spoiler(e) => (e.offset, e.length, EntityKind::Spoiler),
+                E::CustomEmoji(e) => (e.offset, e.length, EntityKind::CustomEmoji(e.document_id)),
+                _ => return None,
+            };
+            Some(TextEntity { range: byte_offset(offset)..byte_offset(offset + length), kind })
+        })
+        .collect()
+}
+
+/// Summarises a message for the chat list, using a placeholder when it has no text.
+fn message_preview(message: &Message) -> String {
+    if !message.text().is_empty() {
+        let entities = text_entities(message.text(), message.fmt_entities().map(Vec::as_slice).unwrap_or_default());
+        return mask_spoilers(message.text(), &entities);
+    }
+    match message.media() {
+        Some(Media::Photo(_)) => "📷 Photo".to_string(),
+        Some(Media::Sticker(sticker)) => format!("{} Sticker", sticker.emoji()),
+        Some(Media::Document(document))
+            if matches!(&document.raw.document, Some(tl::enums::Document::Document(raw)) if is_animation(raw)) =>
+        {
+            "🎞 GIF".to_string()
+        }
+        Some(Media::Document(document)) => format!("📄 {}", document.name()),
+        Some(Media::Poll(_)) => "📊 Poll".to_string(),
+        Some(_) => "Media".to_string(),
+        None => String::new(),
+    }
+}
+
+fn spawn_thumbnail_download(client: Client, tx: mpsc::Sender<BackendEvent>, message_id: i32, photo: Photo) {
+    tokio::spawn(async move {
+        // "m" is the 320px box thumbnail, fall back to the full photo when it's missing
+        let result = match photo.thumbs().into_iter().find(|t| t.photo_type() == "m") {
+            Some(thumb) => download_bytes(&client, &thumb).await,
+            None => download_bytes(&client, &photo).await,
+        };
+        match result {
+            Ok(bytes) => {
+                let _ = tx.send(BackendEvent::MediaDownloaded { message_id, bytes }).await;
+            }
+            Err(e) => {
+                log::warn!("Failed to download photo for message {}: {}", message_id, e);
+            }
+        }
+    });
+}
+
+fn spawn_full_photo_download(client: Client, tx: mpsc::Sender<BackendEvent>, peer: Peer, message_id: i32) {
+    tokio::spawn(async move {
+        let event = match download_full_photo(&client, &peer, message_id).await {
+            Ok(bytes) => BackendEvent::FullPhotoDownloaded { message_id, bytes },
+            Err(e) => BackendEvent::FullPhotoFailed { message_id, error: e.to_string() },
+        };
+        let _ = tx.send(event).await;
+    });
+}
+
+async fn download_full_photo(
+    client: &Client,
+    peer: &Peer,
+    message_id: i32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let message = client
+        .get_messages_by_id(peer, &[message_id])
+        .await?
+        .into_iter()
+        .next()
+        .flatten()
+        .ok_or("Message not found")?;
+    let Some(Media::Photo(photo)) = message.media() else {
+        return Err("Message has no photo".into());
+    };
+    Ok(download_bytes(client, &photo).await?)
+}
+
+fn spawn_avatar_download(client: Client, tx: mpsc::Sender<BackendEvent>, peer_id: String, peer: Peer) {
+    tokio::spawn(async move {
+        // Peers without a photo get initials in the GUI
+        let Some((photo_id, photo)) = avatar_location(&peer) else {
+            return;
+        };
+        // Keyed by the photo, so a new profile photo is downloaded instead of showing the old copy
+        let cache_dir = cache_dir(AVATAR_CACHE_DIR);
+        let cache_path = cache_dir.join(format!("{}.jpg", photo_id));
+        if let Ok(bytes) = tokio::fs::read(&cache_path).await {
+            let _ = tx.send(BackendEvent::AvatarLoaded { peer_id, bytes }).await;
+            return;
+        }
+        match download_bytes(&client, &photo).await {
+            Ok(bytes) => {
+                if let Err(e) = tokio::fs::create_dir_all(&cache_dir).await {
+                    log::warn!("Failed to create avatar cache: {}", e);
+                } else if let Err(e) = tokio::fs::write(&cache_path, &bytes).await {
+                    log::warn!("Failed to cache avatar of {}: {}", peer_id, e);
+                }
+                let _ = tx.send(BackendEvent::AvatarLoaded { peer_id, bytes }).await;
+            }
+            Err(e) => {
+                log::warn!("Failed to download avatar of {}: {}", peer_id, e);
+            }
+        }
+    });
+}
+
+/// The ID and small profile photo of a peer, located through the photo ID in its raw data.
+fn avatar_location(peer: &Peer) -> Option<(i64, ChatPhoto)> {
+    let photo_id = match peer {
+        Peer::User(user) => user.photo()?.photo_id,
+        Peer::Group(group) => group.photo()?.photo_id,
+        Peer::Channel(channel) => channel.photo()?.photo_id,
+    };
+    let location = tl::types::InputPeerPhotoFileLocation { big: false, peer: input_peer(peer)?, photo_id };
+    Some((photo_id, ChatPhoto { raw: location.into() }))
+}
+
+async fn download_bytes<D: Downloadable>(client: &Client, downloadable: &D) -> Result<Vec<u8>, InvocationError> {
+    let mut bytes = Vec::new();
+    let mut download = client.iter_download(downloadable);
+    while let Some(chunk) = download.next().await? {
+        bytes.extend(chunk);
+    }
+    Ok(bytes)
+}
+
+fn spawn_media_download(
+    client: Client,
+    tx: mpsc::Sender<BackendEvent>,
+    peer: Peer,
+    chat_id: String,
+    message_id: i32,
+    path: PathBuf,
+) {
+    tokio::spawn(async move {
+        // Downloads land in the cache first so saving the same attachment twice doesn't hit the network
+        let cache_path = cache_dir(MEDIA_CACHE_DIR).join(format!("{}_{}", chat_id, message_id));
+        if !cache_path.exists()
+            && let Err(e) = download_to_cache(&client, &tx, &peer, message_id, &cache_path).await
+        {
+            let _ = tx.send(BackendEvent::DownloadFailed { message_id, error: format!("Failed to download: {}", e) }).await;
+            return;
+        }
+        match tokio::fs::copy(&cache_path, &path).await {
+            Ok(_) => {
+                let _ = tx.send(BackendEvent::DownloadFinished { message_id, path }).await;
+            }
+            Err(e) => {
+                let _ = tx.send(BackendEvent::DownloadFailed { message_id, error: format!("Failed to save file: {}", e) }).await;
+            }
+        }
+    });
+}
+
+fn spawn_document_upload(
+    client: Client,
+    tx: mpsc::Sender<BackendEvent>,
+    peer: Peer,
+    chat_id: String,
+    path: PathBuf,
+) -> tokio::task::AbortHandle {
+    tokio::spawn(async move {
+        let error = match upload_document(&client, &tx, &peer, &path, None).await {
+            Ok(message) => {
+                // Freshly uploaded, so never a forward with an origin to name
+                let message = message_info(&client, &tx, &message, |_| None);
+                let _ = tx.send(BackendEvent::NewMessage { chat_id, message }).await;
+                None
+            }
+            Err(e) => Some(format!("Failed to send file: {}", e)),
+        };
+        let _ = tx.send(BackendEvent::UploadFinished { voice: false, error }).await;
+    })
+    .abort_handle()
+}
+
+/// Encodes a recording and sends it as a voice message, reporting progress like a document upload.
+fn spawn_voice_upload(
+    client: Client,
+    tx: mpsc::Sender<BackendEvent>,
+    peer: Peer,
+    chat_id: String,
+    samples: Vec<f32>,
+    sample_rate: u32,
+) -> tokio::task::AbortHandle {
+    tokio::spawn(async move {
+        let result: Result<Message, Box<dyn std::error::Error + Send + Sync>> = async {
+            let voice = tokio::task::spawn_blocking(move || voice::encode(&samples, sample_rate)).await??;
+            let path = std::env::temp_dir().join(format!("telegram_voice_{}.ogg", chrono::Utc::now().timestamp_millis()));
+            tokio::fs::write(&path, &voice.ogg).await?;
+            let result = upload_document(&client, &tx, &peer, &path, Some(&voice)).await;
+            let _ = tokio::fs::remove_file(&path).await;
+            result
+        }
+        .await;
+        let error = match result {
+            Ok(message) => {
+                let message = message_info(&client, &tx, &message, |_| None);
+                let _ = tx.send(BackendEvent::NewMessage { chat_id, message }).await;
+                None
+            }
+            Err(e) => Some(format!("Failed to send voice message: {}", e)),
+        };
+        let _ = tx.send(BackendEvent::UploadFinished { voice: true, error }).await;
+    })
+    .abort_handle()
+}
+
+/// Writes every message of a chat to `path`, oldest first.
+///
+/// Nothing is written until all messages are fetched, so a cancelled export leaves no partial file behind.
+fn spawn_chat_export(
+    client: Client,
+    tx: mpsc::Sender<BackendEvent>,
+    peer: Peer,
+    format: ExportFormat,
+    path: PathBuf,
+) -> tokio::task::AbortHandle {
+    tokio::spawn(async move {
+        let result: Result<usize, Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut messages = client.iter_messages(&peer);
+            let total = messages.total().await.ok();
+            let mut exported = Vec::new();
+            while let Some(message) = messages.next().await? {
+                let sender = message.sender().and_then(|s| s.name().map(str::to_string)).unwrap_or("Unknown".to_string());
+                exported.push((message.id(), sender, message.date(), message_preview(&message)));
+                if exported.len() % EXPORT_PROGRESS_STEP == 0 {
+                    let _ = tx.send(BackendEvent::ExportProgress { count: exported.len(), total }).await;
+                }
+            }
+            // History comes newest first
+            exported.reverse();
+            let contents = match format {
+                ExportFormat::Json => {
+                    let messages: Vec<_> = exported
+                        .iter()
+                        .map(|(id, sender, date, text)| {
+                            serde_json::json!({ "id": id, "sender": sender, "date": date.to_rfc3339(), "text": text })
+                        })
+                        .collect();
+                    serde_json::to_string_pretty(&messages)?
+                }
+                ExportFormat::Text => exported
+                    .iter()
+                    .map(|(_, sender, date, text)| {
+                        format!("[{}] {}: {}\n", date.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"), sender, text)
+                    })
+                    .collect(),
+            };
+            tokio::fs::write(&path, contents).await?;
+            Ok(exported.len())
+        }
+        .await;
+        let result = result.map_err(|e| e.to_string());
+        let _ = tx.send(BackendEvent::ExportFinished { path, result }).await;
+    })
+    .abort_handle()
+}
+
+/// Uploads the file at `path` and sends it, as a voice message if `voice` describes it.
+async fn upload_document(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    peer: &Peer,
+    path: &Path,
+    voice: Option<&voice::VoiceNote>,
+) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
+    let file = tokio::fs::File::open(path).await?;
+    let total = file.metadata().await?.len();
+    let name = path.file_name().map_or_else(|| "file".to_string(), |name| name.to_string_lossy().into_owned());
+    let mut reader = ProgressReader { file, tx: tx.clone(), bytes: 0, total };
+    // `upload_file` can't report progress, so the file is streamed through `ProgressReader` instead
+    let uploaded = client.upload_stream(&mut reader, total as usize, name).await?;
+    let mut message = InputMessage::new().document(uploaded);
+    if let Some(voice) = voice {
+        message = message
+            .mime_type("audio/ogg")
+            .attribute(Attribute::Voice { duration: voice.duration, waveform: Some(voice.waveform.clone()) });
+    }
+    Ok(client.send_message(peer, message).await?)
+}
+
+/// Reports how much of a file has been read, which for an upload is how much has been sent.
+struct ProgressReader {
+    file: tokio::fs::File,
+    tx: mpsc::Sender<BackendEvent>,
+    bytes: u64,
+    total: u64,
+}
+
+impl tokio::io::AsyncRead for ProgressReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = tokio::io::AsyncRead::poll_read(std::pin::Pin::new(&mut self.file), cx, buf);
+        if let std::task::Poll::Ready(Ok(())) = poll {
+            self.bytes += (buf.filled().len() - before) as u64;
+            // Skipping an update when the channel is full only makes the progress bar lag a little
+            let _ = self.tx.try_send(BackendEvent::UploadProgress { bytes: self.bytes, total: self.total });
+        }
+        poll
+    }
+}
+
+async fn download_to_cache(
+    client: &Client,
+    tx: &mpsc::Sender<BackendEvent>,
+    peer: &Peer,
+    message_id: i32,
+    cache_path: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let message = client
+        .get_messages_by_id(peer, &[message_id])
+        .await?
+        .into_iter()
+        .next()
+        .flatten()
+        .ok_or("Message not found")?;
+    let media = message.media().ok_or("Message has no media")?;
+    let total = match &media {
+        Media::Photo(photo) => photo.size() as u64,
+        Media::Document(document) => document.size() as u64,
+        _ => 0,
+    };
+
+    if let Some(dir) = cache_path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let part_path = cache_path.with_extension("part");
+    let mut file = tokio::fs::File::create(&part_path).await?;
+    let mut download = client.iter_download(&media);
+    let mut downloaded = 0;
+    while let Some(chunk) = download.next().await? {
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        let _ = tx.send(BackendEvent::DownloadProgress { message_id, downloaded, total }).await;
+    }
+    file.flush().await?;
+    tokio::fs::rename(&part_path, cache_path).await?;
+    Ok(())
+}
+
+/// A phone login waiting for its code.
+///
+/// Kept ourselves because grammers' `LoginToken` hides the hash that `auth.resendCode` needs.
+struct CodeLogin {
+    phone: String,
+    phone_code_hash: String,
+}
+
+/// Like `flood_wait_or`, for the login helpers that can also fail outside of an RPC.
+fn login_error(error: Box<dyn std::error::Error + Send + Sync>) -> BackendEvent {
+    match error.downcast_ref::<InvocationError>() {
+        Some(e) => flood_wait_or(e, e.to_string()),
+        None => BackendEvent::Error(error.to_string().into()),
+    }
+}
+
+fn code_type_name(code_type: &tl::enums::auth::CodeType) -> &'static str {
+    use tl::enums::auth::CodeType;
+
+    match code_type {
+        CodeType::Sms => "SMS",
+        CodeType::Call => "phone call",
+        CodeType::FlashCall => "flash call",
+        CodeType::MissedCall => "missed call",
+        CodeType::FragmentSms => "Fragment SMS",
+    }
+}
+
+async fn send_login_code(
+    client: &Client,
+    session: &SqliteSession,
+    pool_handle: &SenderPoolHandle,
+    phone: &str,
+    api_id: i32,
+    api_hash: &str,
+) -> Result<tl::types::auth::SentCode, Box<dyn std::error::Error + Send + Sync>> {
+    let request = tl::functions::auth::SendCode {
+        phone_number: phone.to_string(),
+        api_id,
+        api_hash: api_hash.to_string(),
+        settings: tl::types::CodeSettings {
+            allow_flashcall: false,
+            current_number: false,
+            allow_app_hash: false,
+            allow_missed_call: false,
+            allow_firebase: false,
+            unknown_number: false,
+            logout_tokens: None,
+            token: None,
+            app_sandbox: None,
+        }
+        .into(),
+    };
+    loop {
+        match client.invoke(&request).await {
+            Ok(tl::enums::auth::SentCode::Code(sent)) => return Ok(sent),
+            // Only happens when passing future auth tokens, which we never do
+            Ok(tl::enums::auth::SentCode::Success(_)) => return Err("unexpected login without a code".into()),
+            Ok(tl::enums::auth::SentCode::PaymentRequired(_)) => return Err(PAYMENT_REQUIRED.into()),
+            Err(InvocationError::Rpc(rpc)) if rpc.name.ends_with("_MIGRATE") && rpc.value.is_some() => {
+                // The phone number belongs to another DC, which becomes the new home DC
+                pool_handle.disconnect_from_dc(session.home_dc_id());
+                session.set_home_dc_id(rpc.value.unwrap_or_default() as i32);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn sign_in_with_code(
+    client: &Client,
+    session: &SqliteSession,
+    login: &CodeLogin,
+    code: &str,
+) -> Result<LoginStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let request = tl::functions::auth::SignIn {
+        phone_number: login.phone.clone(),
+        phone_code_hash: login.phone_code_hash.clone(),
+        phone_code: Some(code.to_string()),
+        email_verification: None,
+    };
+    match client.invoke(&request).await {
+        Ok(authorization) => finish_login(session, authorization),
+        Err(e) if e.is("SESSION_PASSWORD_NEEDED") => {
+            Ok(LoginStatus::PasswordRequired(Box::new(password_token(client).await?)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn password_token(client: &Client) -> Result<PasswordToken, InvocationError> {
+    let password: tl::types::account::Password =
+        client.invoke(&tl::functions::account::GetPassword {}).await?.into();
+    Ok(PasswordToken::new(password))
+}
+
+enum LoginStatus {
+    Pending(String),
+    LoggedIn,
+    PasswordRequired(Box<PasswordToken>),
+}
+
+async fn poll_qr_login(
+    client: &Client,
+    session: &SqliteSession,
+    pool_handle: &SenderPoolHandle,
+    api_id: i32,
+    api_hash: &str,
+) -> Result<LoginStatus, Box<dyn std::error::Error + Send + Sync>> {
+    use tl::enums::auth::LoginToken;
+
+    let request = tl::functions::auth::ExportLoginToken {
+        api_id,
+        api_hash: api_hash.to_string(),
+        except_ids: Vec::new(),
+    };
+    let mut result = client.invoke(&request).await;
+    let authorization = loop {
+        match result {
+            Ok(LoginToken::Token(token)) => {
+                let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&token.token);
+                return Ok(LoginStatus::Pending(format!("tg://login?token={}", token)));
+            }
+            Ok(LoginToken::MigrateTo(migrate)) => {
+                // The account lives in another DC, which becomes the new home DC
+                pool_handle.disconnect_from_dc(session.home_dc_id());
+                session.set_home_dc_id(migrate.dc_id);
+                result = client
+                    .invoke(&tl::functions::auth::ImportLoginToken { token: migrate.token })
+                    .await;
+            }
+            Ok(LoginToken::Success(success)) => break success.authorization,
+            Err(e) if e.is("SESSION_PASSWORD_NEEDED") => {
+                return Ok(LoginStatus::PasswordRequired(Box::new(password_token(client).await?)));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    finish_login(session, authorization)
+}
+
+/// Records who signed in on the session, like grammers' own `sign_in` does.
+fn finish_login(
+    session: &SqliteSession,
+    authorization: tl::enums::auth::Authorization,
+) -> Result<LoginStatus, Box<dyn std::error::Error + Send + Sync>> {
+    match authorization {
+        tl::enums::auth::Authorization::Authorization(auth) => {
+            if let tl::enums::User::User(user) = auth.user {
+                session.cache_peer(&PeerInfo::User {
+                    id: user.id,
+                    auth: user.access_hash.map(PeerAuth::from_hash),
+                    bot: Some(user.bot),
+                    is_self: Some(true),
+                });
+            }
+            Ok(LoginStatus::LoggedIn)
+        }
+        tl::enums::auth::Authorization::SignUpRequired(_) => {
+            Err("this account must be signed up with an official client first".into())
         }
     }
 }
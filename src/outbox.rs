@@ -0,0 +1,75 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A message waiting to be sent, persisted so it survives a crash or restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub chat_id: String,
+    pub text: String,
+    pub markdown: bool,
+    /// Sent as the MTProto `random_id`, so Telegram rejects a second delivery of the same message.
+    pub random_id: i64,
+    /// ID of the placeholder bubble, only meaningful while the app runs.
+    #[serde(skip)]
+    pub temp_id: i32,
+}
+
+impl QueuedMessage {
+    pub fn new(chat_id: String, text: String, markdown: bool, temp_id: i32) -> Self {
+        Self { chat_id, text, markdown, random_id: random_id(), temp_id }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct OutboxFile {
+    messages: Vec<QueuedMessage>,
+}
+
+/// Loads the queued messages of `account`, oldest first.
+pub fn load(account: usize) -> Vec<QueuedMessage> {
+    let Some(path) = outbox_path(account) else {
+        return Vec::new();
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    match toml::from_str::<OutboxFile>(&contents) {
+        Ok(file) => file.messages,
+        Err(e) => {
+            log::warn!("Ignoring invalid outbox file {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+pub fn save(account: usize, messages: &[QueuedMessage]) {
+    let Some(path) = outbox_path(account) else {
+        log::warn!("No data directory available, queued messages will not be saved");
+        return;
+    };
+    let file = OutboxFile { messages: messages.to_vec() };
+    let result = toml::to_string(&file)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(&path, contents).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        log::warn!("Failed to save outbox to {}: {}", path.display(), e);
+    }
+}
+
+fn outbox_path(account: usize) -> Option<PathBuf> {
+    ProjectDirs::from("", "", "telegram_client").map(|dirs| dirs.data_dir().join(format!("outbox_{}.toml", account)))
+}
+
+/// `RandomState` is seeded randomly per instance, which is all the randomness a nonce needs.
+pub fn random_id() -> i64 {
+    std::collections::hash_map::RandomState::new().hash_one(SystemTime::now()) as i64
+}
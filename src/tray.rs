@@ -0,0 +1,180 @@
+use eframe::egui;
+use raw_window_handle::HasWindowHandle;
+use std::sync::mpsc;
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+
+const ICON_SIZE: u32 = 32;
+const ICON_COLOR: [u8; 3] = [0x2a, 0xab, 0xee];
+const SHOW_ID: &str = "show";
+const QUIT_ID: &str = "quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayAction {
+    Show,
+    Quit,
+}
+
+/// The app's own window, brought back straight from the tray's event handlers.
+///
+/// eframe only runs a frame when the window gets a redraw, which Windows never sends to a hidden one,
+/// so `ViewportCommand::Visible(true)` wouldn't be applied until something else showed the window.
+#[derive(Debug, Clone, Copy)]
+pub struct NativeWindow {
+    #[cfg(target_os = "windows")]
+    hwnd: Option<isize>,
+}
+
+impl NativeWindow {
+    pub fn new(window: &impl HasWindowHandle) -> Self {
+        #[cfg(target_os = "windows")]
+        {
+            let hwnd = match window.window_handle().map(|handle| handle.as_raw()) {
+                Ok(raw_window_handle::RawWindowHandle::Win32(handle)) => Some(handle.hwnd.get()),
+                _ => None,
+            };
+            Self { hwnd }
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let _ = window;
+            Self {}
+        }
+    }
+
+    /// Makes the window visible again, after which it gets frames to handle the rest itself.
+    fn show(self) {
+        #[cfg(target_os = "windows")]
+        if let Some(hwnd) = self.hwnd {
+            use windows_sys::Win32::UI::WindowsAndMessaging::{SW_SHOW, ShowWindow};
+            // SAFETY: the handle belongs to the app's only window, which lives until the process exits
+            unsafe {
+                ShowWindow(hwnd as _, SW_SHOW);
+            }
+        }
+    }
+}
+
+/// The icon in the system tray, with a menu to bring the window back or quit.
+pub struct Tray {
+    actions: mpsc::Receiver<TrayAction>,
+    #[cfg(not(target_os = "linux"))]
+    icon: TrayIcon,
+    /// Whether the icon should be shown, picked up by the GTK thread that owns it.
+    #[cfg(target_os = "linux")]
+    visible: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl Tray {
+    /// Adds the icon to the tray, waking `ctx` whenever it's clicked so the action is handled right away.
+    pub fn new(ctx: &egui::Context, window: NativeWindow) -> Result<Self, String> {
+        let (tx, actions) = mpsc::channel();
+        let menu_tx = tx.clone();
+        let menu_ctx = ctx.clone();
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            let action = match event.id.0.as_str() {
+                SHOW_ID => TrayAction::Show,
+                QUIT_ID => TrayAction::Quit,
+                _ => return,
+            };
+            window.show();
+            let _ = menu_tx.send(action);
+            menu_ctx.request_repaint();
+        }));
+        let ctx = ctx.clone();
+        TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+            if let TrayIconEvent::Click { button: MouseButton::Left, button_state: MouseButtonState::Up, .. } = event {
+                window.show();
+                let _ = tx.send(TrayAction::Show);
+                ctx.request_repaint();
+            }
+        }));
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::sync::atomic::{AtomicBool, Ordering};
+            let visible = std::sync::Arc::new(AtomicBool::new(true));
+            let wanted = visible.clone();
+            let (ready_tx, ready) = mpsc::channel();
+            // On Linux the icon lives in GTK, which needs a loop running on a thread of its own
+            std::thread::spawn(move || {
+                if let Err(e) = gtk::init() {
+                    let _ = ready_tx.send(Err(format!("Failed to start GTK: {}", e)));
+                    return;
+                }
+                let icon = match build() {
+                    Ok(icon) => icon,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                let mut shown = true;
+                gtk::glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+                    let visible = wanted.load(Ordering::Relaxed);
+                    if visible != shown {
+                        shown = visible;
+                        if let Err(e) = icon.set_visible(visible) {
+                            log::warn!("Failed to toggle the tray icon: {}", e);
+                        }
+                    }
+                    gtk::glib::ControlFlow::Continue
+                });
+                gtk::main();
+            });
+            ready.recv().map_err(|_| "The tray thread stopped".to_string())??;
+            Ok(Self { actions, visible })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(Self { actions, icon: build()? })
+    }
+
+    /// The next click on the icon or its menu that hasn't been handled yet.
+    pub fn poll(&self) -> Option<TrayAction> {
+        self.actions.try_recv().ok()
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        #[cfg(target_os = "linux")]
+        self.visible.store(visible, std::sync::atomic::Ordering::Relaxed);
+        #[cfg(not(target_os = "linux"))]
+        if let Err(e) = self.icon.set_visible(visible) {
+            log::warn!("Failed to toggle the tray icon: {}", e);
+        }
+    }
+}
+
+fn build() -> Result<TrayIcon, String> {
+    let menu = Menu::with_items(&[
+        &MenuItem::with_id(SHOW_ID, "Show", true, None),
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id(QUIT_ID, "Quit", true, None),
+    ])
+    .map_err(|e| e.to_string())?;
+    TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        // The menu is for the right button, a left click brings the window back
+        .with_menu_on_left_click(false)
+        .with_tooltip(crate::app::APP_TITLE)
+        .with_icon(icon()?)
+        .build()
+        .map_err(|e| format!("Failed to create the tray icon: {}", e))
+}
+
+/// A plain filled circle, there's no icon file shipped with the app.
+fn icon() -> Result<Icon, String> {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            // One pixel of fade keeps the edge from looking jagged
+            let alpha = ((radius - distance).clamp(0.0, 1.0) * 255.0) as u8;
+            rgba.extend_from_slice(&ICON_COLOR);
+            rgba.push(alpha);
+        }
+    }
+    Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE).map_err(|e| e.to_string())
+}
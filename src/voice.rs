@@ -0,0 +1,218 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Opus only encodes at a few rates, 48 kHz being the one every decoder expects.
+const OPUS_RATE: u32 = 48_000;
+/// 20 ms per Opus frame, what Telegram's own clients use.
+const FRAME_SAMPLES: usize = 960;
+/// Telegram draws the waveform of voice messages from 100 values of 5 bits each.
+const WAVEFORM_POINTS: usize = 100;
+/// Recordings stop growing after this, so one left running doesn't fill up the memory.
+pub const MAX_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Records mono audio from the default microphone until dropped or finished.
+pub struct Recorder {
+    // Recording stops as soon as the stream is dropped
+    _stream: cpal::Stream,
+    samples: Arc<Mutex<Vec<f32>>>,
+    sample_rate: u32,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn start() -> Result<Self, String> {
+        let device = cpal::default_host().default_input_device().ok_or("No microphone found")?;
+        let config = device
+            .default_input_config()
+            .map_err(|e| format!("Can't use the microphone: {}", e))?;
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let max_samples = (u64::from(config.sample_rate().0) * MAX_DURATION.as_secs()) as usize;
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => input_stream::<f32>(&device, &config.config(), samples.clone(), max_samples),
+            cpal::SampleFormat::I16 => input_stream::<i16>(&device, &config.config(), samples.clone(), max_samples),
+            cpal::SampleFormat::U16 => input_stream::<u16>(&device, &config.config(), samples.clone(), max_samples),
+            format => return Err(format!("The microphone uses an unsupported sample format ({})", format)),
+        }?;
+        // Denied microphone access usually surfaces here rather than when looking up the device
+        stream
+            .play()
+            .map_err(|e| format!("Can't record from the microphone, is access to it allowed? {}", e))?;
+        Ok(Self { _stream: stream, samples, sample_rate: config.sample_rate().0, started: Instant::now() })
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Stops recording, returning the samples and their rate.
+    pub fn finish(self) -> (Vec<f32>, u32) {
+        let samples = std::mem::take(&mut *self.samples.lock().unwrap_or_else(|e| e.into_inner()));
+        (samples, self.sample_rate)
+    }
+}
+
+/// Collects the input as mono `f32` samples, averaging the channels together, up to `max_samples` of them.
+fn input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Mutex<Vec<f32>>>,
+    max_samples: usize,
+) -> Result<cpal::Stream, String>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let channels = usize::from(config.channels.max(1));
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let mut samples = samples.lock().unwrap_or_else(|e| e.into_inner());
+                let room = max_samples.saturating_sub(samples.len());
+                samples.extend(data.chunks(channels).take(room).map(|frame| {
+                    frame.iter().map(|&s| <f32 as cpal::FromSample<T>>::from_sample_(s)).sum::<f32>() / channels as f32
+                }));
+            },
+            |e| log::error!("Microphone error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Can't record from the microphone: {}", e))
+}
+
+/// A recording ready to be sent as a voice message.
+#[derive(Debug)]
+pub struct VoiceNote {
+    /// Opus in an OGG container, the only format Telegram plays as a voice message.
+    pub ogg: Vec<u8>,
+    pub duration: Duration,
+    pub waveform: Vec<u8>,
+}
+
+pub fn encode(samples: &[f32], sample_rate: u32) -> Result<VoiceNote, String> {
+    if samples.is_empty() || sample_rate == 0 {
+        return Err("Nothing was recorded".to_string());
+    }
+    let duration = Duration::from_secs_f64(samples.len() as f64 / f64::from(sample_rate));
+    let waveform = waveform(samples);
+    let samples = resample(samples, sample_rate, OPUS_RATE);
+
+    let mut encoder = opus::Encoder::new(OPUS_RATE, opus::Channels::Mono, opus::Application::Voip).map_err(|e| e.to_string())?;
+    let pre_skip = encoder.get_lookahead().map_err(|e| e.to_string())? as u16;
+    // Only has to tell apart streams within the one file
+    let serial = 1;
+    let mut ogg = Vec::new();
+    let mut writer = PacketWriter::new(&mut ogg);
+    let io_error = |e: std::io::Error| e.to_string();
+    writer.write_packet(opus_head(pre_skip, sample_rate), serial, PacketWriteEndInfo::EndPage, 0).map_err(io_error)?;
+    writer.write_packet(opus_tags(), serial, PacketWriteEndInfo::EndPage, 0).map_err(io_error)?;
+    let frames = samples.len().div_ceil(FRAME_SAMPLES);
+    let mut packet = [0u8; 4000];
+    for (i, chunk) in samples.chunks(FRAME_SAMPLES).enumerate() {
+        let mut frame = chunk.to_vec();
+        frame.resize(FRAME_SAMPLES, 0.0);
+        let len = encoder.encode_float(&frame, &mut packet).map_err(|e| e.to_string())?;
+        let end = if i + 1 == frames { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::NormalPacket };
+        let granule = u64::from(pre_skip) + ((i + 1) * FRAME_SAMPLES) as u64;
+        writer.write_packet(packet[..len].to_vec(), serial, end, granule).map_err(io_error)?;
+    }
+    drop(writer);
+    Ok(VoiceNote { ogg, duration, waveform })
+}
+
+fn opus_head(pre_skip: u16, input_rate: u32) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&input_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family, plain mono
+    head
+}
+
+fn opus_tags() -> Vec<u8> {
+    let vendor = env!("CARGO_PKG_NAME").as_bytes();
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no comments
+    tags
+}
+
+/// Linear interpolation, plenty for speech.
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * u64::from(to) / u64::from(from)) as usize;
+    let step = f64::from(from) / f64::from(to);
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+            let fraction = (position - index as f64) as f32;
+            samples[index] + (next - samples[index]) * fraction
+        })
+        .collect()
+}
+
+/// The loudness over time packed the way Telegram expects, 5 bits per point.
+fn waveform(samples: &[f32]) -> Vec<u8> {
+    let chunk = samples.len().div_ceil(WAVEFORM_POINTS).max(1);
+    let peaks: Vec<f32> = samples.chunks(chunk).map(|c| c.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))).collect();
+    let loudest = peaks.iter().copied().fold(0.0f32, f32::max).max(f32::EPSILON);
+    let mut packed = vec![0u8; (peaks.len() * 5).div_ceil(8)];
+    for (i, peak) in peaks.iter().enumerate() {
+        let value = ((peak / loudest * 31.0).round() as u16) << (i * 5 % 8);
+        packed[i * 5 / 8] |= value as u8;
+        if let Some(byte) = packed.get_mut(i * 5 / 8 + 1) {
+            *byte |= (value >> 8) as u8;
+        }
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resamples_to_the_new_rate() {
+        let samples = [0.0, 1.0, 0.0, -1.0];
+        assert_eq!(resample(&samples, 16_000, 16_000), samples);
+        assert_eq!(resample(&samples, 16_000, 32_000), [0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+        assert_eq!(resample(&samples, 16_000, 8_000), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn packs_the_waveform_into_five_bits_per_point() {
+        let mut samples = vec![0.0; WAVEFORM_POINTS];
+        samples[0] = 1.0;
+        samples[1] = -0.5;
+        let waveform = waveform(&samples);
+        assert_eq!(waveform.len(), (WAVEFORM_POINTS * 5).div_ceil(8));
+        // 31 in the lowest 5 bits, then 16 straddling the first two bytes
+        assert_eq!(waveform[0], 0b0001_1111);
+        assert_eq!(waveform[1], 0b0000_0010);
+        assert!(waveform[2..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn waveform_of_silence_is_flat() {
+        assert!(waveform(&[0.0; 4800]).iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn writes_the_opus_header() {
+        let head = opus_head(312, 44_100);
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[8..10], [1, 1]);
+        assert_eq!(u16::from_le_bytes([head[10], head[11]]), 312);
+        assert_eq!(u32::from_le_bytes([head[12], head[13], head[14], head[15]]), 44_100);
+        assert_eq!(head[16..], [0, 0, 0]);
+    }
+}